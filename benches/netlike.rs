@@ -142,9 +142,9 @@ fn bench_dataset(
     group.bench_function(format!("{label} - NetLikeScanner"), |b| {
         let mut result = 0;
         b.iter(|| {
-            let mut s = NetLikeScanner::new(black_box(data));
+            let s = NetLikeScanner::new(black_box(data));
             let mut count = 0;
-            while let Some((_s, _e)) = s.next() {
+            for (_s, _e) in s {
                 count += 1;
             }
             result = count;
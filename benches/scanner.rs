@@ -4,11 +4,10 @@ use criterion::measurement::WallTime;
 use criterion::{BenchmarkGroup, Criterion, criterion_group, criterion_main};
 
 use regex::bytes::Regex;
-use zerocopy::IntoBytes;
 
 use ipgrep::matching::{AcceptSet, InterfaceMode};
 use ipgrep::net::Net;
-use ipgrep::scanner::{NetCandidate, NetCandidateScanner};
+use ipgrep::scanner::{MatchKind, NetCandidate, NetCandidateScanner};
 
 fn bench_netcandidatescanner(c: &mut Criterion) {
     let mut group = c.benchmark_group("NetCandidateScanner");
@@ -28,7 +27,7 @@ fn bench_netcandidatescanner(c: &mut Criterion) {
             end
             ",
         ),
-        &dup::<1, _>(&vec![
+        &dup::<1, _>(&[
             "192.168.0.1/32",
             "::1/128",
             "::ffff:10.0.0.2/127",
@@ -52,7 +51,7 @@ fn bench_netcandidatescanner(c: &mut Criterion) {
             end
             ",
         ),
-        &dup::<1000, _>(&vec![
+        &dup::<1000, _>(&[
             "192.168.0.1/32",
             "::1/128",
             "::ffff:10.0.0.2/127",
@@ -75,7 +74,7 @@ fn bench_netcandidatescanner(c: &mut Criterion) {
             quickly
             ",
         ),
-        &vec![],
+        &[],
     );
 
     // duration | slow  | method              | remarks
@@ -92,7 +91,31 @@ fn bench_netcandidatescanner(c: &mut Criterion) {
             match
             ",
         ),
-        &vec![],
+        &[],
+    );
+
+    // duration | slow  | method              | remarks
+    // --------:|------:|---------------------|--------
+    //    940us |   win | NetCandidateScanner | oldnet slurp stays linear
+    let oldnet = AcceptSet {
+        ip: true,
+        net: true,
+        oldnet: true,
+        iface: true,
+    };
+    bench_scanner(
+        &mut group,
+        "Old style netmasks and bogus long dotted suffixes",
+        &dup::<1000, _>(
+            b"
+            route 10.0.0.0/255.255.255.0 via 192.168.1.0/255.255.255.128
+            bogus 10.1.2.3/1.2.3.4.5.6.7.8.9.10.11.12.13.14.15.16.17.18.19
+            and 1.2.3.4/5.6.7.8.9.0.1.2.3.4.5.6.7.8.9.0.1.2.3.4.5.6.7.8.9
+            end
+            ",
+        ),
+        &dup::<1000, _>(&["10.0.0.0/24", "192.168.1.0/25"]),
+        oldnet,
     );
 
     group.finish();
@@ -109,13 +132,11 @@ fn dup<const N: usize, T: Clone>(chunk: &[T]) -> Vec<T> {
 fn bench_dataset(
     group: &mut BenchmarkGroup<'_, WallTime>,
     label: &str,
-    data: &Vec<u8>,
-    expected: &Vec<&str>,
+    data: &[u8],
+    expected: &[&str],
 ) {
     // Test regex baseline.
     group.bench_function(format!("{label} - Regex baseline"), |b| {
-        let data = data.as_bytes();
-
         // This basic regex is not good enough to handle all our corner
         // cases. But it serves as a nice base line to compare against.
         let re = Regex::new(
@@ -152,12 +173,14 @@ fn bench_dataset(
                 .map(|m| NetCandidate {
                     range: (m.start(), m.end()),
                     net: Net::try_from(m.as_bytes()).unwrap(),
+                    kind: MatchKind::default(),
+                    port: None,
                 })
                 .collect()
         };
 
         // Do preliminary test
-        let netcandidates = re_fn(&data);
+        let netcandidates = re_fn(data);
         let net_strs: Vec<String> =
             netcandidates.iter().map(|c| c.net.to_string()).collect();
         assert_eq!(net_strs, *expected);
@@ -171,29 +194,37 @@ fn bench_dataset(
 
     // Test our custom hand-made scanner. When it is not faster than the
     // regex, it is at least more feature complete.
-    group.bench_function(format!("{label} - NetCandidateScanner"), |b| {
-        let data = data.as_bytes();
+    let acc = AcceptSet {
+        ip: true,
+        net: true,
+        oldnet: false,
+        iface: true,
+    };
+    bench_scanner(group, label, data, expected, acc);
+}
 
+fn bench_scanner(
+    group: &mut BenchmarkGroup<'_, WallTime>,
+    label: &str,
+    data: &[u8],
+    expected: &[&str],
+    acc: AcceptSet,
+) {
+    group.bench_function(format!("{label} - NetCandidateScanner"), |b| {
         // Setup
-        let acc = AcceptSet {
-            ip: true,
-            net: true,
-            oldnet: false,
-            iface: true,
-        };
         let ncs = NetCandidateScanner::new()
             .set_accept(acc)
             .set_interface_mode(InterfaceMode::TreatAsIp);
 
         // Do preliminary test
-        let netcandidates = ncs.find_all(&data, "(stdin)");
+        let netcandidates = ncs.find_all(data, "(stdin)");
         let net_strs: Vec<String> =
             netcandidates.iter().map(|c| c.net.to_string()).collect();
         assert_eq!(net_strs, *expected);
 
         // Do timing
         b.iter(|| {
-            let netcandidates = ncs.find_all(&black_box(data), "(stdin)");
+            let netcandidates = ncs.find_all(black_box(data), "(stdin)");
             black_box(netcandidates);
         });
     });
@@ -1,11 +1,20 @@
+use std::fs;
+use std::io::{self, BufRead};
+use std::ops::RangeInclusive;
+use std::path::Path;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
 
+use clap::builder::PossibleValuesParser;
 use clap::error::ErrorKind;
 use clap::{ArgAction, Error, Parser, ValueEnum, value_parser};
 
+use crate::diagnostics::{Diagnostics, Silent, Stderr};
+use crate::needle::{BUILTINS, extend_needles, find_redundant};
 use crate::params::{
-    AcceptSet, InterfaceMode, MatchMode, Needle, OutputStyle, Parameters,
-    RecurseHaystacks, ShowContext,
+    AcceptSet, BinaryFiles, Devices, GeoFilter, InterfaceMode, MatchMode,
+    Needle, OutputStyle, Parameters, RecurseHaystacks, ShowColor, ShowContext,
 };
 
 #[cfg(feature = "version-from-env")]
@@ -33,20 +42,62 @@ pub enum InterfaceModeArg {
     Net,
     #[value(alias = "c")]
     Complain,
+    #[value(alias = "s")]
+    Summary,
 }
 
 #[derive(Clone, ValueEnum, Debug)]
 pub enum MatchModeArg {
     #[value(alias = "a")]
     Auto,
-    #[value(alias = "c")]
+    #[value(aliases = ["c", "supernet-of"])]
     Contains,
-    #[value(alias = "w")]
+    #[value(aliases = ["w", "subnet-of"])]
     Within,
     #[value(alias = "e")]
     Equals,
     #[value(alias = "o")]
     Overlaps,
+    #[value(alias = "t")]
+    Touches,
+}
+
+#[derive(Clone, PartialEq, ValueEnum, Debug)]
+pub enum OutputFormatArg {
+    Text,
+    Json,
+}
+
+#[derive(Clone, PartialEq, ValueEnum, Debug)]
+pub enum ColorArg {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Clone, PartialEq, ValueEnum, Debug)]
+pub enum BinaryFilesArg {
+    Binary,
+    Text,
+    WithoutMatch,
+}
+
+#[derive(Clone, PartialEq, ValueEnum, Debug)]
+pub enum DevicesArg {
+    Read,
+    Skip,
+}
+
+#[derive(Clone, PartialEq, ValueEnum, Debug)]
+pub enum DerefArg {
+    None,
+    Args,
+    All,
+}
+
+#[derive(Clone, PartialEq, ValueEnum, Debug)]
+pub enum DedupArg {
+    Specific,
 }
 
 #[derive(Clone, Debug)]
@@ -89,7 +140,7 @@ Example invocations:
 pub struct Args {
     /// Accept input forms (may repeat)
     #[arg(
-        short='a', long="accept", value_enum,
+        short='a', long="accept", value_enum, value_delimiter=',',
         default_values_t=vec![
             AcceptSetArg::Ip, AcceptSetArg::Net, AcceptSetArg::Iface],
         help_heading="Matching Control",
@@ -102,6 +153,33 @@ Accept input forms (may repeat or use commas):
     )]
     pub accept: Vec<AcceptSetArg>,
 
+    /// Only match host addresses, with host address needles
+    #[arg(
+        long = "hosts-only",
+        visible_alias = "exact-host",
+        conflicts_with = "accept",
+        help_heading = "Matching Control",
+        long_help = "\
+Only match bare host addresses (like -a ip), and only take single host
+needles: a needle that is a network, like 10.0.0.0/8 or rfc1918, is a
+usage error instead of silently matching many hosts."
+    )]
+    pub hosts_only: bool,
+
+    /// Take bare IPv4 needles as their legacy class A/B/C network
+    #[arg(
+        long = "classful",
+        conflicts_with = "hosts_only",
+        help_heading = "Matching Control",
+        long_help = "\
+Take each bare IPv4 needle as the network of its legacy address class:
+class A (0-127.x.x.x) as /8, class B (128-191.x.x.x) as /16 and class C
+(192-223.x.x.x) as /24. E.g. 10.5.6.7 becomes 10.0.0.0/8. Needles with
+an explicit prefix, IPv6 and class D/E addresses are left alone. Each
+widened needle is noted on stderr, unless -s or -q is given."
+    )]
+    pub classful: bool,
+
     /// Select interface IP matching mode
     #[arg(
         short='I', long="interface-mode", value_enum,
@@ -111,7 +189,8 @@ Accept input forms (may repeat or use commas):
 Select interface IP matching mode:
   ip        - treat as single IP
   net       - treat as if network bits were unset
-  complain  - complain/reject when network bits are set"
+  complain  - complain/reject when network bits are set
+  summary   - reject those too, and report how many once per file"
     )]
     pub interface_mode: InterfaceModeArg,
 
@@ -124,12 +203,385 @@ Select interface IP matching mode:
 Match mode:
    auto     - 'contains' if the all needles are a single IP, else 'within'
    contains - haystack net contains needle net
+              (alias: supernet-of; haystack is a supernet of the needle)
    within   - haystack net is within needle net (inverse of contains)
+              (alias: subnet-of; haystack is a subnet of the needle)
    equals   - exact IP or network equality
-   overlaps - haystack and needle nets overlap"
+   overlaps - haystack and needle nets overlap
+   touches  - haystack and needle nets overlap or are adjacent"
     )]
     pub match_mode: MatchModeArg,
 
+    /// Show addresses of the other family on matching lines
+    #[arg(
+        long = "report-other-family",
+        help_heading = "Matching Control",
+        long_help = "\
+Show addresses of the other family on lines matching the needles. All
+needles must be of a single family. E.g. with needle 10.0.0.0/8, lines
+that have a match and also have IPv6 addresses are selected, and the
+IPv6 addresses are the matches shown."
+    )]
+    pub report_other_family: bool,
+
+    /// Select lines that match every needle, instead of any
+    #[arg(
+        long = "all",
+        overrides_with = "match_any",
+        conflicts_with_all = ["report_other_family", "compare"],
+        help_heading = "Matching Control",
+        long_help = "\
+Select lines that have a match for every needle, instead of for any of
+them. E.g. with needles 10.0.0.0/8,2001:db8::/32, only lines with both
+an IPv4 address in 10.0.0.0/8 and an IPv6 address in 2001:db8::/32 are
+selected. An alias (like rfc1918) or a range counts as one needle.
+!needles still reject the addresses they match."
+    )]
+    pub match_all: bool,
+
+    /// Select lines that match any needle (default)
+    #[arg(
+        long = "any",
+        overrides_with = "match_all",
+        help_heading = "Matching Control"
+    )]
+    pub match_any: bool,
+
+    /// Match the addresses that are not in the needles instead
+    #[arg(
+        long = "outside",
+        conflicts_with_all = ["report_other_family", "match_all", "compare"],
+        help_heading = "Matching Control",
+        long_help = "\
+Match the addresses that the needles do not match, instead of those that
+they do. Unlike -v/--invert-match, which selects the lines without a
+match, this selects the lines with at least one address outside the
+needles, and those addresses are the matches. E.g. --outside 10.0.0.0/8
+selects \"10.1.2.3 8.8.8.8\", with 8.8.8.8 as the match. Addresses of
+the other family are outside too: it also selects \"::1\". Only for match
+modes contains and within."
+    )]
+    pub outside: bool,
+
+    /// Also require literal STR on matching lines (may repeat)
+    #[arg(
+        long = "also-literal",
+        value_name = "STR",
+        help_heading = "Matching Control",
+        long_help = "\
+Also require literal STR on matching lines (may repeat). Lines are only
+selected if an IP/network matches AND all of these strings are found."
+    )]
+    pub also_literal: Vec<String>,
+
+    /// Also select lines with literal STR (may repeat)
+    #[arg(
+        long = "or-literal",
+        value_name = "STR",
+        help_heading = "Matching Control",
+        long_help = "\
+Also select lines with literal STR (may repeat). Lines are selected if
+an IP/network matches OR any of these strings are found. Lines selected
+by the literal alone have no matches to show with -o/--only-matching."
+    )]
+    pub or_literal: Vec<String>,
+
+    /// Accept zero-padded IPv4 octets, like 010.000.000.001
+    #[arg(
+        long = "leading-zeros",
+        help_heading = "Matching Control",
+        long_help = "\
+Accept IPv4 addresses with zero-padded octets, like 010.000.000.001 as
+written by some appliances. The octets are read as decimal, so this is
+10.0.0.1 (not octal 8.0.0.1). By default such addresses are ignored."
+    )]
+    pub leading_zeros: bool,
+
+    /// Only match addresses bounded by whitespace or ,()[]"'
+    #[arg(
+        long = "strict-boundaries",
+        help_heading = "Matching Control",
+        long_help = "\
+Only match addresses and networks that are bounded by whitespace, the
+start or end of the line, or one of the characters ,()[]\"' on both
+sides. This rejects those glued to letters or colons, like in
+\"id=x10.0.0.1:\", at the cost of missing some real ones: 10.0.0.1:80 is
+only a match with --with-ports, and src=10.0.0.1 is not a match."
+    )]
+    pub strict_boundaries: bool,
+
+    /// Match IPv4-mapped IPv6 addresses as IPv4
+    #[arg(
+        long = "ipv4-mapped",
+        help_heading = "Matching Control",
+        long_help = "\
+Match IPv4-mapped IPv6 addresses and networks (like ::ffff:10.0.0.1, in
+any case) as the IPv4 address they map. E.g. ::ffff:10.0.0.1 then
+matches needle 10.0.0.0/8, but no longer matches IPv6 needles."
+    )]
+    pub ipv4_mapped: bool,
+
+    /// Match IPv4 and IPv4-mapped IPv6 against each other
+    #[arg(
+        long = "map-v4",
+        help_heading = "Matching Control",
+        long_help = "\
+Treat IPv4 as the IPv4-mapped IPv6 range ::ffff:0:0/96 when matching, so
+::ffff:10.0.0.1 matches needle 10.0.0.0/8 and 10.0.0.1 matches needle
+::ffff:10.0.0.0/104. IPv4 prefix lengths /0 to /32 are IPv6 /96 to /128.
+Other IPv6 networks do not match IPv4: ip6 (::/0) does not match 10.0.0.1.
+Unlike --ipv4-mapped, which only rewrites ::ffff:10.0.0.1 to 10.0.0.1,
+addresses keep their family: ::ffff:10.0.0.1 also still matches ip6."
+    )]
+    pub map_v4: bool,
+
+    /// Include the port after an address, like 10.0.0.1:443
+    #[arg(
+        long = "with-ports",
+        help_heading = "Matching Control",
+        long_help = "\
+Include the port after an IPv4 address (10.0.0.1:443) or a bracketed IPv6
+address ([2001:db8::1]:443) in the match. With -o, the port is printed
+with the address."
+    )]
+    pub with_ports: bool,
+
+    /// Only match addresses with a port in PORTS (implies --with-ports)
+    #[arg(
+        long = "port",
+        value_name = "PORTS",
+        value_delimiter = ',',
+        value_parser = parse_port_range,
+        help_heading = "Matching Control",
+        long_help = "\
+Only match addresses followed by a port in PORTS, a comma separated list
+of ports and port ranges, like: --port 22,443 or --port 1024-65535.
+Implies --with-ports."
+    )]
+    pub ports: Vec<RangeInclusive<u16>>,
+
+    /// Only consider the WHICH-th address on each line
+    #[arg(
+        long = "nth",
+        value_name = "WHICH",
+        value_parser = parse_nth,
+        allow_negative_numbers = true,
+        help_heading = "Matching Control",
+        long_help = "\
+Only consider the WHICH-th address on each line, when matching and
+highlighting: first, last, a number N counting from 1, or a negative
+number counting back from the end (-1 is the last). A line with fewer
+addresses does not match. All addresses count, also those of the other
+family or of forms that --accept leaves out: --nth=first 10.0.0.0/8 does
+not match the line \"::1 10.0.0.1\"."
+    )]
+    pub nth: Option<isize>,
+
+    /// Only match IPv4 addresses/networks with a prefix length of at least N
+    #[arg(
+        long = "prefix-len-min",
+        value_name = "N",
+        value_parser = value_parser!(u8).range(0..=32),
+        help_heading = "Matching Control",
+        long_help = "\
+Only match IPv4 addresses and networks with a prefix length of at least
+N, like --prefix-len-min 8 to ignore 0.0.0.0/0 and other overly broad
+networks. A bare address has prefix length 32. For IPv6, see
+--v6-prefix-len-min."
+    )]
+    pub prefix_len_min: Option<u8>,
+
+    /// Only match IPv4 addresses/networks with a prefix length of at most N
+    #[arg(
+        long = "prefix-len-max",
+        value_name = "N",
+        value_parser = value_parser!(u8).range(0..=32),
+        help_heading = "Matching Control",
+        long_help = "\
+Only match IPv4 addresses and networks with a prefix length of at most
+N, like --prefix-len-max 24 to only find networks of /24 and larger. A
+bare address has prefix length 32, so it does not match then. For IPv6,
+see --v6-prefix-len-max."
+    )]
+    pub prefix_len_max: Option<u8>,
+
+    /// Like --prefix-len-min, for IPv6
+    #[arg(
+        long = "v6-prefix-len-min",
+        value_name = "N",
+        value_parser = value_parser!(u8).range(0..=128),
+        help_heading = "Matching Control"
+    )]
+    pub v6_prefix_len_min: Option<u8>,
+
+    /// Like --prefix-len-max, for IPv6
+    #[arg(
+        long = "v6-prefix-len-max",
+        value_name = "N",
+        value_parser = value_parser!(u8).range(0..=128),
+        help_heading = "Matching Control"
+    )]
+    pub v6_prefix_len_max: Option<u8>,
+
+    /// Look up addresses in the MaxMind DB FILE for --country/--asn
+    #[arg(
+        long = "mmdb",
+        value_name = "FILE",
+        help_heading = "Matching Control",
+        long_help = "\
+Look up the matching addresses in the MaxMind DB FILE (like
+GeoLite2-Country.mmdb or GeoLite2-ASN.mmdb), and only keep those that
+pass --country and --asn. Needs ipgrep built with the \"mmdb\" feature."
+    )]
+    pub mmdb: Option<String>,
+
+    /// Only match addresses in country CC (needs --mmdb)
+    #[arg(
+        long = "country",
+        value_name = "CC",
+        value_delimiter = ',',
+        requires = "mmdb",
+        help_heading = "Matching Control",
+        long_help = "\
+Only match addresses that the --mmdb database places in one of the
+countries CC, a comma separated list of ISO 3166 codes, like: --country
+NL,BE. Addresses that are not in the database do not match."
+    )]
+    pub countries: Vec<String>,
+
+    /// Only match addresses announced by AS number ASN (needs --mmdb)
+    #[arg(
+        long = "asn",
+        value_name = "ASN",
+        value_delimiter = ',',
+        value_parser = parse_asn,
+        requires = "mmdb",
+        help_heading = "Matching Control",
+        long_help = "\
+Only match addresses that the --mmdb database places in one of the
+autonomous systems ASN, a comma separated list like: --asn 1136,AS15169.
+Addresses that are not in the database do not match."
+    )]
+    pub asns: Vec<u32>,
+
+    /// Warn about needles that are covered by other needles
+    #[arg(
+        long = "warn-redundant-needles",
+        help_heading = "Matching Control",
+        long_help = "\
+After parsing the needles, warn about positive needles that never add
+matches, because another needle already covers them under the active
+match mode. E.g. with -m within, 10.1.0.0/16 is redundant next to
+10.0.0.0/8. Useful for cleaning up long needle lists."
+    )]
+    pub warn_redundant_needles: bool,
+
+    /// Remove redundant matches on a line
+    #[arg(
+        long = "dedup",
+        value_enum,
+        value_name = "HOW",
+        help_heading = "Matching Control",
+        long_help = "\
+Remove redundant matches on a line:
+  specific  - keep only the most specific (longest prefix) of the
+              matches that overlap; of identical matches the first is
+              kept. E.g. 10.0.0.0/24 is dropped if 10.0.0.5 is found
+              on the same line."
+    )]
+    pub dedup: Option<DedupArg>,
+
+    /// Compare the addresses matched by needle lists A and B
+    #[arg(
+        long = "compare",
+        num_args = 2,
+        value_names = ["A", "B"],
+        conflicts_with_all = ["invert_match", "stdin_split"],
+        help_heading = "Matching Control",
+        long_help = "\
+Compare the IPs/networks matched by needle lists A and B (each a needle
+list like NEEDLES, or a file with such a list on one or more lines).
+At the end, the unique matches are printed in three sections: those
+only matched by A, those only matched by B and those matched by both.
+With --compare, all positional arguments are haystacks."
+    )]
+    pub compare: Vec<String>,
+
+    /// Read needles from FILE, one per line (may repeat)
+    #[arg(
+        short = 'f',
+        long = "file",
+        value_name = "FILE",
+        conflicts_with_all = ["compare", "stdin_split"],
+        help_heading = "Matching Control",
+        long_help = "\
+Read needles from FILE, one per line (may repeat). Blank lines and
+comments starting with '#' are skipped. With -f, all positional
+arguments are haystacks."
+    )]
+    pub needle_files: Vec<String>,
+
+    /// Add the needles of a builtin set NAME (may repeat)
+    #[arg(
+        long = "builtin",
+        value_name = "NAME",
+        value_parser = PossibleValuesParser::new(BUILTINS),
+        ignore_case = true,
+        conflicts_with_all = ["compare", "stdin_split"],
+        help_heading = "Matching Control",
+        long_help = "\
+Add the needles of a builtin set NAME (may repeat), like the needle alias
+of the same name:
+   rfc1918       - 10.0.0.0/8, 172.16.0.0/12, 192.168.0.0/16
+   loopback      - 127.0.0.0/8, ::1/128
+   linklocal     - 169.254.0.0/16, fe80::/10
+   multicast     - 224.0.0.0/4, ff00::/8
+   documentation - 192.0.2.0/24, 198.51.100.0/24, 203.0.113.0/24,
+                   2001:db8::/32
+With --builtin, all positional arguments are haystacks. E.g. the lines
+without an RFC 1918 address: ipgrep --builtin rfc1918 -v FILE"
+    )]
+    pub builtin: Vec<String>,
+
+    /// Exclude addresses matching NET (may repeat)
+    #[arg(
+        long = "not",
+        value_name = "NET",
+        conflicts_with = "compare",
+        help_heading = "Matching Control",
+        long_help = "\
+Exclude addresses matching NET, under the same match mode (may repeat).
+NET is a needle list like NEEDLES, and --not NET is the same as adding
+!NET to NEEDLES: an address that matches both a positive needle and an
+excluded one is not a match. E.g.: ipgrep 10.0.0.0/8 --not 10.1.2.0/24"
+    )]
+    pub not: Vec<String>,
+
+    /// Also separate needles by CHAR (may repeat)
+    #[arg(
+        long = "needle-sep",
+        value_name = "CHAR",
+        value_parser = parse_needle_sep,
+        help_heading = "Matching Control",
+        long_help = "\
+Also separate the needles in NEEDLES, --not and --compare lists by CHAR
+(may repeat). They are always separated by commas, semicolons, spaces
+and newlines. E.g. for a tab separated list: --needle-sep=$'\\t'"
+    )]
+    pub needle_sep: Vec<char>,
+
+    /// Also look for IPs/networks in the file paths themselves
+    #[arg(
+        long = "match-paths",
+        conflicts_with = "invert_match",
+        help_heading = "Matching Control",
+        long_help = "\
+Also look for IPs/networks in the path of every searched file, like
+logs/10.0.0.5/app.log. Path matches are printed prefixed with
+\"(path):\", and count as matches for -c/-l/-q."
+    )]
+    pub match_paths: bool,
+
     /// Print only a count of matching records
     #[arg(
         short = 'c',
@@ -138,6 +590,115 @@ Match mode:
     )]
     pub count: bool,
 
+    /// With -c, count matches per needle instead of per file
+    #[arg(
+        long = "by-needle",
+        requires = "count",
+        conflicts_with = "invert_match",
+        help_heading = "General Output Control",
+        long_help = "\
+With -c/--count, print a count for every positive needle instead of for
+every file, as \"NEEDLE: COUNT\", totalled over all files. Needles without
+matches are listed with a count of 0. A match is attributed to the
+first needle (in command line order) that it matches."
+    )]
+    pub by_needle: bool,
+
+    /// With -c, count every matching address, not every matching line
+    #[arg(
+        long = "count-matches",
+        requires = "count",
+        conflicts_with = "invert_match",
+        help_heading = "General Output Control",
+        long_help = "\
+With -c/--count, count every matching address instead of every matching
+line: a line with three matching IPs counts as three."
+    )]
+    pub count_matches: bool,
+
+    /// With -c, also print the total number of lines
+    #[arg(
+        long = "count-ratio",
+        requires = "count",
+        conflicts_with_all = ["by_needle", "count_matches"],
+        help_heading = "General Output Control",
+        long_help = "\
+With -c/--count, print the count as COUNT/LINES, where LINES is the total
+number of lines in the file, matching or not. Both are line counts, so
+this does not combine with --count-matches."
+    )]
+    pub count_ratio: bool,
+
+    /// With -c, print one grand total instead of a count per file
+    #[arg(
+        long = "total",
+        requires = "count",
+        conflicts_with_all = [
+            "by_needle", "count_ratio", "split_kind", "files_with_matches",
+        ],
+        help_heading = "General Output Control",
+        long_help = "\
+With -c/--count, print a single count: the count summed over all files,
+instead of a count for every file."
+    )]
+    pub total: bool,
+
+    /// With -c, count hosts, networks and interfaces separately
+    #[arg(
+        long = "split-kind",
+        requires = "count",
+        conflicts_with_all = [
+            "by_needle", "count_ratio", "csv", "match_paths", "invert_match",
+        ],
+        help_heading = "General Output Control",
+        long_help = "\
+With -c/--count, count the matches per kind, as hosts=N nets=N ifaces=N:
+  hosts   - bare IPs (10.0.0.1) and single address networks (10.0.0.1/32)
+  nets    - networks without host bits (10.0.0.0/24, or old style masks)
+  ifaces  - IPs with a prefix and host bits set (10.0.0.1/24)"
+    )]
+    pub split_kind: bool,
+
+    /// With -c, print the counts as CSV
+    #[arg(
+        long = "csv",
+        requires = "count",
+        help_heading = "General Output Control",
+        long_help = "\
+With -c/--count, print the counts as CSV, for spreadsheet import: a
+header row (path,count or, with --by-needle, needle,count) followed by a
+row per file (or needle). With --count-ratio there is a lines column."
+    )]
+    pub csv: bool,
+
+    /// Do not print the CSV header row
+    #[arg(
+        long = "no-header",
+        requires = "csv",
+        help_heading = "General Output Control"
+    )]
+    pub no_header: bool,
+
+    /// Print matches as text or as JSON objects
+    #[arg(
+        long = "output-format",
+        alias = "format",
+        value_name = "FORMAT",
+        value_enum,
+        default_value_t = OutputFormatArg::Text,
+        conflicts_with_all = [
+            "count", "files_with_matches", "quiet", "approx_unique",
+            "compare", "tree", "invert_match",
+        ],
+        help_heading = "General Output Control",
+        long_help = "\
+Print matches as text (default) or as JSON. With json, every match is
+printed as a JSON object on a line of its own (NDJSON), with the fields
+file, line, column (the byte offset in the line), match (the text as
+found), network (the normalized IP/network) and family (ipv4 or ipv6)."
+    )]
+    pub output_format: OutputFormatArg,
+
     /// List filenames with matches only
     #[arg(
         short = 'l',
@@ -146,6 +707,21 @@ Match mode:
     )]
     pub files_with_matches: bool,
 
+    /// List filenames without matches only
+    #[arg(
+        short = 'L',
+        long = "files-without-match",
+        conflicts_with_all = [
+            "files_with_matches", "total", "exec", "exec_batch",
+        ],
+        help_heading = "General Output Control",
+        long_help = "\
+List the names of the files that have no matches, instead of the files
+that do (-l). The exit status is 0 if any file was listed. Combine with
+-Z/--null to end the names with a NUL."
+    )]
+    pub files_without_match: bool,
+
     /// Print only the matching IPs/networks
     #[arg(
         short = 'o',
@@ -167,6 +743,262 @@ prefix length. E.g. pass 24 to get 192.168.2.0/24 instead of 192.168.2.4",
     )]
     pub output_prefix: Option<u8>,
 
+    /// Print -o matches normalized, like ::1 for ::0001
+    #[arg(
+        long = "canonical",
+        help_heading = "General Output Control",
+        long_help = "\
+With -o/--only-matching, print each match the normalized way instead of
+as found on the line: ::0001 is printed as ::1, 10.0.0.1/32 as 10.0.0.1,
+and interfaces as translated by --interface-mode. Lines (without -o) are
+still printed as is."
+    )]
+    pub canonical: bool,
+
+    /// Print each matching network of the specified size only once
+    #[arg(
+        long = "unique-prefix",
+        value_name = "PREFIX",
+        conflicts_with = "output_prefix",
+        help_heading = "General Output Control",
+        long_help = "\
+Like -O/--output-prefix, but print each truncated network only once, the
+first time it is found. E.g. pass 24 to list the distinct /24 networks.
+Cannot be combined with -j/--jobs.",
+        value_parser = value_parser!(u8).range(0..=128)
+    )]
+    pub unique_prefix: Option<u8>,
+
+    /// Print each matching IP/network only once
+    #[arg(
+        long = "unique",
+        help_heading = "General Output Control",
+        long_help = "\
+Implies -o/--only-matching. Print each matching IP/network only once, the
+first time it is found. Equal addresses are equal regardless of how they
+are written. With -O, each truncated network is printed once (like
+--unique-prefix). Cannot be combined with -j/--jobs."
+    )]
+    pub unique: bool,
+
+    /// With --unique, remember at most N distinct matches
+    #[arg(
+        long = "unique-cap",
+        value_name = "N",
+        value_parser = value_parser!(u64).range(1..),
+        help_heading = "General Output Control",
+        long_help = "\
+With --unique/--unique-prefix, remember at most N distinct matches, to
+bound the memory use. Once N are remembered, the matches that are not
+among them are printed every time they are found, with a warning (unless
+-q/--quiet is given). For the number of distinct matches in a huge set,
+see --approx-unique."
+    )]
+    pub unique_cap: Option<u64>,
+
+    /// Print the hostname after each matching IP
+    #[arg(
+        long = "resolve",
+        conflicts_with_all = [
+            "output_prefix", "unique_prefix", "host_index", "sort",
+        ],
+        help_heading = "General Output Control",
+        long_help = "\
+Implies -o/--only-matching. Print the hostname (the reverse DNS or
+/etc/hosts name) after each matching IP, like: 10.0.0.5 (host.example.com).
+IPs without a name are printed as is. Lookups are done with getent(1),
+a few at a time, each taking at most two seconds, and are cached. The
+output waits for the lookups of a batch of lines."
+    )]
+    pub resolve: bool,
+
+    /// Print the matching IPs/networks sorted, at the end
+    #[arg(
+        long = "sort",
+        conflicts_with = "host_index",
+        help_heading = "General Output Control",
+        long_help = "\
+Implies -o/--only-matching. Print the matching IPs/networks at the end,
+in numeric order: IPv4 first, then by network address and prefix length
+(so 10.0.0.9 comes before 10.0.0.10). The matches are printed without
+filename or line number. Combine with --unique for a sorted set."
+    )]
+    pub sort: bool,
+
+    /// Print the offset of each match within its needle network
+    #[arg(
+        long = "host-index",
+        conflicts_with_all = ["output_prefix", "unique_prefix"],
+        help_heading = "General Output Control",
+        long_help = "\
+Implies -o/--only-matching, but prints the offset of each matching
+address from the network address of the needle it matched, instead of
+the address. E.g. 10.0.0.5 is 5 in needle 10.0.0.0/24. Mostly useful
+with -m within. Matches outside their needle network are not printed."
+    )]
+    pub host_index: bool,
+
+    /// End -o/-O matches with a NUL instead of a linefeed
+    #[arg(
+        long = "print0",
+        help_heading = "General Output Control",
+        long_help = "\
+With -o/--only-matching (or -O), end every match with a NUL byte instead
+of a linefeed, for use with xargs -0. Unlike -Z/--null, which ends the
+filenames of -l/--files-with-matches."
+    )]
+    pub print0: bool,
+
+    /// Suppress -o/-O matches that repeat the previous one
+    #[arg(
+        long = "squeeze",
+        help_heading = "General Output Control",
+        long_help = "\
+With -o/--only-matching or -O/--output-prefix, suppress a match if its
+output is identical to the match printed right before it, like uniq(1)
+does. Compares the printed text (the truncated network for -O), not the
+filename or line number prefixes."
+    )]
+    pub squeeze: bool,
+
+    /// When to use colors: auto, always or never
+    #[arg(
+        long = "color",
+        alias = "colour",
+        value_name = "WHEN",
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_value_t = ColorArg::Auto,
+        default_missing_value = "auto",
+        help_heading = "General Output Control",
+        long_help = "\
+When to use colors: auto (default; when the output is a terminal and
+NO_COLOR is not set), always (also when piped, like into less -R) or
+never. The colors can be changed with GREP_COLORS."
+    )]
+    pub color: ColorArg,
+
+    /// Color for -o/-O matches (SGR like 01;32, or none)
+    #[arg(
+        long = "match-color",
+        value_name = "SGR",
+        help_heading = "General Output Control",
+        long_help = "\
+Color the matches that are printed on their own (with -o/-O, but also
+--tree and the like) with these SGR parameters, like 01;32 for bold green,
+instead of the default 01;31 (bold red). Pass none to not color them at
+all. Matches highlighted inside lines keep the default color. Has no
+effect when no colors are shown (see --color).",
+        value_parser = parse_match_color
+    )]
+    pub match_color: Option<String>,
+
+    /// Run CMD once per unique match, after searching
+    #[arg(
+        long = "exec",
+        value_name = "CMD",
+        conflicts_with_all = [
+            "exec_batch", "invert_match", "quiet", "files_with_matches",
+        ],
+        help_heading = "General Output Control",
+        long_help = "\
+After all files are searched, run CMD once for every unique matching
+IP/network, e.g. to add firewall rules. CMD is split on whitespace and
+run without a shell (there is no quoting). In its arguments, {} and {net}
+are replaced by the network (like 10.0.0.1/32) and {addr} by the address
+(like 10.0.0.1). Without placeholders, the network is appended. A failing
+command is reported on stderr.
+CAUTION: the matches come from the searched files, so do not use this
+on untrusted input with commands that could do harm."
+    )]
+    pub exec: Option<String>,
+
+    /// Run CMD for batches of unique matches, after searching
+    #[arg(
+        long = "exec-batch",
+        value_name = "CMD",
+        conflicts_with_all = ["invert_match", "quiet", "files_with_matches"],
+        help_heading = "General Output Control",
+        long_help = "\
+Like --exec, but run CMD once for many matches, like xargs(1) does. An
+argument with a placeholder is repeated for every match in the batch;
+without placeholders, the matches are appended."
+    )]
+    pub exec_batch: Option<String>,
+
+    /// Print the total number of matches on stderr, at the end
+    #[arg(
+        long = "count-to-stderr",
+        help_heading = "General Output Control",
+        long_help = "\
+Print the total number of matches, over all files, on stderr at the end.
+The normal output (like the -o/--only-matching matches) still goes to
+stdout, so it can be piped elsewhere without a second pass for the count."
+    )]
+    pub count_to_stderr: bool,
+
+    /// Print an estimate of the number of unique matches, at the end
+    #[arg(
+        long = "approx-unique",
+        conflicts_with = "invert_match",
+        help_heading = "General Output Control",
+        long_help = "\
+Print an estimate of the number of unique matching IPs/networks, over
+all files, at the end. Uses a HyperLogLog estimator, so memory use stays
+constant (16 KiB) however many there are. The estimate is usually within
+a few percent, and exact for small numbers."
+    )]
+    pub approx_unique: bool,
+
+    /// Print the matching networks as a tree, at the end
+    #[arg(
+        long = "tree",
+        help_heading = "General Output Control",
+        long_help = "\
+Print the unique matching IPs/networks at the end, sorted, as a tree:
+networks are indented below the networks that contain them."
+    )]
+    pub tree: bool,
+
+    /// Print the matching networks aggregated, at the end
+    #[arg(
+        long = "aggregate",
+        conflicts_with_all = ["invert_match", "tree"],
+        help_heading = "General Output Control",
+        long_help = "\
+Print the matching IPs/networks at the end, merged into the fewest
+networks that cover the same addresses, sorted, IPv4 first. E.g.
+10.0.0.0/25 and 10.0.0.128/25 become 10.0.0.0/24."
+    )]
+    pub aggregate: bool,
+
+    /// Maximum indentation depth for --tree
+    #[arg(
+        long = "tree-depth",
+        value_name = "N",
+        default_value_t = 8,
+        help_heading = "General Output Control",
+        long_help = "\
+Maximum indentation depth for --tree. Networks that are nested deeper
+are shown at this depth."
+    )]
+    pub tree_depth: usize,
+
+    /// Stop reading a file after N selected lines
+    #[arg(
+        short = 'M',
+        long = "max-count",
+        value_name = "N",
+        value_parser = value_parser!(usize),
+        help_heading = "General Output Control",
+        long_help = "\
+Stop reading a file after N selected lines. Trailing context (-A/-C) is
+still printed after the last line. With -c, the line count never exceeds
+N; with --count-matches, all addresses on those N lines are counted."
+    )]
+    pub max_count: Option<usize>,
+
     /// Quiet; exit status only
     #[arg(
         short = 'q',
@@ -175,6 +1007,18 @@ prefix length. E.g. pass 24 to get 192.168.2.0/24 instead of 192.168.2.4",
     )]
     pub quiet: bool,
 
+    /// Suppress error messages about unreadable or truncated files
+    #[arg(
+        short = 's',
+        long = "no-messages",
+        help_heading = "General Output Control",
+        long_help = "\
+Suppress the messages about unreadable, skipped or truncated files, and
+the notes and warnings about the needles (--classful and
+--warn-redundant-needles)."
+    )]
+    pub no_messages: bool,
+
     /// Select non-matching lines, will include non-IPs in output
     #[arg(
         short = 'v',
@@ -191,6 +1035,37 @@ prefix length. E.g. pass 24 to get 192.168.2.0/24 instead of 192.168.2.4",
     )]
     pub no_filename: bool,
 
+    /// Print the filename prefix, also for a single file
+    #[arg(
+        short = 'H',
+        long = "with-filename",
+        help_heading = "Output Line Prefix Control"
+    )]
+    pub with_filename: bool,
+
+    /// Print the filename above the matches of each file, not before each
+    #[arg(
+        long = "heading",
+        help_heading = "Output Line Prefix Control",
+        long_help = "\
+Print the filename once, on a line of its own, above the matching lines
+(or -o/--only-matching matches) of each file, instead of in front of
+each of them. Only when filenames are shown (see -H/--with-filename)."
+    )]
+    pub heading: bool,
+
+    /// Show stdin as NAME instead of (stdin)
+    #[arg(
+        long = "label",
+        value_name = "NAME",
+        help_heading = "Output Line Prefix Control",
+        long_help = "\
+Show input from stdin as file NAME instead of (stdin), wherever filenames
+are shown (like with -H/--with-filename). Ignored when stdin is not
+searched."
+    )]
+    pub label: Option<String>,
+
     /// Prefix each output line/record with lineno
     #[arg(
         short = 'n',
@@ -199,6 +1074,19 @@ prefix length. E.g. pass 24 to get 192.168.2.0/24 instead of 192.168.2.4",
     )]
     pub line_number: bool,
 
+    /// Prefix each output line with its byte offset in the file
+    #[arg(
+        short = 'b',
+        long = "byte-offset",
+        help_heading = "Output Line Prefix Control",
+        long_help = "\
+Prefix each output line with the byte offset of its start in the file,
+after the line number if -n is also given. With -o/--only-matching, this
+is the offset of the match itself. Offsets are those of the input as
+read, after decompression."
+    )]
+    pub byte_offset: bool,
+
     /// Output a zero byte instead of LF in output; only useful in
     /// tandem with -l
     #[arg(
@@ -206,52 +1094,237 @@ prefix length. E.g. pass 24 to get 192.168.2.0/24 instead of 192.168.2.4",
         long = "null",
         help_heading = "Output Line Prefix Control"
     )]
-    pub null: bool,
+    pub null: bool,
+
+    /// Print NUM lines of leading context
+    #[arg(
+        short = 'B',
+        long = "before-context",
+        help_heading = "Context Line Control"
+    )]
+    pub before_context: Option<usize>,
+
+    /// Print NUM lines of trailing context
+    #[arg(
+        short = 'A',
+        long = "after-context",
+        help_heading = "Context Line Control"
+    )]
+    pub after_context: Option<usize>,
+
+    /// Print NUM lines of output context
+    #[arg(
+        short = 'C',
+        long = "context",
+        help_heading = "Context Line Control"
+    )]
+    pub context: Option<usize>,
+
+    /// Read all files under each directory, recursively
+    #[arg(
+        short = 'r',
+        long = "recursive",
+        help_heading = "File and Directory Selection"
+    )]
+    pub recursive: bool,
+
+    /// Read all files under each directory, while dereferencing
+    /// found symlinks
+    #[arg(
+        short = 'R',
+        long = "dereference-recursive",
+        help_heading = "File and Directory Selection"
+    )]
+    pub deref_recursive: bool,
+
+    /// Select which symlinks to follow
+    #[arg(
+        long = "deref",
+        value_enum,
+        value_name = "WHICH",
+        conflicts_with = "deref_recursive",
+        help_heading = "File and Directory Selection",
+        long_help = "\
+Select which symlinks to follow:
+  none      - no symlinks, not even those on the command line
+  args      - only symlinks on the command line (default)
+  all       - also symlinks found while recursing (like -R)"
+    )]
+    pub deref: Option<DerefArg>,
+
+    /// Search only files whose name matches GLOB (may repeat)
+    #[arg(
+        long = "include",
+        value_name = "GLOB",
+        help_heading = "File and Directory Selection",
+        long_help = "\
+While recursing, search only files whose name matches GLOB (may repeat;
+a file matching any of them is searched). Directories are always
+searched. GLOB may use *, ? and [...], like: --include='*.log'"
+    )]
+    pub include: Vec<String>,
+
+    /// Skip files whose name matches GLOB (may repeat)
+    #[arg(
+        long = "exclude",
+        value_name = "GLOB",
+        help_heading = "File and Directory Selection",
+        long_help = "\
+While recursing, skip files whose name matches GLOB (may repeat). This
+wins over --include. Directories are always searched."
+    )]
+    pub exclude: Vec<String>,
+
+    /// What to do with binary files
+    #[arg(
+        long = "binary-files",
+        value_name = "TYPE",
+        value_enum,
+        default_value_t = BinaryFilesArg::Binary,
+        help_heading = "File and Directory Selection",
+        long_help = "\
+What to do with binary files (files with a NUL byte near the start):
+  binary        - print \"Binary file X matches\" instead of the matching
+                  lines (default)
+  text          - search and print them like text files
+  without-match - skip them"
+    )]
+    pub binary_files: BinaryFilesArg,
+
+    /// What to do with FIFOs, sockets and devices
+    #[arg(
+        long = "devices",
+        value_name = "ACTION",
+        value_enum,
+        default_value_t = DevicesArg::Read,
+        help_heading = "File and Directory Selection",
+        long_help = "\
+What to do with FIFOs, sockets and devices named on the command line:
+  read - read them like regular files (default)
+  skip - skip them
+Those found while recursing are always skipped."
+    )]
+    pub devices: DevicesArg,
+
+    /// Skip files larger than SIZE while recursing
+    #[arg(
+        long = "max-filesize",
+        value_name = "SIZE",
+        value_parser = parse_size,
+        help_heading = "File and Directory Selection",
+        long_help = "\
+Skip the files found while recursing that are larger than SIZE bytes.
+SIZE may have a K, M, G or T suffix (powers of 1024). Files named on the
+command line are always searched."
+    )]
+    pub max_filesize: Option<u64>,
+
+    /// Flush output on every line
+    #[arg(long = "line-buffered", help_heading = "Other Options")]
+    pub line_buffered: bool,
+
+    /// Input and output lines end with a NUL instead of a linefeed
+    #[arg(
+        short = 'z',
+        long = "null-data",
+        help_heading = "Other Options",
+        long_help = "\
+Treat the input as lines ending in a NUL byte instead of a linefeed, like
+the output of find -print0. Line numbers count those NUL-ended lines, and
+the output lines and -o matches end with a NUL too (implies --print0).
+Unlike -Z/--null, which only ends the filenames of -l."
+    )]
+    pub null_data: bool,
+
+    /// Remove ANSI color codes from the input before searching
+    #[arg(
+        long = "strip-ansi",
+        conflicts_with = "byte_offset",
+        help_heading = "Other Options",
+        long_help = "\
+Remove ANSI color codes (CSI escape sequences) from each input line
+before searching, so already colored input can be matched. The lines
+are shown with the color codes removed. Cannot be combined with
+-b/--byte-offset, whose offsets are those in the file."
+    )]
+    pub strip_ansi: bool,
 
-    /// Print NUM lines of leading context
+    /// Search files using N threads (default: the number of CPUs)
     #[arg(
-        short = 'B',
-        long = "before-context",
-        help_heading = "Context Line Control"
+        short = 'j',
+        long = "jobs",
+        visible_alias = "threads",
+        value_name = "N",
+        value_parser = value_parser!(u16).range(1..),
+        help_heading = "Other Options",
+        long_help = "\
+Search files using N threads (default: the number of CPUs). The output is
+the same as with a single thread: results are shown in file order. The
+output of a file is only shown once the whole file has been searched, so
+stdin, a single file and --line-buffered output are searched on a single
+thread. With --unique/--unique-prefix, the default is a single thread."
     )]
-    pub before_context: Option<usize>,
+    pub threads: Option<u16>,
 
-    /// Print NUM lines of trailing context
+    /// Stop scanning a file after BYTES bytes
     #[arg(
-        short = 'A',
-        long = "after-context",
-        help_heading = "Context Line Control"
+        long = "scan-limit",
+        value_name = "BYTES",
+        value_parser = value_parser!(u64).range(1..),
+        help_heading = "Other Options",
+        long_help = "\
+Stop scanning a file after BYTES bytes, to bound the work done on
+untrusted input. The rest of the file is skipped with a notice (unless
+-s/--no-messages is given), including a line that the limit cuts short.
+Matches found before the limit still count."
     )]
-    pub after_context: Option<usize>,
+    pub scan_limit: Option<u64>,
 
-    /// Print NUM lines of output context
+    /// Report diagnostics on stderr
     #[arg(
-        short = 'C',
-        long = "context",
-        help_heading = "Context Line Control"
+        long = "debug",
+        help_heading = "Other Options",
+        long_help = "\
+Report diagnostics on stderr. Currently: near-misses, where digits are
+separated by a dot (like 10,0.0.1 or 1.2.3) but no IP/network was
+found. Useful for spotting malformed data. Matching is not affected."
     )]
-    pub context: Option<usize>,
+    pub debug: bool,
 
-    /// Read all files under each directory, recursively
+    /// Print scan statistics on stderr, at the end
     #[arg(
-        short = 'r',
-        long = "recursive",
-        help_heading = "File and Directory Selection"
+        long = "stats",
+        help_heading = "Other Options",
+        long_help = "\
+Print the number of files searched, lines and bytes read, matches and
+the elapsed wall time on stderr at the end. The normal output and the
+exit status are not affected. They are also printed with -q/--quiet,
+which stops at the first match, so they show how much was read until
+then."
     )]
-    pub recursive: bool,
+    pub stats: bool,
 
-    /// Read all files under each directory, while dereferencing
-    /// found symlinks
+    /// Read needles from stdin up to a separator line, then search the
+    /// rest of stdin
     #[arg(
-        short = 'R',
-        long = "dereference-recursive",
-        help_heading = "File and Directory Selection"
+        long = "stdin-split",
+        conflicts_with_all = ["needles", "haystacks"],
+        help_heading = "Other Options",
+        long_help = "\
+Read needles from stdin, one or more per line, up to a separator line
+(see --stdin-separator). The rest of stdin is searched. It is an error
+if there is no separator line."
     )]
-    pub deref_recursive: bool,
+    pub stdin_split: bool,
 
-    /// Flush output on every line
-    #[arg(long = "line-buffered", help_heading = "Other Options")]
-    pub line_buffered: bool,
+    /// Separator line for --stdin-split
+    #[arg(
+        long = "stdin-separator",
+        value_name = "LINE",
+        default_value = "---",
+        help_heading = "Other Options"
+    )]
+    pub stdin_separator: String,
 
     /// Show help
     #[arg(
@@ -267,11 +1340,10 @@ prefix length. E.g. pass 24 to get 192.168.2.0/24 instead of 192.168.2.4",
     pub version: Option<bool>,
 
     /// Needles (one or more networks separated by comma or whitespace)
-    #[arg(
-        default_value = "ip4,ip6",
-        long_help = "\
-Needles are one or more IP addresses, networks or IP classes (separated
-by comma/whitespace). Needles may be negated by prefixing them with a '!'.
+    #[arg(long_help = "\
+Needles are one or more IP addresses, networks, IP ranges or IP classes
+(separated by comma/semicolon/whitespace). Needles may be negated by
+prefixing them with a '!'.
 Matches are made if any of the positive needles match and none of the
 negative ones do.
 
@@ -280,10 +1352,11 @@ Examples of valid needles:
 - 192.168.0.0/16
 - 10.0.0.0/8,!10.2.0.0/16,fc00::/7
 - ip4,!rfc1918
+- 10.0.0.1-10.0.0.20
+- 10.0.{1,2,3}.0/24 (expands to 10.0.1.0/24,10.0.2.0/24,10.0.3.0/24)
 
-Valid classes include: ip4, ip6, global, localhost4, multicast6, private."
-    )]
-    pub needles: NeedleArg,
+Valid classes include: ip4, ip6, global, localhost4, multicast6, private.")]
+    pub needles: Option<NeedleArg>,
 
     /// Haystacks are one or more files. If none (or '-') given, stdin is read.
     pub haystacks: Vec<String>,
@@ -292,9 +1365,28 @@ Valid classes include: ip4, ip6, global, localhost4, multicast6, private."
 const ERR_CONTEXT_CONFLICT: &str = "\
 --context conflicts with --before-context/--after-context\n";
 const ERR_INVONLY_CONFLICT: &str = "\
---invert-match conflicts with --only-matching/--output-prefix\n";
+--invert-match conflicts with --only-matching/--output-prefix/--tree/\
+--unique-prefix/--host-index\n";
+const ERR_OTHER_FAMILY_CONFLICT: &str = "\
+--report-other-family needs needles of a single family\n";
+const ERR_UNIQUE_THREADS_CONFLICT: &str = "\
+--unique/--unique-prefix cannot be used with --jobs\n";
+const ERR_UNIQUE_CAP: &str = "\
+--unique-cap needs --unique or --unique-prefix\n";
+const ERR_EXEC_EMPTY: &str = "\
+--exec/--exec-batch needs a command\n";
 const ERR_RECURSIVE_CONFLICT: &str = "\
 choose either --recursive or --deref-recursive\n";
+const ERR_NO_FILE_NEEDLES: &str = "\
+--file: no needles found in the needle files\n";
+const ERR_FILENAME_CONFLICT: &str = "\
+choose either --no-filename or --with-filename\n";
+const ERR_MMDB_NO_FILTER: &str = "\
+--mmdb needs --country and/or --asn\n";
+const ERR_OUTSIDE_MATCH_MODE: &str = "\
+--outside needs match mode contains or within\n";
+const ERR_PREFIX_LEN_RANGE: &str = "\
+--prefix-len-min/--v6-prefix-len-min is above the matching -max\n";
 
 impl Args {
     pub fn parse() -> Self {
@@ -307,11 +1399,115 @@ impl Args {
         let output_style = self.make_output_style();
         let show_context = self.make_show_context();
         let recursive = self.make_recursive();
+        let follow_arg_symlinks = self.deref != Some(DerefArg::None);
+        let v4_prefix_lens =
+            make_prefix_lens(self.prefix_len_min, self.prefix_len_max, 32);
+        let v6_prefix_lens = make_prefix_lens(
+            self.v6_prefix_len_min,
+            self.v6_prefix_len_max,
+            128,
+        );
+
+        if [&self.exec, &self.exec_batch]
+            .iter()
+            .any(|cmd| cmd.as_ref().is_some_and(|c| c.trim().is_empty()))
+        {
+            Error::raw(ErrorKind::InvalidValue, ERR_EXEC_EMPTY).exit();
+        }
+        if self.no_filename && self.with_filename {
+            Error::raw(ErrorKind::ArgumentConflict, ERR_FILENAME_CONFLICT)
+                .exit();
+        }
+        let is_unique = self.unique || self.unique_prefix.is_some();
+        if is_unique && self.threads.is_some_and(|n| n > 1) {
+            // The threads would each keep their own unique networks.
+            Error::raw(
+                ErrorKind::ArgumentConflict,
+                ERR_UNIQUE_THREADS_CONFLICT,
+            )
+            .exit();
+        }
+        if self.unique_cap.is_some() && !is_unique {
+            Error::raw(ErrorKind::MissingRequiredArgument, ERR_UNIQUE_CAP)
+                .exit();
+        }
+        let threads = match self.threads {
+            Some(n) => n.into(),
+            None if is_unique => 1,
+            None => thread::available_parallelism().map_or(1, |n| n.get()),
+        };
+
+        let compare_needles = match self.compare.as_slice() {
+            [a, b] => Some((
+                read_compare_needles(a, &self.needle_sep),
+                read_compare_needles(b, &self.needle_sep),
+            )),
+            _ => None,
+        };
+
+        // Like grep, -q is silent.
+        let diagnostics: Arc<dyn Diagnostics> = match self.quiet {
+            true => Arc::new(Silent),
+            false => Arc::new(Stderr),
+        };
+        // The notes and warnings about the needles are suppressed by -s
+        // too, like the messages about unreadable files.
+        let needle_diagnostics: &dyn Diagnostics = match self.no_messages {
+            true => &Silent,
+            false => &*diagnostics,
+        };
 
-        let all_needles: Vec<Needle> = self.needles.into();
+        let (all_needles, match_mode) = if let Some((a, b)) = &compare_needles {
+            // Match mode depends on the needles of both lists. We look
+            // for anything that A or B might match.
+            let all_needles: Vec<Needle> = a.iter().chain(b).cloned().collect();
+            let match_mode = self.match_mode.resolve(&all_needles);
+            let any_needles =
+                all_needles.into_iter().filter(|n| !n.is_negated).collect();
+            (any_needles, match_mode)
+        } else {
+            let mut all_needles: Vec<Needle> = if !self.needle_files.is_empty()
+            {
+                read_needle_files(&self.needle_files)
+            } else if self.stdin_split {
+                self.read_stdin_needles()
+                    .with_separators(&self.needle_sep)
+                    .into()
+            } else if !self.builtin.is_empty() {
+                Vec::new()
+            } else {
+                self.needles
+                    .clone()
+                    .unwrap_or_else(|| NeedleArg("ip4,ip6".into()))
+                    .with_separators(&self.needle_sep)
+                    .into()
+            };
+            for name in &self.builtin {
+                let needles = Needle::builtin(name).expect("clap");
+                extend_needles(&mut all_needles, needles);
+            }
+            // The --not needles are negated needles.
+            for list in &self.not {
+                let needles: Vec<Needle> = NeedleArg(list.clone())
+                    .with_separators(&self.needle_sep)
+                    .into();
+                let needles = needles.into_iter().map(|mut needle| {
+                    needle.is_negated ^= true;
+                    needle
+                });
+                extend_needles(&mut all_needles, needles.collect());
+            }
+            if self.classful {
+                widen_to_classful(&mut all_needles, needle_diagnostics);
+            }
+            if self.hosts_only {
+                require_hosts(&all_needles);
+            }
 
-        // Match mode depends on the needles.
-        let match_mode: MatchMode = self.match_mode.resolve(&all_needles);
+            // Match mode depends on the needles.
+            let match_mode = self.match_mode.resolve(&all_needles);
+            (all_needles, match_mode)
+        };
 
         // Needles are split into positive and negative ones.
         let (negative_needles, mut positive_needles): (
@@ -319,44 +1515,152 @@ impl Args {
             Vec<Needle>,
         ) = all_needles.into_iter().partition(|n| n.is_negated);
         if positive_needles.is_empty() {
-            // Design choice: if the user specifies "!rfc1918" they will
-            // only get IPv4 addresses.  If they want IPv6 as well, they
-            // should use "any,!rfc1918".
-            let has_v4 = negative_needles.iter().any(|n| n.net.is_ipv4());
-            let has_v6 = negative_needles.iter().any(|n| n.net.is_ipv6());
-            assert!(has_v4 || has_v6);
-            if has_v4 {
-                positive_needles.push(Needle::try_from("0.0.0.0/0").unwrap());
-            }
-            if has_v6 {
-                positive_needles.push(Needle::try_from("::/0").unwrap());
+            positive_needles = implied_positive_needles(&negative_needles);
+        }
+        if self.warn_redundant_needles {
+            for (idx, other) in find_redundant(&positive_needles, &match_mode) {
+                needle_diagnostics.warn(
+                    &positive_needles[idx].src,
+                    &format!(
+                        "redundant needle: covered by {} (with -m {match_mode})",
+                        positive_needles[other].src
+                    ),
+                );
             }
         }
+        if self.outside
+            && !matches!(match_mode, MatchMode::Contains | MatchMode::Within)
+        {
+            Error::raw(ErrorKind::ArgumentConflict, ERR_OUTSIDE_MATCH_MODE)
+                .exit();
+        }
+        if self.report_other_family
+            && positive_needles.iter().any(|n| n.net.is_ipv4())
+            && positive_needles.iter().any(|n| n.net.is_ipv6())
+        {
+            Error::raw(ErrorKind::ArgumentConflict, ERR_OTHER_FAMILY_CONFLICT)
+                .exit();
+        }
+        let geo_filter = self
+            .mmdb
+            .as_deref()
+            .map(|path| open_geo_filter(path, &self.countries, &self.asns));
 
         Parameters {
-            accept: self.accept.into(),
+            accept: if self.hosts_only {
+                vec![AcceptSetArg::Ip].into()
+            } else {
+                self.accept.into()
+            },
             interface_mode: self.interface_mode.into(),
             match_mode,
+            report_other_family: self.report_other_family,
+            match_all: self.match_all,
+            outside: self.outside,
+            also_literals: self.also_literal,
+            or_literals: self.or_literal,
+            ipv4_mapped: self.ipv4_mapped,
+            map_v4: self.map_v4,
+            ports: (self.with_ports || !self.ports.is_empty())
+                .then_some(self.ports),
+            nth: self.nth,
+            v4_prefix_lens,
+            v6_prefix_lens,
+            geo_filter,
+            leading_zeros: self.leading_zeros,
+            strict_boundaries: self.strict_boundaries,
+            dedup_specific: self.dedup == Some(DedupArg::Specific),
+            match_paths: self.match_paths,
             output_style,
-            rewrite_output_prefix: self.output_prefix,
+            rewrite_output_prefix: self.output_prefix.or(self.unique_prefix),
+            canonical: self.canonical,
+            unique: self.unique || self.unique_prefix.is_some(),
+            unique_cap: self
+                .unique_cap
+                .map(|n| usize::try_from(n).unwrap_or(usize::MAX)),
+            sort: self.sort,
+            resolve: self.resolve,
+            host_index: self.host_index,
+            count_matches: self.count_matches,
+            count_ratio: self.count_ratio,
+            count_total: self.total,
+            count_to_stderr: self.count_to_stderr,
+            split_kind: self.split_kind,
+            exec_command: self
+                .exec
+                .as_ref()
+                .or(self.exec_batch.as_ref())
+                .map(|cmd| cmd.split_whitespace().map(String::from).collect())
+                .unwrap_or_default(),
+            exec_batch: self.exec_batch.is_some(),
+            csv: self.csv,
+            csv_header: !self.no_header,
+            squeeze: self.squeeze,
+            print0: self.print0 || self.null_data,
+            color: match self.color {
+                ColorArg::Auto => ShowColor::Auto,
+                ColorArg::Always => ShowColor::Always,
+                ColorArg::Never => ShowColor::Never,
+            },
+            match_color: self.match_color,
+            tree_depth: self.tree_depth,
             invert_match: self.invert_match,
+            max_count: self.max_count,
             hide_filename: self.no_filename,
+            force_filename: self.with_filename,
+            heading: self.heading,
+            label: self.label,
             show_lineno: self.line_number,
+            show_byte_offset: self.byte_offset,
             show_context,
             recursive,
+            follow_arg_symlinks,
+            include_globs: self.include,
+            exclude_globs: self.exclude,
+            binary_files: match self.binary_files {
+                BinaryFilesArg::Binary => BinaryFiles::Binary,
+                BinaryFilesArg::Text => BinaryFiles::Text,
+                BinaryFilesArg::WithoutMatch => BinaryFiles::WithoutMatch,
+            },
+            devices: match self.devices {
+                DevicesArg::Read => Devices::Read,
+                DevicesArg::Skip => Devices::Skip,
+            },
+            max_filesize: self.max_filesize,
             line_buffered: self.line_buffered,
+            null_data: self.null_data,
+            strip_ansi: self.strip_ansi,
+            scan_limit: self.scan_limit,
+            no_messages: self.no_messages,
+            threads,
+            debug: self.debug,
+            stats: self.stats,
+            diagnostics,
             positive_needles,
             negative_needles,
-            haystack_filenames: self.haystacks,
+            haystack_filenames: match self.needles {
+                // With --compare, -f or --builtin, the first positional
+                // argument is a haystack too.
+                Some(NeedleArg(haystack))
+                    if compare_needles.is_some()
+                        || !self.needle_files.is_empty()
+                        || !self.builtin.is_empty() =>
+                {
+                    std::iter::once(haystack).chain(self.haystacks).collect()
+                }
+                _ => self.haystacks,
+            },
+            compare_needles,
         }
     }
 
     // GNU grep (3.11) has these output modes:
     // "-q/--quiet" shows nothing;
-    // "-l/--files-with-matches" only shows files;
-    // "-c/--count" shows files with counts (not a grand total);
+    // "-l/--files-with-matches" only shows files (-L those without);
+    // "-c/--count" shows files with counts (or a grand total, --total);
     // "-o/--only-matching" shows the matches;
     // -q trumps -l, -l trumps -c, -c trumps -o.
+    // Our --approx-unique and --tree sit between -c and -o.
     fn make_output_style(&self) -> OutputStyle {
         if self.quiet {
             // -q/--quiet
@@ -367,10 +1671,45 @@ impl Args {
         } else if self.files_with_matches {
             // -l/--file-with-matches
             OutputStyle::ShowFilesWithLf
+        } else if self.files_without_match && self.null {
+            // -L/--files-without-match, -Z/--null
+            OutputStyle::ShowFilesWithoutMatchNull
+        } else if self.files_without_match {
+            // -L/--files-without-match
+            OutputStyle::ShowFilesWithoutMatchLf
+        } else if self.count && self.by_needle {
+            // -c/--count, --by-needle
+            OutputStyle::ShowCountsPerNeedle
         } else if self.count {
             // -c/--count
             OutputStyle::ShowCountsPerFile
-        } else if self.only_matching || self.output_prefix.is_some() {
+        } else if self.approx_unique {
+            // --approx-unique
+            OutputStyle::ShowApproxUnique
+        } else if !self.compare.is_empty() {
+            // --compare
+            OutputStyle::ShowComparison
+        } else if self.tree {
+            // --tree
+            if self.invert_match {
+                Error::raw(ErrorKind::ArgumentConflict, ERR_INVONLY_CONFLICT)
+                    .exit();
+            }
+            OutputStyle::ShowTree
+        } else if self.aggregate {
+            // --aggregate
+            OutputStyle::ShowAggregated
+        } else if self.output_format == OutputFormatArg::Json {
+            // --output-format=json
+            OutputStyle::ShowJsonMatches
+        } else if self.only_matching
+            || self.output_prefix.is_some()
+            || self.unique_prefix.is_some()
+            || self.unique
+            || self.sort
+            || self.resolve
+            || self.host_index
+        {
             // -o/--only-matching
             if self.invert_match {
                 Error::raw(ErrorKind::ArgumentConflict, ERR_INVONLY_CONFLICT)
@@ -382,6 +1721,22 @@ impl Args {
         }
     }
 
+    fn read_stdin_needles(&self) -> NeedleArg {
+        let sep = &self.stdin_separator;
+        match read_until_separator(&mut io::stdin().lock(), sep) {
+            Ok(Some(needles)) => NeedleArg(needles),
+            Ok(None) => Error::raw(
+                ErrorKind::ValueValidation,
+                format!("--stdin-split: separator line {sep:?} not found\n"),
+            )
+            .exit(),
+            Err(e) => {
+                Error::raw(ErrorKind::Io, format!("--stdin-split: {e}\n"))
+                    .exit()
+            }
+        }
+    }
+
     fn make_show_context(&self) -> ShowContext {
         let mut context = ShowContext::default();
         if let Some(value) = self.context {
@@ -408,7 +1763,9 @@ impl Args {
             Error::raw(ErrorKind::ArgumentConflict, ERR_RECURSIVE_CONFLICT)
                 .exit();
         }
-        if self.deref_recursive {
+        if self.deref_recursive
+            || (self.recursive && self.deref == Some(DerefArg::All))
+        {
             RecurseHaystacks::FollowDirectorySymlinks
         } else if self.recursive {
             RecurseHaystacks::FollowDirectories
@@ -418,6 +1775,306 @@ impl Args {
     }
 }
 
+/// Return the positive needles that go with only negative ones.
+fn implied_positive_needles(negative_needles: &[Needle]) -> Vec<Needle> {
+    // Design choice: if the user specifies "!rfc1918" they will
+    // only get IPv4 addresses.  If they want IPv6 as well, they
+    // should use "any,!rfc1918".
+    let has_v4 = negative_needles.iter().any(|n| n.net.is_ipv4());
+    let has_v6 = negative_needles.iter().any(|n| n.net.is_ipv6());
+    assert!(has_v4 || has_v6);
+    let mut positive_needles = Vec::new();
+    if has_v4 {
+        positive_needles.push(Needle::try_from("0.0.0.0/0").unwrap());
+    }
+    if has_v6 {
+        positive_needles.push(Needle::try_from("::/0").unwrap());
+    }
+    positive_needles
+}
+
+/// Replace the bare IPv4 needles by their classful network, for
+/// --classful, noting each in the diagnostics.
+fn widen_to_classful(needles: &mut [Needle], diagnostics: &dyn Diagnostics) {
+    for needle in needles {
+        if let Some(classful) = needle.to_classful() {
+            diagnostics.warn(
+                &needle.src,
+                &format!("taken as classful network {}", classful.net),
+            );
+            *needle = classful;
+        }
+    }
+}
+
+/// Exit with an error for all needles that are not a single host, for
+/// --hosts-only.
+fn require_hosts(needles: &[Needle]) {
+    let mut msgs: Vec<String> = needles
+        .iter()
+        .filter_map(|n| n.require_host().err())
+        .map(|err| err.to_string())
+        .collect();
+    // Aliases and ranges give several needles with the same src.
+    msgs.dedup();
+    if !msgs.is_empty() {
+        let msg = format!("{}\n", msgs.join("\nerror: "));
+        Error::raw(ErrorKind::InvalidValue, msg).exit();
+    }
+}
+
+/// Parse the --match-color SGR parameters. Returns an empty string for
+/// none.
+fn parse_match_color(s: &str) -> Result<String, String> {
+    if s == "none" {
+        Ok(String::new())
+    } else if !s.is_empty()
+        && s.bytes().all(|c| c.is_ascii_digit() || c == b';')
+    {
+        Ok(s.to_string())
+    } else {
+        Err("expected SGR parameters like 01;32, or none".to_string())
+    }
+}
+
+/// Parse one --port value: a port (22) or a port range (1024-65535).
+fn parse_port_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let parse = |port: &str| {
+        port.trim()
+            .parse::<u16>()
+            .map_err(|_| format!("expected a port or port range, not {s:?}"))
+    };
+    match s.split_once('-') {
+        Some((first, last)) => {
+            let (first, last) = (parse(first)?, parse(last)?);
+            if first > last {
+                return Err(format!("port range ends before it starts: {s}"));
+            }
+            Ok(first..=last)
+        }
+        None => parse(s).map(|port| port..=port),
+    }
+}
+
+/// Expand the brace patterns in a needle list, like 10.0.{1,2}.0/24 to
+/// 10.0.1.0/24,10.0.2.0/24. The commas inside the braces separate the
+/// alternatives; only those outside separate the needles. Nested braces
+/// are not supported.
+fn expand_braces(list: &str) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut needle = String::new();
+    let mut in_braces = false;
+    for ch in list.chars() {
+        match ch {
+            '{' if in_braces => {
+                return Err(format!("nested braces in needles {list:?}"));
+            }
+            '}' if !in_braces => {
+                return Err(format!("unmatched '}}' in needles {list:?}"));
+            }
+            '{' | '}' => in_braces = !in_braces,
+            ',' | ';' | ' ' | '\n' if !in_braces => {
+                expand_needle(&needle, &mut expanded);
+                expanded.push(ch);
+                needle.clear();
+                continue;
+            }
+            _ => {}
+        }
+        needle.push(ch);
+    }
+    if in_braces {
+        return Err(format!("unmatched '{{' in needles {list:?}"));
+    }
+    expand_needle(&needle, &mut expanded);
+    Ok(expanded)
+}
+
+/// Append the needles that the (balanced) brace patterns in needle
+/// expand to, comma separated. Several patterns expand to all
+/// combinations.
+fn expand_needle(needle: &str, expanded: &mut String) {
+    let Some((head, rest)) = needle.split_once('{') else {
+        expanded.push_str(needle);
+        return;
+    };
+    let (alternatives, tail) = rest.split_once('}').expect("balanced");
+    for (idx, alternative) in alternatives.split(',').enumerate() {
+        if idx != 0 {
+            expanded.push(',');
+        }
+        expand_needle(&format!("{head}{alternative}{tail}"), expanded);
+    }
+}
+
+/// Parse a --max-filesize value: a number of bytes, optionally with a
+/// K, M, G or T suffix.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let err = || format!("expected a size like 500K, 10M or 2G, not {s:?}");
+    let (digits, shift) = match s.as_bytes().last() {
+        Some(b'K' | b'k') => (&s[..s.len() - 1], 10),
+        Some(b'M' | b'm') => (&s[..s.len() - 1], 20),
+        Some(b'G' | b'g') => (&s[..s.len() - 1], 30),
+        Some(b'T' | b't') => (&s[..s.len() - 1], 40),
+        _ => (s, 0),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(err());
+    }
+    let size = digits.parse::<u64>().map_err(|_| err())?;
+    size.checked_mul(1 << shift).ok_or_else(err)
+}
+
+/// The --prefix-len-min/max range of a family with max_len bit
+/// addresses, or exit if it is empty.
+fn make_prefix_lens(
+    min: Option<u8>,
+    max: Option<u8>,
+    max_len: u8,
+) -> RangeInclusive<u8> {
+    let lens = min.unwrap_or(0)..=max.unwrap_or(max_len);
+    if lens.is_empty() {
+        Error::raw(ErrorKind::ArgumentConflict, ERR_PREFIX_LEN_RANGE).exit();
+    }
+    lens
+}
+
+/// Parse the --nth value: first, last, or a non-zero 1-based index that
+/// counts back from the end when negative.
+fn parse_nth(s: &str) -> Result<isize, String> {
+    match s {
+        "first" => Ok(1),
+        "last" => Ok(-1),
+        _ => match s.parse::<isize>() {
+            Ok(0) | Err(_) => Err(format!(
+                "expected first, last or a non-zero number, not {s:?}"
+            )),
+            Ok(n) => Ok(n),
+        },
+    }
+}
+
+/// Parse a --needle-sep value: a single character that cannot be part
+/// of a needle.
+fn parse_needle_sep(s: &str) -> Result<char, String> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(ch), None)
+            if !ch.is_alphanumeric() && !".:/!-{}%".contains(ch) =>
+        {
+            Ok(ch)
+        }
+        _ => Err(format!(
+            "expected a single character that is not part of a needle, \
+             not {s:?}"
+        )),
+    }
+}
+
+/// Parse one --asn value: an AS number, optionally prefixed with "AS".
+fn parse_asn(s: &str) -> Result<u32, String> {
+    let digits = s
+        .strip_prefix("AS")
+        .or_else(|| s.strip_prefix("as"))
+        .unwrap_or(s);
+    digits
+        .parse::<u32>()
+        .map_err(|_| format!("expected an AS number, not {s:?}"))
+}
+
+/// Open the --mmdb database, or exit if that fails.
+fn open_geo_filter(
+    path: &str,
+    countries: &[String],
+    asns: &[u32],
+) -> GeoFilter {
+    if countries.is_empty() && asns.is_empty() {
+        Error::raw(ErrorKind::MissingRequiredArgument, ERR_MMDB_NO_FILTER)
+            .exit();
+    }
+    match GeoFilter::open(path, countries, asns) {
+        Ok(filter) => filter,
+        Err(e) => {
+            Error::raw(ErrorKind::Io, format!("--mmdb: {path}: {e}\n")).exit()
+        }
+    }
+}
+
+/// Read a --compare needle list: the list itself, or a file with it.
+fn read_compare_needles(arg: &str, separators: &[char]) -> Vec<Needle> {
+    let list = if Path::new(arg).is_file() {
+        match fs::read_to_string(arg) {
+            Ok(contents) => contents,
+            Err(e) => {
+                Error::raw(ErrorKind::Io, format!("--compare: {arg}: {e}\n"))
+                    .exit()
+            }
+        }
+    } else {
+        arg.to_string()
+    };
+    let mut needles: Vec<Needle> =
+        NeedleArg(list).with_separators(separators).into();
+    if needles.iter().all(|n| n.is_negated) {
+        let implied = implied_positive_needles(&needles);
+        needles.extend(implied);
+    }
+    needles
+}
+
+/// Read the -f needle files. Reports all bad lines at once.
+fn read_needle_files(paths: &[String]) -> Vec<Needle> {
+    let mut needles = Vec::new();
+    let mut msgs = Vec::new();
+    for path in paths {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                Error::raw(ErrorKind::Io, format!("--file: {path}: {e}\n"))
+                    .exit()
+            }
+        };
+        match Needle::parse_lines(&contents) {
+            Ok(parsed) => extend_needles(&mut needles, parsed),
+            Err(errors) => msgs.extend(
+                errors
+                    .iter()
+                    .map(|(lineno, err)| format!("{path}:{lineno}: {err}")),
+            ),
+        }
+    }
+    if !msgs.is_empty() {
+        let msg = format!("{}\n", msgs.join("\nerror: "));
+        Error::raw(ErrorKind::InvalidValue, msg).exit();
+    }
+    if needles.is_empty() {
+        Error::raw(ErrorKind::InvalidValue, ERR_NO_FILE_NEEDLES).exit();
+    }
+    needles
+}
+
+/// Read lines up to (and including) the separator line and return them
+/// joined by commas. Returns None if there was no separator line. The
+/// reader is left positioned just after the separator.
+fn read_until_separator(
+    reader: &mut dyn BufRead,
+    separator: &str,
+) -> io::Result<Option<String>> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let trimmed = line.trim_end_matches(['\r', '\n']);
+        if trimmed == separator {
+            return Ok(Some(lines.join(",")));
+        }
+        lines.push(trimmed.to_string());
+    }
+}
+
 /// Conversion from String to NeedleArg during clap arg parsing
 impl FromStr for NeedleArg {
     type Err = std::convert::Infallible;
@@ -428,21 +2085,28 @@ impl FromStr for NeedleArg {
 }
 
 /// Conversion helper for AcceptSetArg to AcceptSet
-impl From<Vec<AcceptSetArg>> for AcceptSet {
-    fn from(args: Vec<AcceptSetArg>) -> Self {
+impl From<AcceptSetArg> for AcceptSet {
+    fn from(arg: AcceptSetArg) -> Self {
         let mut set = AcceptSet::default();
-        for arg in args {
-            match arg {
-                AcceptSetArg::Ip => set.ip = true,
-                AcceptSetArg::Net => set.net = true,
-                AcceptSetArg::Oldnet => set.oldnet = true,
-                AcceptSetArg::Iface => set.iface = true,
-            }
+        match arg {
+            AcceptSetArg::Ip => set.ip = true,
+            AcceptSetArg::Net => set.net = true,
+            AcceptSetArg::Oldnet => set.oldnet = true,
+            AcceptSetArg::Iface => set.iface = true,
         }
         set
     }
 }
 
+/// Conversion helper for multiple AcceptSetArgs to a single AcceptSet
+impl From<Vec<AcceptSetArg>> for AcceptSet {
+    fn from(args: Vec<AcceptSetArg>) -> Self {
+        args.into_iter()
+            .map(AcceptSet::from)
+            .fold(AcceptSet::default(), AcceptSet::union)
+    }
+}
+
 /// Conversion helper for InterfaceModeArg to InterfaceMode
 impl From<InterfaceModeArg> for InterfaceMode {
     fn from(i: InterfaceModeArg) -> Self {
@@ -450,6 +2114,7 @@ impl From<InterfaceModeArg> for InterfaceMode {
             InterfaceModeArg::Ip => InterfaceMode::TreatAsIp,
             InterfaceModeArg::Net => InterfaceMode::TreatAsNetwork,
             InterfaceModeArg::Complain => InterfaceMode::ComplainAndSkip,
+            InterfaceModeArg::Summary => InterfaceMode::SummarizeAndSkip,
         }
     }
 }
@@ -477,6 +2142,17 @@ impl MatchModeArg {
             MatchModeArg::Within => MatchMode::Within,
             MatchModeArg::Equals => MatchMode::Equals,
             MatchModeArg::Overlaps => MatchMode::Overlaps,
+            MatchModeArg::Touches => MatchMode::Touches,
+        }
+    }
+}
+
+impl NeedleArg {
+    /// Also separate the needles by the --needle-sep characters.
+    fn with_separators(self, separators: &[char]) -> Self {
+        match separators {
+            [] => self,
+            _ => NeedleArg(self.0.replace(separators, ",")),
         }
     }
 }
@@ -484,21 +2160,23 @@ impl MatchModeArg {
 /// Conversion helper for NeedleArg to Vec<Needle>
 impl From<NeedleArg> for Vec<Needle> {
     fn from(s: NeedleArg) -> Vec<Needle> {
-        let mut needles = Vec::new();
-        for tok in s.0.split([',', ' ']) {
-            let trimmed = tok.trim();
-            if trimmed.is_empty() {
-                continue;
+        let list = match expand_braces(&s.0) {
+            Ok(list) => list,
+            Err(err) => {
+                Error::raw(ErrorKind::InvalidValue, format!("{err}\n")).exit()
             }
-
-            match Needle::parse(trimmed) {
-                Ok(parsed_needles) => needles.extend(parsed_needles),
-                Err(err) => {
-                    Error::raw(ErrorKind::InvalidValue, format!("{err}\n"))
-                        .exit();
-                }
+        };
+        let mut needles = match Needle::parse_list(&list) {
+            Ok(needles) => needles,
+            Err(errors) => {
+                // Report all bad needles at once, not just the first. Clap
+                // prefixes the first line with "error: "; do the others.
+                let msgs: Vec<String> =
+                    errors.iter().map(|err| err.to_string()).collect();
+                let msg = format!("{}\n", msgs.join("\nerror: "));
+                Error::raw(ErrorKind::InvalidValue, msg).exit();
             }
-        }
+        };
 
         if needles.is_empty() {
             // Default to 'any'.
@@ -511,6 +2189,191 @@ impl From<NeedleArg> for Vec<Needle> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::diagnostics::Collect;
+    use crate::files::make_test_dir;
+
+    fn parse_args(args: &[&str]) -> Args {
+        <Args as Parser>::try_parse_from(
+            std::iter::once("ipgrep").chain(args.iter().copied()),
+        )
+        .expect("valid args")
+    }
+
+    #[test]
+    fn test_accept_default() {
+        let acc: AcceptSet = parse_args(&[]).accept.into();
+        assert!(acc.ip && acc.net && !acc.oldnet && acc.iface);
+    }
+
+    #[test]
+    fn test_accept_repeated_and_comma_joined() {
+        for args in [
+            &["-a", "ip", "-a", "oldnet"][..],
+            &["-a", "ip,oldnet"][..],
+            &["-aip,o", "-a", "ip"][..],
+        ] {
+            let acc: AcceptSet = parse_args(args).accept.into();
+            assert!(acc.ip && !acc.net && acc.oldnet && !acc.iface, "{args:?}");
+        }
+    }
+
+    #[test]
+    fn test_accept_union_across_sources() {
+        // E.g. "-a ip" on the command line and "-a net" from elsewhere.
+        let cli: AcceptSet = parse_args(&["-a", "ip"]).accept.into();
+        let other: AcceptSet = vec![AcceptSetArg::Net].into();
+        let acc = cli.union(other);
+        assert!(acc.ip && acc.net && !acc.oldnet && !acc.iface);
+        assert!(AcceptSet::default().is_empty());
+        assert!(!acc.is_empty());
+    }
+
+    #[test]
+    fn test_classful() {
+        let params = parse_args(&["--classful", "10.5.6.7,!10.5.0.0/16,::1"])
+            .into_parameters();
+        let nets = |needles: &[Needle]| {
+            needles
+                .iter()
+                .map(|n| n.net.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(nets(&params.positive_needles), ["10.0.0.0/8", "::1/128"]);
+        assert_eq!(nets(&params.negative_needles), ["10.5.0.0/16"]);
+        let mut needles = Needle::parse_list("10.5.6.7,10.0.0.0/8").unwrap();
+        let diagnostics = Collect::default();
+        widen_to_classful(&mut needles, &diagnostics);
+        assert_eq!(
+            diagnostics.take(),
+            ["10.5.6.7: taken as classful network 10.0.0.0/8"]
+        );
+        assert!(
+            Args::try_parse_from([
+                "ipgrep",
+                "--classful",
+                "--hosts-only",
+                "ip"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_hosts_only() {
+        let params =
+            parse_args(&["--hosts-only", "10.0.0.1,::1"]).into_parameters();
+        assert!(params.accept.ip && !params.accept.net && !params.accept.iface);
+        assert_eq!(params.positive_needles.len(), 2);
+        assert!(
+            Args::try_parse_from(["ipgrep", "--hosts-only", "-a", "net", "ip"])
+                .is_err()
+        );
+        let needles = Needle::parse_list("10.0.0.1,10.0.0.0/8").unwrap();
+        assert!(needles[0].require_host().is_ok());
+        assert_eq!(
+            needles[1].require_host().unwrap_err().to_string(),
+            "needle is not a single host address: 10.0.0.0/8"
+        );
+    }
+
+    #[test]
+    fn test_deref() {
+        for (args, recursive, follow_arg_symlinks) in [
+            (&["-r"][..], RecurseHaystacks::FollowDirectories, true),
+            (&["-R"][..], RecurseHaystacks::FollowDirectorySymlinks, true),
+            (
+                &["-r", "--deref=none"][..],
+                RecurseHaystacks::FollowDirectories,
+                false,
+            ),
+            (
+                &["-r", "--deref=args"][..],
+                RecurseHaystacks::FollowDirectories,
+                true,
+            ),
+            (
+                &["-r", "--deref=all"][..],
+                RecurseHaystacks::FollowDirectorySymlinks,
+                true,
+            ),
+            (&["--deref=none"][..], RecurseHaystacks::No, false),
+        ] {
+            let params = parse_args(args).into_parameters();
+            assert_eq!(params.recursive, recursive, "{args:?}");
+            assert_eq!(
+                params.follow_arg_symlinks, follow_arg_symlinks,
+                "{args:?}"
+            );
+        }
+        assert!(
+            <Args as Parser>::try_parse_from(["ipgrep", "-R", "--deref=none"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_read_until_separator() {
+        let mut input = io::Cursor::new(
+            b"10.0.0.0/8\n192.168.0.0/16,::1\r\n---\nhaystack 10.1.2.3\n---\n"
+                .to_vec(),
+        );
+        let needles = read_until_separator(&mut input, "---").unwrap();
+        assert_eq!(needles.as_deref(), Some("10.0.0.0/8,192.168.0.0/16,::1"));
+        let mut rest = String::new();
+        input.read_line(&mut rest).unwrap();
+        assert_eq!(rest, "haystack 10.1.2.3\n");
+
+        let mut input = io::Cursor::new(b"10.0.0.0/8\n--\nx\n".to_vec());
+        assert_eq!(read_until_separator(&mut input, "---").unwrap(), None);
+        let mut input = io::Cursor::new(b"10.0.0.0/8\n--\nx\n".to_vec());
+        assert_eq!(
+            read_until_separator(&mut input, "--").unwrap().as_deref(),
+            Some("10.0.0.0/8")
+        );
+    }
+
+    #[test]
+    fn test_count_ratio_conflicts() {
+        assert!(parse_args(&["-c", "--count-ratio"]).count_ratio);
+        assert!(
+            <Args as Parser>::try_parse_from([
+                "ipgrep",
+                "-c",
+                "--count-ratio",
+                "--count-matches",
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_stdin_split_conflicts() {
+        assert!(parse_args(&["--stdin-split"]).stdin_split);
+        for args in [
+            &["ipgrep", "--stdin-split", "10.0.0.0/8"][..],
+            &["ipgrep", "--stdin-split", "--", "10.0.0.0/8", "file"][..],
+        ] {
+            assert!(<Args as Parser>::try_parse_from(args).is_err());
+        }
+    }
+
+    #[test]
+    fn test_match_mode_aliases() {
+        for (arg, expected) in [
+            ("contains", "contains"),
+            ("c", "contains"),
+            ("supernet-of", "contains"),
+            ("within", "within"),
+            ("w", "within"),
+            ("subnet-of", "within"),
+            ("e", "equals"),
+            ("o", "overlaps"),
+        ] {
+            let args = parse_args(&["-m", arg]);
+            let match_mode = args.match_mode.resolve(&[]);
+            assert_eq!(match_mode.to_string(), expected, "-m {arg}");
+        }
+    }
 
     #[test]
     fn test_match_mode_auto_contains_because_no_needles() {
@@ -565,4 +2428,282 @@ mod tests {
         let match_mode = MatchModeArg::Auto.resolve(&needles);
         assert!(matches!(match_mode, MatchMode::Within));
     }
+
+    #[test]
+    fn test_needle_separators() {
+        let srcs = |list: &str| -> Vec<String> {
+            let needles: Vec<Needle> = NeedleArg(list.into()).into();
+            needles.into_iter().map(|n| n.src).collect()
+        };
+        assert_eq!(srcs("10.0.0.0/8,"), ["10.0.0.0/8"]);
+        assert_eq!(srcs("10.0.0.1;::1"), ["10.0.0.1", "::1"]);
+        assert_eq!(srcs("10.0.0.1,,::1"), ["10.0.0.1", "::1"]);
+        assert_eq!(
+            srcs("10.0.0.1\r\n::1; 10.0.0.0/8 ,\n"),
+            ["10.0.0.1", "::1", "10.0.0.0/8"]
+        );
+    }
+
+    #[test]
+    fn test_expand_braces() {
+        let srcs = |list: &str| -> Vec<String> {
+            let needles: Vec<Needle> = NeedleArg(list.into()).into();
+            needles
+                .into_iter()
+                .map(|n| match n.is_negated {
+                    true => format!("!{}", n.src),
+                    false => n.src,
+                })
+                .collect()
+        };
+        assert_eq!(srcs("10.0.{1,2}.0/24"), srcs("10.0.1.0/24,10.0.2.0/24"));
+        assert_eq!(
+            srcs("::1,!10.{1,2}.0.0/16;{1,2}.0.0.{3,4}"),
+            [
+                "::1",
+                "!10.1.0.0/16",
+                "!10.2.0.0/16",
+                "1.0.0.3",
+                "1.0.0.4",
+                "2.0.0.3",
+                "2.0.0.4"
+            ]
+        );
+        assert_eq!(expand_braces("ip4, 10.0.0.1"), Ok("ip4, 10.0.0.1".into()));
+        for bad in ["10.{1,{2,3}}.0.0", "10.0.{1,2.0/24", "10.0.1}.0/24"] {
+            assert!(expand_braces(bad).is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn test_match_color() {
+        assert_eq!(
+            parse_args(&["--match-color=1;32"]).match_color.unwrap(),
+            "1;32"
+        );
+        assert_eq!(
+            parse_args(&["--match-color=none"]).match_color.unwrap(),
+            ""
+        );
+        for bad in ["", "red", "1;32m"] {
+            let args = ["ipgrep", "--match-color", bad];
+            assert!(<Args as Parser>::try_parse_from(args).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_parse_port_range() {
+        assert_eq!(parse_port_range("22"), Ok(22..=22));
+        assert_eq!(parse_port_range("1024-65535"), Ok(1024..=65535));
+        assert!(parse_port_range("65536").is_err());
+        assert!(parse_port_range("443-80").is_err());
+        assert!(parse_port_range("ssh").is_err());
+        let ports = parse_args(&["--port=22,80-81", "ip"]).ports;
+        assert_eq!(ports, [22..=22, 80..=81]);
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1000"), Ok(1000));
+        assert_eq!(parse_size("10M"), Ok(10 << 20));
+        assert_eq!(parse_size("2g"), Ok(2 << 30));
+        for garbage in ["", "M", "-1K", "1.5G", "10MB", "1 K", "99999999T"] {
+            assert!(parse_size(garbage).is_err(), "{garbage:?}");
+        }
+        let args = parse_args(&["--max-filesize=500K"]);
+        assert_eq!(args.max_filesize, Some(500 << 10));
+    }
+
+    #[test]
+    fn test_needle_sep() {
+        let srcs = |params: &Parameters| -> Vec<String> {
+            params
+                .positive_needles
+                .iter()
+                .map(|n| n.src.clone())
+                .collect()
+        };
+        // Newlines, like from a $(...) capture, always separate.
+        let params =
+            parse_args(&["10.0.0.0/8\n2001:db8::/32"]).into_parameters();
+        assert_eq!(srcs(&params), ["10.0.0.0/8", "2001:db8::/32"]);
+
+        let params = parse_args(&[
+            "--needle-sep=|",
+            "--needle-sep=\t",
+            "::1|10.0.0.1\t\t",
+        ])
+        .into_parameters();
+        assert_eq!(srcs(&params), ["::1", "10.0.0.1"]);
+        let params = parse_args(&[
+            "--needle-sep",
+            "|",
+            "10.0.{1,2}.0/24||::1",
+            "--not",
+            "10.0.1.1|10.0.2.1",
+        ])
+        .into_parameters();
+        assert_eq!(srcs(&params), ["10.0.1.0/24", "10.0.2.0/24", "::1"]);
+        assert_eq!(params.negative_needles.len(), 2);
+
+        for garbage in ["", "ab", "a", "0", ".", ":", "/", "!", "-", "{"] {
+            assert!(parse_needle_sep(garbage).is_err(), "{garbage:?}");
+        }
+    }
+
+    #[test]
+    fn test_parse_nth() {
+        assert_eq!(parse_nth("first"), Ok(1));
+        assert_eq!(parse_nth("last"), Ok(-1));
+        assert_eq!(parse_nth("3"), Ok(3));
+        assert_eq!(parse_nth("-2"), Ok(-2));
+        for garbage in ["0", "", "second", "1.5"] {
+            assert!(parse_nth(garbage).is_err(), "{garbage:?}");
+        }
+        assert_eq!(parse_args(&["--nth", "-1"]).nth, Some(-1));
+    }
+
+    #[test]
+    fn test_mmdb_args() {
+        assert_eq!(parse_asn("1136"), Ok(1136));
+        assert_eq!(parse_asn("AS15169"), Ok(15169));
+        assert!(parse_asn("ASX").is_err());
+        let args =
+            parse_args(&["--mmdb=a.mmdb", "--country=nl,BE", "--asn=AS1,2"]);
+        assert_eq!(args.mmdb.as_deref(), Some("a.mmdb"));
+        assert_eq!(args.countries, ["nl", "BE"]);
+        assert_eq!(args.asns, [1, 2]);
+        // The filters need a database.
+        for arg in ["--country=NL", "--asn=1"] {
+            assert!(<Args as Parser>::try_parse_from(["ipgrep", arg]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_needle_files() {
+        let dir = make_test_dir("needle-files");
+        let a = dir.join("a.txt").display().to_string();
+        let b = dir.join("b.txt").display().to_string();
+        fs::write(&a, "# blocklist\n10.0.0.0/8\n\n!10.2.0.0/16\n").unwrap();
+        fs::write(&b, "192.168.0.0/16 # office\n").unwrap();
+
+        let params = parse_args(&["-f", &a, "--file", &b, "log1", "log2"])
+            .into_parameters();
+        let srcs: Vec<&str> = params
+            .positive_needles
+            .iter()
+            .map(|n| n.src.as_str())
+            .collect();
+        assert_eq!(srcs, ["10.0.0.0/8", "192.168.0.0/16"]);
+        assert_eq!(params.negative_needles.len(), 1);
+        assert_eq!(params.haystack_filenames, ["log1", "log2"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_builtin() {
+        let params =
+            parse_args(&["--builtin", "rfc1918", "--builtin=Loopback", "log"])
+                .into_parameters();
+        let srcs: Vec<&str> = params
+            .positive_needles
+            .iter()
+            .map(|n| n.src.as_str())
+            .collect();
+        assert_eq!(
+            srcs,
+            [
+                "10.0.0.0/8",
+                "172.16.0.0/12",
+                "192.168.0.0/16",
+                "127.0.0.0/8",
+                "::1/128"
+            ]
+        );
+        assert_eq!(params.haystack_filenames, ["log"]);
+
+        // Together with --not.
+        let params =
+            parse_args(&["--builtin", "rfc1918", "--not", "10.1.0.0/16"])
+                .into_parameters();
+        assert_eq!(params.positive_needles.len(), 3);
+        assert_eq!(params.negative_needles.len(), 1);
+
+        assert!(
+            Args::try_parse_from(["ipgrep", "--builtin", "global"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_not() {
+        let params = parse_args(&[
+            "10.0.0.0/8",
+            "--not",
+            "10.1.2.0/24,::1",
+            "--not=!10.1.2.3",
+        ])
+        .into_parameters();
+        let srcs = |needles: &[Needle]| -> Vec<String> {
+            needles.iter().map(|n| n.src.clone()).collect()
+        };
+        assert_eq!(srcs(&params.positive_needles), ["10.0.0.0/8", "10.1.2.3"]);
+        assert_eq!(srcs(&params.negative_needles), ["10.1.2.0/24", "::1"]);
+
+        // Without positive needles, all of the family is implied.
+        let params = parse_args(&["--not", "10.1.2.0/24"]).into_parameters();
+        assert_eq!(srcs(&params.positive_needles), ["0.0.0.0/0", "::/0"]);
+    }
+
+    #[test]
+    fn test_jobs() {
+        let threads =
+            |args: &[&str]| parse_args(args).into_parameters().threads;
+        assert_eq!(threads(&["-j3"]), 3);
+        assert_eq!(threads(&["--threads=2"]), 2);
+        assert_eq!(
+            threads(&[]),
+            thread::available_parallelism().map_or(1, |n| n.get())
+        );
+        assert_eq!(threads(&["--unique"]), 1);
+        assert_eq!(threads(&["--unique-prefix=24", "-j1"]), 1);
+    }
+
+    #[test]
+    fn test_color() {
+        for (args, expected) in [
+            (&[][..], ShowColor::Auto),
+            (&["--color"][..], ShowColor::Auto),
+            (&["--color=always"][..], ShowColor::Always),
+            (&["--colour=never"][..], ShowColor::Never),
+        ] {
+            let params = parse_args(args).into_parameters();
+            assert_eq!(params.color, expected, "{args:?}");
+        }
+        // Without "=", the next argument is not taken as WHEN.
+        let args = parse_args(&["--color", "never"]);
+        assert_eq!(args.needles.unwrap().0, "never");
+    }
+
+    #[test]
+    fn test_strip_ansi_byte_offset() {
+        // The byte offsets of the matches would be those in the stripped
+        // lines, not in the file.
+        for args in [
+            &["--strip-ansi", "-b", "ip"],
+            &["-ob", "--strip-ansi", "ip"],
+        ] {
+            let args = std::iter::once("ipgrep").chain(args.iter().copied());
+            assert!(<Args as Parser>::try_parse_from(args).is_err());
+        }
+    }
+
+    #[test]
+    fn test_quiet_diagnostics() {
+        let diagnostics = |args: &[&str]| {
+            format!("{:?}", parse_args(args).into_parameters().diagnostics)
+        };
+        assert_eq!(diagnostics(&[]), "Stderr");
+        assert_eq!(diagnostics(&["-q"]), "Silent");
+    }
 }
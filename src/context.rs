@@ -56,6 +56,11 @@ impl<T> ContextBuffer<T> {
         first_c_lineno > self.last_printed_lineno + 1
     }
 
+    /// Returns true if anything was printed (in this file) yet.
+    pub fn has_printed(&self) -> bool {
+        self.last_printed_lineno != 0
+    }
+
     /// Get the before-lines.
     pub fn get_before_lines(&self) -> &VecDeque<(usize, Vec<u8>, T)> {
         &self.queue
@@ -96,6 +101,11 @@ impl<T> ContextBuffer<T> {
         true
     }
 
+    /// Are there after-lines left to print?
+    pub fn is_after_pending(&self) -> bool {
+        self.after_remaining != 0
+    }
+
     /// Record what line we're at. Call this when printing lines or
     /// after-lines.
     pub fn update_last_printed(&mut self, lineno: usize) {
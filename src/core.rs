@@ -1,35 +1,41 @@
-use std::io::{self, BufRead, BufWriter, IsTerminal, LineWriter, Write};
-use std::process::ExitCode;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::env;
+use std::io::{self, BufRead, BufWriter, IsTerminal, LineWriter, Read, Write};
+use std::net::IpAddr;
+use std::process::{Command, ExitCode};
+use std::sync::{Arc, Condvar, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use memchr::memmem;
 
 use crate::context::ContextBuffer;
+use crate::diagnostics::Silent;
 use crate::files;
-use crate::output::{Display, OutputStyle};
+use crate::hll::HyperLogLog;
+use crate::matching::{AcceptSet, InterfaceMode, MatchMode};
+use crate::needle::Needle;
+use crate::net::Net;
+use crate::output::{Colors, Display, LinePos, OutputStyle, ShowColor};
 use crate::params;
+use crate::params::BinaryFiles;
+use crate::resolve::Resolver;
 use crate::scanner;
 
 /// Entry point for the application, called from main().
 pub fn run(params: &params::Parameters) -> io::Result<ExitCode> {
-    let file_iter = if params.haystack_filenames.is_empty() {
-        files::FileSourceIter::new().add_stdin()
-    } else {
-        files::FileSourceIter::new()
-            .set_recursion(params.recursive)
-            .add_files(&params.haystack_filenames)
-    };
-
-    // GNU grep 3 compatibility:
-    // - by default, no filename is shown;
-    // - for more than one file (including recursion), we show;
-    // - unless it is explicitly hidden.
-    let show_filename = if params.hide_filename {
-        false
-    } else {
-        file_iter.has_more_than_one_file()
-    };
+    let file_iter = make_file_iter(params);
 
     let stdout = io::stdout();
     let isatty = stdout.is_terminal();
-    let with_color = isatty; // or colorchoice::ColorChoice::global() == ???
+    let with_color = match params.color {
+        ShowColor::Always => true,
+        ShowColor::Never => false,
+        // https://no-color.org/: set and not empty disables colors.
+        ShowColor::Auto => {
+            isatty && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+        }
+    };
 
     // Line-buffered or not.
     let mut writer: Box<dyn Write> = if params.line_buffered || isatty {
@@ -38,244 +44,3366 @@ pub fn run(params: &params::Parameters) -> io::Result<ExitCode> {
         Box::new(BufWriter::new(stdout.lock()))
     };
 
-    // Create scanner that knows what to expect.
-    let netcandidatescanner = scanner::NetCandidateScanner::new()
-        .ignore_ipv4(params.positive_needles.iter().all(|n| !n.net.is_ipv4()))
-        .ignore_ipv6(params.positive_needles.iter().all(|n| !n.net.is_ipv6()))
-        .set_accept(params.accept)
-        .set_interface_mode(params.interface_mode);
+    let match_count = run_with(params, file_iter, with_color, &mut writer)?;
+    finish(params, match_count, &mut writer, &mut io::stderr().lock())?;
+
+    let exit = match match_count != 0 {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::from(1),
+    };
+
+    Ok(exit)
+}
+
+/// Flush the output and write the --count-to-stderr total to
+/// err_writer. The flush comes first, so that on a terminal the total
+/// is not printed before the last matches.
+fn finish(
+    params: &params::Parameters,
+    match_count: usize,
+    writer: &mut dyn Write,
+    err_writer: &mut dyn Write,
+) -> io::Result<()> {
+    // Flush, just in case.
+    writer.flush().ok();
+
+    // The total on stderr, keeping stdout clean for a pipeline.
+    if params.count_to_stderr {
+        writeln!(err_writer, "{match_count}")?;
+    }
+    Ok(())
+}
+
+/// The files to search: the haystacks, or stdin if there are none.
+fn make_file_iter(params: &params::Parameters) -> files::FileSourceIter {
+    if params.haystack_filenames.is_empty() {
+        let file_iter = files::FileSourceIter::new();
+        match &params.label {
+            Some(label) => file_iter.set_stdin_label(label).add_stdin(),
+            None => file_iter.add_stdin(),
+        }
+    } else {
+        files::FileSourceIter::new()
+            .set_recursion(params.recursive)
+            .set_follow_arg_symlinks(params.follow_arg_symlinks)
+            .set_globs(&params.include_globs, &params.exclude_globs)
+            .set_devices(params.devices)
+            .set_max_filesize(params.max_filesize)
+            // Like the messages about unreadable files, -s suppresses
+            // the warnings about skipped files.
+            .set_diagnostics(match params.no_messages {
+                true => Arc::new(Silent),
+                false => params.diagnostics.clone(),
+            })
+            .add_files(&params.haystack_filenames)
+    }
+}
+
+/// Search the files and print the results to writer, like run() does
+/// to stdout. Returns the number of matches.
+fn run_with(
+    params: &params::Parameters,
+    file_iter: files::FileSourceIter,
+    with_color: bool,
+    writer: &mut dyn Write,
+) -> io::Result<usize> {
+    let show_filename = show_filename(params, &file_iter);
 
     // Create display that knows how to output.
-    let disp = Display::new()
+    let mut disp = Display::new()
         .show_filename(show_filename)
+        .heading(params.heading && show_filename)
         .show_lineno(params.show_lineno)
-        .show_color(with_color);
+        .show_byte_offset(params.show_byte_offset)
+        .show_color(with_color)
+        .csv(params.csv)
+        .print0(params.print0)
+        .canonical(params.canonical);
+    if with_color && let Ok(grep_colors) = env::var("GREP_COLORS") {
+        disp = disp.colors(Colors::default().with_grep_colors(&grep_colors));
+    }
+    if let Some(sgr) = &params.match_color {
+        disp = disp.match_color(sgr);
+    }
+
+    search_files(params, file_iter, &disp, writer)
+}
 
-    let mut any_match = false;
+/// Whether to prefix the output with filenames.
+fn show_filename(
+    params: &params::Parameters,
+    file_iter: &files::FileSourceIter,
+) -> bool {
+    // GNU grep 3 compatibility:
+    // - by default, no filename is shown;
+    // - for more than one file (including recursion), we show;
+    // - unless it is explicitly hidden or shown.
+    if params.hide_filename {
+        false
+    } else if params.force_filename {
+        true
+    } else {
+        file_iter.has_more_than_one_file()
+    }
+}
 
-    for file_res in file_iter {
-        let mut file = match file_res {
-            Ok(o) => o,
-            Err(e) => {
-                eprintln!("ipgrep: {e}");
-                continue;
+/// Search all files and print the results. Returns the number of
+/// matches (0 if nothing matched).
+fn search_files(
+    params: &params::Parameters,
+    file_iter: files::FileSourceIter,
+    disp: &Display,
+    writer: &mut dyn Write,
+) -> io::Result<usize> {
+    // For --stats.
+    let started = Instant::now();
+
+    // Create scanner that knows what to expect.
+    let netcandidatescanner = make_scanner(params);
+    let matcher = NeedleMatcher::new(params);
+
+    let mut collector = Collector::new(params);
+
+    // The CSV header, once. Not if -c is overruled by -l or -q.
+    if params.csv && params.csv_header {
+        let columns: &[&str] = match params.output_style {
+            OutputStyle::ShowCountsPerNeedle => &["needle", "count"],
+            OutputStyle::ShowCountsPerFile if params.count_total => &["count"],
+            OutputStyle::ShowCountsPerFile if params.count_ratio => {
+                &["path", "count", "lines"]
             }
+            OutputStyle::ShowCountsPerFile => &["path", "count"],
+            _ => &[],
         };
+        if !columns.is_empty() {
+            disp.print_csv_header(writer, columns)?;
+        }
+    }
 
-        let match_count = search_in_file(
-            &disp,
-            &mut file,
+    let total_count = if use_threads(params, &file_iter) {
+        search_files_parallel(
+            file_iter,
+            disp,
             &netcandidatescanner,
+            &matcher,
             params,
-            &mut writer,
-        )?;
+            &mut collector,
+            writer,
+        )?
+    } else {
+        let mut total_count = 0;
+        for file_res in file_iter {
+            let mut file = match file_res {
+                Ok(o) => o,
+                Err(e) => {
+                    if !params.no_messages {
+                        eprintln!("ipgrep: {e}");
+                    }
+                    continue;
+                }
+            };
 
-        any_match = any_match || (match_count != 0);
+            let match_count = search_file(
+                disp,
+                &mut file,
+                &netcandidatescanner,
+                &matcher,
+                params,
+                &mut collector,
+                writer,
+            )?;
 
-        match params.output_style {
-            OutputStyle::JustExitCode => {
-                if match_count != 0 {
-                    break;
-                }
-            }
-            OutputStyle::ShowFilesWithLf => {
-                if match_count != 0 {
-                    disp.print_filename(&mut writer, &file.name, b"\n")?;
-                }
-            }
-            OutputStyle::ShowFilesWithNull => {
-                if match_count != 0 {
-                    disp.print_filename(&mut writer, &file.name, b"\0")?;
-                }
-            }
-            OutputStyle::ShowCountsPerFile => {
-                disp.print_counts(&mut writer, &file.name, match_count)?;
+            total_count += match_count;
+
+            if params.output_style == OutputStyle::JustExitCode
+                && total_count != 0
+            {
+                break;
             }
-            OutputStyle::ShowOnlyMatching => {}
-            OutputStyle::ShowLinesAndContext => {}
         }
+        total_count
+    };
+
+    print_collected(disp, &collector, params, writer)?;
+
+    if params.output_style == OutputStyle::ShowCountsPerFile
+        && params.count_total
+    {
+        disp.print_total(writer, total_count)?;
     }
 
-    let exit = match any_match {
-        true => ExitCode::SUCCESS,
-        false => ExitCode::from(1),
-    };
+    if !params.exec_command.is_empty() {
+        // Our output first, then that of the commands.
+        writer.flush()?;
+        run_exec_commands(params, &collector.exec_nets);
+    }
 
-    // Flush, just in case.
-    writer.flush().ok();
+    if params.stats {
+        eprintln!("ipgrep: {}", collector.stats.summary(started.elapsed()));
+    }
 
-    Ok(exit)
+    Ok(total_count)
 }
 
-fn search_in_file(
+/// Whether lines are printed with context lines (and "--" delimiters).
+fn uses_context(params: &params::Parameters) -> bool {
+    params.output_style == OutputStyle::ShowLinesAndContext
+        && (params.show_context.before > 0 || params.show_context.after > 0)
+}
+
+/// Whether to search the files on params.threads threads. Those write
+/// nothing for a file until it has been searched completely, so stdin
+/// (think tail -f), a single file and --line-buffered output are
+/// searched on this thread, as they are read.
+fn use_threads(
+    params: &params::Parameters,
+    file_iter: &files::FileSourceIter,
+) -> bool {
+    params.threads > 1
+        && !params.line_buffered
+        && file_iter.has_more_than_one_file()
+}
+
+/// The files handed out to the search_files_parallel() workers.
+struct FileQueue {
+    files: files::FileSourceIter,
+    // Index of the next file to hand out.
+    next: usize,
+    // Number of files whose results have been printed.
+    printed: usize,
+    stop: bool,
+}
+
+/// The result for a single file, from a search_files_parallel() worker.
+enum FileOutcome {
+    Unreadable(String),
+    Searched(io::Result<(Vec<u8>, usize)>),
+}
+
+/// Search the files like search_files() does, but on params.threads
+/// threads. Every file is rendered to a buffer of its own, and the
+/// buffers are printed in the original file order. Workers may only run
+/// a limited number of files ahead of the printed ones, so a slow file
+/// does not make us buffer the output of all the others.
+fn search_files_parallel(
+    file_iter: files::FileSourceIter,
     disp: &Display,
-    file: &mut files::FileSource,
     netcandidatescanner: &scanner::NetCandidateScanner,
+    matcher: &NeedleMatcher,
     params: &params::Parameters,
+    collector: &mut Collector,
     writer: &mut dyn Write,
 ) -> io::Result<usize> {
-    let mut line = Vec::new();
-    let mut lineno = 0;
-
-    let mut matches = Vec::new();
-    let mut match_count: usize = 0;
+    let max_ahead = 2 * params.threads;
+    let queue = Mutex::new(FileQueue {
+        files: file_iter,
+        next: 0,
+        printed: 0,
+        stop: false,
+    });
+    let queue_changed = Condvar::new();
+    let (tx, rx) = mpsc::channel::<(usize, FileOutcome)>();
 
-    let mut context =
-        ContextBuffer::<Vec<scanner::NetCandidate>>::from_show_context(
-            &params.show_context,
-        );
-
-    loop {
-        // TODO: This could use some test case. But it looks like it
-        // works, even including files without trailing newlines.
-        let _n = match file.reader.read_until(b'\n', &mut line) {
-            Ok(0) => break,
-            Ok(n) => n,
-            Err(e) => {
-                eprintln!("ipgrep: {}: {} (skipping)", file.name, e);
-                break;
-            }
-        };
-        lineno += 1;
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..params.threads)
+            .map(|_| {
+                let tx = tx.clone();
+                let (queue, queue_changed) = (&queue, &queue_changed);
+                scope.spawn(move || {
+                    let mut collector = Collector::new(params);
+                    loop {
+                        let (idx, file_res) = {
+                            let mut q = queue.lock().expect("poisoned");
+                            while !q.stop && q.next >= q.printed + max_ahead {
+                                q = queue_changed.wait(q).expect("poisoned");
+                            }
+                            if q.stop {
+                                break;
+                            }
+                            let Some(file_res) = q.files.next() else {
+                                break;
+                            };
+                            q.next += 1;
+                            (q.next - 1, file_res)
+                        };
+                        let outcome = match file_res {
+                            Ok(mut file) => {
+                                // The "--" between files is added when
+                                // printing, below.
+                                collector.context_printed = false;
+                                let mut output = Vec::new();
+                                FileOutcome::Searched(
+                                    search_file(
+                                        disp,
+                                        &mut file,
+                                        netcandidatescanner,
+                                        matcher,
+                                        params,
+                                        &mut collector,
+                                        &mut output,
+                                    )
+                                    .map(|match_count| (output, match_count)),
+                                )
+                            }
+                            Err(e) => FileOutcome::Unreadable(e),
+                        };
+                        if tx.send((idx, outcome)).is_err() {
+                            break;
+                        }
+                    }
+                    collector
+                })
+            })
+            .collect();
+        drop(tx);
 
-        // Check all possible candidates on the line.
-        for candidate in netcandidatescanner.find_all(&line, &file.name) {
-            // Check them for negative match first.
-            let mut matches_negative = false;
-            for needle in &params.negative_needles {
-                if params.match_mode.matches(&candidate.net, &needle.net) {
-                    matches_negative = true;
-                    break;
+        // Print the outcomes in order, using a reorder buffer.
+        let mut print_in_order = || -> io::Result<usize> {
+            let mut pending = BTreeMap::new();
+            let mut printed = 0;
+            let mut total_count = 0;
+            let mut context_printed = false;
+            for (idx, outcome) in &rx {
+                pending.insert(idx, outcome);
+                while let Some(outcome) = pending.remove(&printed) {
+                    printed += 1;
+                    match outcome {
+                        FileOutcome::Unreadable(e) => {
+                            if !params.no_messages {
+                                eprintln!("ipgrep: {e}");
+                            }
+                        }
+                        FileOutcome::Searched(res) => {
+                            let (output, match_count) = res?;
+                            if uses_context(params) && !output.is_empty() {
+                                if context_printed && !disp.is_heading() {
+                                    disp.print_context_delimiter(
+                                        writer, "", 0,
+                                    )?;
+                                }
+                                context_printed = true;
+                            }
+                            writer.write_all(&output)?;
+                            total_count += match_count;
+                        }
+                    }
+                    if params.output_style == OutputStyle::JustExitCode
+                        && total_count != 0
+                    {
+                        return Ok(total_count);
+                    }
                 }
+                queue.lock().expect("poisoned").printed = printed;
+                queue_changed.notify_all();
             }
-            if matches_negative {
-                // Candidate rejected by a !needle.
-                continue;
-            }
+            Ok(total_count)
+        };
+        let res = print_in_order();
 
-            // Check them for positive match.
-            let mut matches_positive = false;
-            for needle in &params.positive_needles {
-                if params.match_mode.matches(&candidate.net, &needle.net) {
-                    matches_positive = true;
-                    break;
-                }
-            }
-            if matches_positive {
-                // Candidate confirmed.
-                matches.push(candidate);
-            }
+        // Stop the workers, in case we returned early.
+        queue.lock().expect("poisoned").stop = true;
+        queue_changed.notify_all();
+        for worker in workers {
+            collector.merge(worker.join().expect("worker panicked"));
         }
+        res
+    })
+}
 
-        // Mirroring GNU grep, we simply invert the lines we show. This
-        // also means we now also store matches (for highlighting) in the
-        // context buffer.
-        let is_line_selected = if params.invert_match {
-            matches.is_empty()
-        } else {
-            !matches.is_empty()
+/// Search a single file (its contents, and its path for --match-paths)
+/// and print the per-file results. Returns the number of matches, or
+/// for -L whether the file was listed.
+fn search_file(
+    disp: &Display,
+    file: &mut files::FileSource,
+    netcandidatescanner: &scanner::NetCandidateScanner,
+    matcher: &NeedleMatcher,
+    params: &params::Parameters,
+    collector: &mut Collector,
+    writer: &mut dyn Write,
+) -> io::Result<usize> {
+    // With --heading, the filename goes above the first output line.
+    let mut heading_writer;
+    let writer: &mut dyn Write = if disp.is_heading()
+        && matches!(
+            params.output_style,
+            OutputStyle::ShowLinesAndContext | OutputStyle::ShowOnlyMatching
+        ) {
+        heading_writer = HeadingWriter {
+            disp,
+            filename: file.name.clone(),
+            inner: writer,
+            pending: true,
         };
+        &mut heading_writer
+    } else {
+        writer
+    };
 
-        if !is_line_selected {
-            if context.is_used() {
-                if context.should_print_after_line() {
-                    assert_eq!(
-                        params.output_style,
-                        OutputStyle::ShowLinesAndContext
-                    );
-                    disp.print_context(
-                        writer, &file.name, lineno, &line, &matches,
-                    )?;
-                    context.update_last_printed(lineno);
-                } else {
-                    context.push_before_line(
-                        lineno,
-                        &line,
-                        // We don't need the matches, let the buffer have them.
-                        std::mem::take(&mut matches),
-                    );
-                }
+    let path_count = if params.match_paths {
+        search_in_path(
+            disp,
+            file,
+            netcandidatescanner,
+            matcher,
+            params,
+            writer,
+        )?
+    } else {
+        0
+    };
+
+    let counts = search_in_file(
+        disp,
+        file,
+        netcandidatescanner,
+        matcher,
+        params,
+        collector,
+        writer,
+    )?;
+    let match_count = path_count + counts.matches;
+    collector.stats.add(&counts, match_count);
+
+    match params.output_style {
+        OutputStyle::JustExitCode => {}
+        OutputStyle::ShowFilesWithLf => {
+            if match_count != 0 {
+                disp.print_filename(writer, &file.name, b"\n")?;
             }
-        } else {
-            match_count += if params.invert_match {
-                1
+        }
+        OutputStyle::ShowFilesWithNull => {
+            if match_count != 0 {
+                disp.print_filename(writer, &file.name, b"\0")?;
+            }
+        }
+        OutputStyle::ShowFilesWithoutMatchLf => {
+            if match_count == 0 {
+                disp.print_filename(writer, &file.name, b"\n")?;
+            }
+        }
+        OutputStyle::ShowFilesWithoutMatchNull => {
+            if match_count == 0 {
+                disp.print_filename(writer, &file.name, b"\0")?;
+            }
+        }
+        OutputStyle::ShowCountsPerFile => {
+            if params.count_total {
+                // Printed once, by search_files().
+            } else if params.split_kind {
+                disp.print_kind_counts(writer, &file.name, counts.kinds)?;
+            } else if params.count_ratio {
+                disp.print_count_ratio(
+                    writer,
+                    &file.name,
+                    match_count,
+                    counts.lines,
+                )?;
             } else {
-                matches.len()
-            };
+                disp.print_counts(writer, &file.name, match_count)?;
+            }
+        }
+        OutputStyle::ShowCountsPerNeedle => {}
+        OutputStyle::ShowApproxUnique => {}
+        OutputStyle::ShowComparison => {}
+        OutputStyle::ShowTree => {}
+        OutputStyle::ShowAggregated => {}
+        OutputStyle::ShowJsonMatches => {}
+        OutputStyle::ShowOnlyMatching => {}
+        OutputStyle::ShowLinesAndContext => {}
+    }
 
-            match params.output_style {
-                OutputStyle::JustExitCode
-                | OutputStyle::ShowFilesWithLf
-                | OutputStyle::ShowFilesWithNull => {
-                    // Short circuit. Don't trust the match_count, so
-                    // set it to 1.
-                    match_count = 1;
-                    break;
-                }
-                OutputStyle::ShowCountsPerFile => {}
-                OutputStyle::ShowOnlyMatching => {
-                    // TODO: GNU grep 3 behaviour is kind of peculiar when
-                    // using -o and context. The -C context decides if "--"
-                    // delimiters will be shown or not: for -C3 you'll
-                    // get a delimiter if there is more than three lines
-                    // between the matches.
-                    // Do we want to implement this? Seems like an
-                    // implementation detail, and not something useful.
-                    if let Some(rewrite_prefix) = params.rewrite_output_prefix {
-                        disp.print_network_matches(
-                            writer,
-                            &file.name,
-                            lineno,
-                            rewrite_prefix,
-                            &matches,
-                        )?;
-                    } else {
-                        disp.print_matches(
-                            writer, &file.name, lineno, &line, &matches,
-                        )?;
-                    }
-                }
-                OutputStyle::ShowLinesAndContext => {
-                    if context.is_used() {
-                        // Print delimiter ("--")?
-                        if context.is_new_match_block(lineno) {
-                            disp.print_context_delimiter(
-                                writer,
-                                &file.name,
-                                lineno - 1,
-                            )?;
-                        }
-                        // Print before-lines?
-                        for (c_lineno, c_line, c_matches) in
-                            context.get_before_lines()
-                        {
-                            disp.print_context(
-                                writer, &file.name, *c_lineno, c_line,
-                                c_matches,
-                            )?;
-                        }
-                        context.clear_before_lines();
-                    }
+    match params.output_style {
+        // For -L, a listed file is what counts as success.
+        OutputStyle::ShowFilesWithoutMatchLf
+        | OutputStyle::ShowFilesWithoutMatchNull => {
+            Ok(usize::from(match_count == 0))
+        }
+        _ => Ok(match_count),
+    }
+}
 
-                    // Print the line with the matches.
-                    disp.print_line(
-                        writer, &file.name, lineno, &line, &matches,
-                    )?;
+/// Writes the --heading for a file before its first output, so files
+/// without output get none.
+struct HeadingWriter<'a> {
+    disp: &'a Display,
+    filename: String,
+    inner: &'a mut dyn Write,
+    pending: bool,
+}
 
-                    if context.is_used() {
-                        // Record that we might need to print
-                        // after-lines in the future. And keep track of
-                        // what we printed, for the context-delimiter.
-                        context.request_after();
-                        context.update_last_printed(lineno);
-                    }
-                }
-            }
+impl Write for HeadingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.pending && !buf.is_empty() {
+            self.pending = false;
+            self.disp.print_heading(self.inner, &self.filename)?;
         }
+        self.inner.write(buf)
+    }
 
-        matches.clear();
-        line.clear();
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Counts for a single searched file.
+struct FileCounts {
+    matches: usize,
+    // Lines read; all of them, unless we stopped at the first match.
+    lines: usize,
+    // Bytes read, likewise.
+    bytes: u64,
+    // Matches per MatchKind, for --split-kind.
+    kinds: [usize; 3],
+}
+
+/// Totals over all searched files, for --stats.
+#[derive(Default)]
+struct Stats {
+    files: usize,
+    lines: usize,
+    bytes: u64,
+    matches: usize,
+}
+
+impl Stats {
+    /// Count a searched file, with its matches (including those in its
+    /// path, for --match-paths).
+    fn add(&mut self, counts: &FileCounts, matches: usize) {
+        self.files += 1;
+        self.lines += counts.lines;
+        self.bytes += counts.bytes;
+        self.matches += matches;
     }
 
-    Ok(match_count)
+    fn merge(&mut self, other: &Stats) {
+        self.files += other.files;
+        self.lines += other.lines;
+        self.bytes += other.bytes;
+        self.matches += other.matches;
+    }
+
+    /// The --stats line, without the "ipgrep: " prefix.
+    fn summary(&self, elapsed: Duration) -> String {
+        format!(
+            "stats: {} files, {} lines, {} bytes, {} matches in {:.3}s",
+            self.files,
+            self.lines,
+            self.bytes,
+            self.matches,
+            elapsed.as_secs_f64()
+        )
+    }
+}
+
+/// Matches collected across all files, for the output styles that
+/// print their results at the end of the run.
+struct Collector {
+    // The unique matches, for --tree and --aggregate.
+    matched_nets: BTreeSet<Net>,
+    // Match counts for each of the positive needles.
+    needle_counts: Vec<usize>,
+    // Only allocated for --approx-unique.
+    unique_estimator: Option<HyperLogLog>,
+    // For --compare: matched by A only, by B only, and by both.
+    compared_nets: [BTreeSet<Net>; 3],
+    // The networks printed so far, for --unique/--unique-prefix.
+    unique_nets: HashSet<Net>,
+    // Whether unique_nets reached the --unique-cap.
+    unique_capped: bool,
+    // The -o matches and their output text, for --sort.
+    sorted_matches: Vec<(Net, String)>,
+    // The hostname cache, for --resolve. Not merged: each thread has
+    // its own.
+    resolver: Option<Resolver>,
+    // The matches to run --exec/--exec-batch for.
+    exec_nets: BTreeSet<Net>,
+    // Whether lines with context were printed, so the next file starts
+    // with a "--" delimiter. Not merged: only for sequential printing.
+    context_printed: bool,
+    // Counted for every run; only printed with --stats.
+    stats: Stats,
+}
+
+impl Collector {
+    fn new(params: &params::Parameters) -> Self {
+        Self {
+            matched_nets: BTreeSet::new(),
+            needle_counts: vec![0; params.positive_needles.len()],
+            unique_estimator: (params.output_style
+                == OutputStyle::ShowApproxUnique)
+                .then(HyperLogLog::new),
+            compared_nets: Default::default(),
+            unique_nets: HashSet::new(),
+            unique_capped: false,
+            sorted_matches: Vec::new(),
+            resolver: params
+                .resolve
+                .then(|| Resolver::new(params.diagnostics.clone())),
+            exec_nets: BTreeSet::new(),
+            context_printed: false,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Add the matches collected by another (worker thread) collector.
+    fn merge(&mut self, other: Collector) {
+        self.matched_nets.extend(other.matched_nets);
+        self.exec_nets.extend(other.exec_nets);
+        self.stats.merge(&other.stats);
+        self.sorted_matches.extend(other.sorted_matches);
+        for (nets, other_nets) in
+            self.compared_nets.iter_mut().zip(other.compared_nets)
+        {
+            nets.extend(other_nets);
+        }
+        for (count, other_count) in
+            self.needle_counts.iter_mut().zip(other.needle_counts)
+        {
+            *count += other_count;
+        }
+        if let (Some(estimator), Some(other_estimator)) =
+            (&mut self.unique_estimator, &other.unique_estimator)
+        {
+            estimator.merge(other_estimator);
+        }
+    }
+}
+
+/// Print the results of the output styles that wait for all files.
+fn print_collected(
+    disp: &Display,
+    collector: &Collector,
+    params: &params::Parameters,
+    writer: &mut dyn Write,
+) -> io::Result<()> {
+    match params.output_style {
+        OutputStyle::ShowCountsPerNeedle => {
+            // One row per needle: the networks of an IP range are summed
+            // and shown as the range.
+            let counts: Vec<(&Needle, usize)> = params
+                .positive_needles
+                .iter()
+                .zip(collector.needle_counts.iter().copied())
+                .collect();
+            let rows: Vec<(String, usize)> = counts
+                .chunk_by(|(a, _), (b, _)| a.is_same_needle(b))
+                .map(|same| match same {
+                    [(needle, count)] => (needle.net.to_string(), *count),
+                    _ => (
+                        same[0].0.src.clone(),
+                        same.iter().map(|(_, count)| count).sum(),
+                    ),
+                })
+                .collect();
+            disp.print_needle_counts(
+                writer,
+                rows.iter().map(|(needle, count)| (needle.as_str(), *count)),
+            )?;
+        }
+        OutputStyle::ShowApproxUnique => {
+            if let Some(estimator) = &collector.unique_estimator {
+                disp.print_approx_unique(writer, estimator.estimate())?;
+            }
+        }
+        OutputStyle::ShowComparison => {
+            let labels = ["only in A", "only in B", "in both"];
+            for (label, nets) in labels.iter().zip(&collector.compared_nets) {
+                disp.print_section(writer, label, nets)?;
+            }
+        }
+        OutputStyle::ShowTree => {
+            disp.print_tree(
+                writer,
+                &collector.matched_nets,
+                params.tree_depth,
+            )?;
+        }
+        OutputStyle::ShowAggregated => {
+            // The aggregated networks are disjoint: a tree without depth.
+            let nets = Net::aggregate(&collector.matched_nets);
+            disp.print_tree(writer, &nets, 0)?;
+        }
+        OutputStyle::ShowOnlyMatching if params.sort => {
+            // Numeric order: IpAddr sorts IPv4 before IPv6, and by the
+            // address bytes. The text is the tie breaker, so the output
+            // does not depend on the file order (or on --threads).
+            let mut sorted: Vec<&(Net, String)> =
+                collector.sorted_matches.iter().collect();
+            sorted.sort_by(|(a, a_text), (b, b_text)| {
+                (a.0.network(), a.0.prefix_len(), a.0.addr())
+                    .cmp(&(b.0.network(), b.0.prefix_len(), b.0.addr()))
+                    .then_with(|| a_text.cmp(b_text))
+            });
+            disp.print_match_texts(
+                writer,
+                sorted.iter().map(|(_, text)| text.as_str()),
+            )?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// The number of matches passed to a single --exec-batch command.
+const EXEC_BATCH_SIZE: usize = 256;
+
+// The number of -o lines to look up the --resolve hostnames for at once.
+const RESOLVE_BATCH_LINES: usize = 256;
+
+/// Print the --resolve matches of the pending lines, after looking up
+/// the hostnames of all their addresses together.
+fn print_resolved_lines(
+    disp: &Display,
+    filename: &str,
+    resolver: &mut Resolver,
+    pending: &mut Vec<(LinePos, Vec<u8>, Vec<scanner::NetCandidate>)>,
+    writer: &mut dyn Write,
+) -> io::Result<()> {
+    // Only single IPs have a hostname.
+    let addrs: Vec<IpAddr> = pending
+        .iter()
+        .flat_map(|(_, _, matches)| matches)
+        .filter(|m| m.net.is_single_ip())
+        .map(|m| m.net.0.addr())
+        .collect();
+    resolver.resolve_all(&addrs);
+    for (pos, line, matches) in pending.drain(..) {
+        let names: Vec<Option<&str>> = matches
+            .iter()
+            .map(|m| match m.net.is_single_ip() {
+                true => resolver.name(&m.net.0.addr()),
+                false => None,
+            })
+            .collect();
+        disp.print_resolved_matches(
+            writer, filename, pos, &line, &matches, &names,
+        )?;
+    }
+    Ok(())
+}
+
+/// Run the --exec/--exec-batch command for the unique matches. Failures
+/// are reported, but do not stop the other commands.
+fn run_exec_commands(params: &params::Parameters, nets: &BTreeSet<Net>) {
+    let nets: Vec<&Net> = nets.iter().collect();
+    let batch_size = if params.exec_batch {
+        EXEC_BATCH_SIZE
+    } else {
+        1
+    };
+    for batch in nets.chunks(batch_size) {
+        let args = make_exec_args(&params.exec_command, batch);
+        let status = Command::new(&args[0]).args(&args[1..]).status();
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => eprintln!("ipgrep: {}: {status}", args[0]),
+            Err(e) => eprintln!("ipgrep: {}: {e}", args[0]),
+        }
+    }
+}
+
+/// Build the command line for one --exec command (one net) or one
+/// --exec-batch command (many nets). Arguments with a placeholder are
+/// repeated for every net; without any, the nets are appended.
+fn make_exec_args(template: &[String], nets: &[&Net]) -> Vec<String> {
+    const PLACEHOLDERS: [&str; 3] = ["{}", "{net}", "{addr}"];
+    let has_placeholder =
+        |arg: &str| PLACEHOLDERS.iter().any(|p| arg.contains(p));
+    let mut args = Vec::new();
+    for arg in template {
+        if has_placeholder(arg) {
+            for net in nets {
+                let addr = net.0.addr().to_string();
+                let net = net.to_string();
+                args.push(
+                    arg.replace("{}", &net)
+                        .replace("{net}", &net)
+                        .replace("{addr}", &addr),
+                );
+            }
+        } else {
+            args.push(arg.clone());
+        }
+    }
+    if !template.iter().any(|arg| has_placeholder(arg)) {
+        args.extend(nets.iter().map(|net| net.to_string()));
+    }
+    args
+}
+
+/// Create a scanner that only looks for the families/forms we need.
+fn make_scanner(params: &params::Parameters) -> scanner::NetCandidateScanner {
+    // For --report-other-family, --map-v4 and --outside we need to see
+    // both families.
+    let ignore_family = |is_family: fn(&Net) -> bool| {
+        !params.report_other_family
+            && !params.map_v4
+            && !params.outside
+            && params.positive_needles.iter().all(|n| !is_family(&n.net))
+    };
+    scanner::NetCandidateScanner::new()
+        .ignore_ipv4(ignore_family(Net::is_ipv4))
+        .ignore_ipv6(ignore_family(Net::is_ipv6))
+        .set_accept(params.accept)
+        .set_interface_mode(params.interface_mode)
+        .set_ipv4_mapped(params.ipv4_mapped)
+        .set_leading_zeros(params.leading_zeros)
+        .set_strict_boundaries(params.strict_boundaries)
+        .set_ports(params.ports.clone())
+        .set_diagnostics(params.diagnostics.clone())
+        .set_prefix_lens(
+            params.v4_prefix_lens.clone(),
+            params.v6_prefix_lens.clone(),
+        )
+        .build()
+        .expect("needles and --accept are never empty")
+}
+
+/// Count the nets that match the needles. This is for the benchmarks,
+/// which cannot reach the NeedleMatcher.
+#[cfg(feature = "bench")]
+pub fn count_matches(params: &params::Parameters, nets: &[Net]) -> usize {
+    let matcher = NeedleMatcher::new(params);
+    nets.iter().filter(|net| matcher.is_match(net)).count()
+}
+
+/// The needles and match mode, prepared for matching candidates.
+struct NeedleMatcher<'a> {
+    params: &'a params::Parameters,
+    // Set if 0.0.0.0/0 (or ::/0) is a positive needle and the match
+    // mode makes it match anything in that family. Then we can skip
+    // the per-needle comparisons.
+    any_ipv4: bool,
+    any_ipv6: bool,
+    // For --report-other-family: whether the needles are IPv4.
+    needles_are_ipv4: Option<bool>,
+    // The only positive needle, if there is just one. This is the
+    // common case ("ipgrep 10.0.0.0/8 file") which skips the loop.
+    single_needle: Option<&'a Net>,
+}
+
+impl<'a> NeedleMatcher<'a> {
+    fn new(params: &'a params::Parameters) -> Self {
+        let matches_any = matches!(
+            params.match_mode,
+            MatchMode::Within | MatchMode::Overlaps | MatchMode::Touches
+        );
+        let has_default_route = |default: &str| {
+            let default = Net::from_str_unchecked(default);
+            params.positive_needles.iter().any(|n| n.net == default)
+        };
+        Self {
+            params,
+            any_ipv4: matches_any && has_default_route("0.0.0.0/0"),
+            any_ipv6: matches_any && has_default_route("::/0"),
+            needles_are_ipv4: params.report_other_family.then(|| {
+                params.positive_needles.iter().all(|n| n.net.is_ipv4())
+            }),
+            single_needle: match params.positive_needles.as_slice() {
+                [needle] => Some(&needle.net),
+                _ => None,
+            },
+        }
+    }
+
+    /// Returns true if we're reporting other family addresses
+    /// (--report-other-family) and the candidate is one.
+    fn is_other_family(&self, net: &Net) -> bool {
+        self.needles_are_ipv4
+            .is_some_and(|needles_are_ipv4| net.is_ipv4() != needles_are_ipv4)
+    }
+
+    /// Returns true if the candidate matches any positive needle and
+    /// none of the negative ones (or, with --outside, does not), and
+    /// passes the --mmdb filter.
+    fn is_match(&self, net: &Net) -> bool {
+        self.is_needle_match(net) != self.params.outside
+            && self
+                .params
+                .geo_filter
+                .as_ref()
+                .is_none_or(|geo| geo.matches(net.0.addr()))
+    }
+
+    fn is_needle_match(&self, net: &Net) -> bool {
+        let params = self.params;
+
+        // Check for negative match first.
+        if params
+            .negative_needles
+            .iter()
+            .any(|needle| self.matches(net, &needle.net))
+        {
+            // Candidate rejected by a !needle.
+            return false;
+        }
+
+        // Check for positive match.
+        if (self.any_ipv4 && net.is_ipv4()) || (self.any_ipv6 && net.is_ipv6())
+        {
+            return true;
+        }
+        if let Some(needle) = self.single_needle {
+            return self.matches(net, needle);
+        }
+        params
+            .positive_needles
+            .iter()
+            .any(|needle| self.matches(net, &needle.net))
+    }
+
+    /// Returns true if every positive needle is matched by one of the
+    /// (already confirmed) matches, for --all. The needles of one group
+    /// (an alias like rfc1918, or an IP range) need only one match.
+    fn is_all_matched(&self, matches: &[scanner::NetCandidate]) -> bool {
+        self.params
+            .positive_needles
+            .chunk_by(|a, b| a.group == b.group)
+            .all(|group| {
+                matches.iter().any(|m| {
+                    group.iter().any(|needle| self.matches(&m.net, &needle.net))
+                })
+            })
+    }
+
+    /// Returns true if the candidate matches any positive needle and
+    /// none of the negative ones in the needle list (for --compare).
+    fn is_match_in(&self, needles: &[Needle], net: &Net) -> bool {
+        let mut any_positive = false;
+        for needle in needles.iter().filter(|n| self.matches(net, &n.net)) {
+            if needle.is_negated {
+                return false;
+            }
+            any_positive = true;
+        }
+        any_positive
+    }
+
+    /// Returns the index of the first positive needle that the
+    /// (already confirmed) candidate matches. Other family candidates
+    /// have none.
+    fn needle_index(&self, net: &Net) -> Option<usize> {
+        self.params
+            .positive_needles
+            .iter()
+            .position(|needle| self.matches(net, &needle.net))
+    }
+
+    /// Returns the --host-index of the (already confirmed) match in the
+    /// positive needle at idx. For an IP range, that is the offset from
+    /// the start of the range, not from the network it was split into.
+    fn host_index(
+        &self,
+        match_: &scanner::NetCandidate,
+        idx: usize,
+    ) -> Option<u128> {
+        let needles = &self.params.positive_needles[..=idx];
+        let first = needles
+            .iter()
+            .rev()
+            .take_while(|n| n.is_same_needle(&needles[idx]))
+            .last()?;
+        let offset = match_.net.host_index(&needles[idx].net)?;
+        Some(offset + needles[idx].net.host_index_from(&first.net)?)
+    }
+
+    /// Match one candidate against one needle, across families for
+    /// --map-v4.
+    fn matches(&self, net: &Net, needle: &Net) -> bool {
+        let match_mode = &self.params.match_mode;
+        if self.params.map_v4 {
+            match_mode.matches_mapped(net, needle)
+        } else {
+            match_mode.matches(net, needle)
+        }
+    }
+}
+
+/// Copy src to dst, leaving out ANSI CSI escape sequences ("ESC [ params
+/// intermediates final"). Incomplete sequences are copied as is.
+fn strip_ansi(src: &[u8], dst: &mut Vec<u8>) {
+    dst.clear();
+    let mut pos = 0;
+    while let Some(esc) = memchr::memchr(b'\x1b', &src[pos..]) {
+        let esc = pos + esc;
+        dst.extend_from_slice(&src[pos..esc]);
+
+        let mut end = esc + 1;
+        let mut complete = false;
+        if end < src.len() && src[end] == b'[' {
+            end += 1;
+            while end < src.len() && matches!(src[end], 0x30..=0x3f) {
+                end += 1; // parameter bytes
+            }
+            while end < src.len() && matches!(src[end], 0x20..=0x2f) {
+                end += 1; // intermediate bytes
+            }
+            if end < src.len() && matches!(src[end], 0x40..=0x7e) {
+                end += 1; // final byte
+                complete = true;
+            }
+        }
+        if !complete {
+            dst.extend_from_slice(&src[esc..end]);
+        }
+        pos = end;
+    }
+    dst.extend_from_slice(&src[pos..]);
+}
+
+/// Find the digit-dot-digit sequences that are not part of an address,
+/// and the addresses glued to a preceding "digit,", for --debug. The
+/// line is scanned for all forms of both families, independent of the
+/// needles and --accept. Returns the ranges of the (digits, dots and
+/// commas) tokens they are in.
+fn find_near_misses(line: &[u8]) -> Vec<(usize, usize)> {
+    let candidates: Vec<_> =
+        scanner::scan_bytes(line, AcceptSet::ALL).collect();
+    let is_part = |c: &u8| c.is_ascii_digit() || *c == b'.' || *c == b',';
+    let token = |at: usize| {
+        let start = line[..at]
+            .iter()
+            .rposition(|c| !is_part(c))
+            .map_or(0, |p| p + 1);
+        let end = line[at..]
+            .iter()
+            .position(|c| !is_part(c))
+            .map_or(line.len(), |p| at + p);
+        (start, end)
+    };
+    let mut near_misses = Vec::new();
+    let mut pos = 1;
+    while let Some(dot) = memchr::memchr(b'.', line.get(pos..).unwrap_or(&[])) {
+        let dot = pos + dot;
+        pos = dot + 1;
+        if !(line[dot - 1].is_ascii_digit()
+            && line.get(dot + 1).is_some_and(u8::is_ascii_digit))
+            || candidates
+                .iter()
+                .any(|c| c.range.0 <= dot && dot < c.range.1)
+        {
+            continue;
+        }
+        let (start, end) = token(dot);
+        near_misses.push((start, end));
+        pos = end.max(pos);
+    }
+    // Like 5,10.0.0.1: a thousands separator, or a mangled address. Not
+    // a list like 10.0.0.1,10.0.0.2.
+    for candidate in &candidates {
+        let start = candidate.range.0;
+        if start >= 2
+            && line[start - 1] == b','
+            && line[start - 2].is_ascii_digit()
+            && !candidates.iter().any(|c| c.range.1 == start - 1)
+        {
+            near_misses.push(token(start));
+        }
+    }
+    near_misses.sort_unstable();
+    near_misses.dedup();
+    near_misses
+}
+
+/// Keep only the candidate at the nth address on the line, for --nth:
+/// 1-based, or counting back from the end if negative. All addresses
+/// count, also those of a family or form that is not searched for. None
+/// are kept if there are fewer, or if the nth is not a candidate.
+fn keep_nth(
+    line: &[u8],
+    candidates: &mut Vec<scanner::NetCandidate>,
+    nth: isize,
+) {
+    let starts: Vec<usize> = scanner::scan_bytes(line, AcceptSet::ALL)
+        .map(|c| c.range.0)
+        .collect();
+    let idx = match nth {
+        1.. => Some(nth.unsigned_abs() - 1),
+        _ => starts.len().checked_sub(nth.unsigned_abs()),
+    };
+    let start = idx.and_then(|idx| starts.get(idx));
+    candidates.retain(|c| Some(&c.range.0) == start);
+}
+
+/// Drop the matches that contain another (more specific) match, and
+/// the repeats of identical matches, for --dedup=specific. The order of
+/// the remaining matches is kept.
+fn keep_most_specific(matches: &mut Vec<scanner::NetCandidate>) {
+    let nets: Vec<Net> = matches.iter().map(|m| m.net.clone()).collect();
+    let mut idx = 0;
+    matches.retain(|m| {
+        let (before, after) = nets.split_at(idx);
+        idx += 1;
+        let is_repeat = before.contains(&m.net);
+        let has_more_specific = before
+            .iter()
+            .chain(&after[1..])
+            .any(|other| *other != m.net && m.net.contains_net(other));
+        !is_repeat && !has_more_specific
+    });
+}
+
+/// The text that -o prints for a match: as found on the line (or
+/// normalized with --canonical), or the truncated network with -O.
+fn match_text(
+    params: &params::Parameters,
+    line: &[u8],
+    match_: &scanner::NetCandidate,
+) -> Vec<u8> {
+    match params.rewrite_output_prefix {
+        Some(prefix) => match_.net.supernet(prefix).to_string().into_bytes(),
+        None if params.canonical => match_.canonical().into_bytes(),
+        None => line[match_.range.0..match_.range.1.min(line.len())].to_vec(),
+    }
+}
+
+/// Look for matches in the file path itself, for --match-paths. Returns
+/// the number of matches.
+fn search_in_path(
+    disp: &Display,
+    file: &files::FileSource,
+    netcandidatescanner: &scanner::NetCandidateScanner,
+    matcher: &NeedleMatcher,
+    params: &params::Parameters,
+    writer: &mut dyn Write,
+) -> io::Result<usize> {
+    let mut matches =
+        netcandidatescanner.find_all(file.name.as_bytes(), &file.name);
+    matches.retain(|m| matcher.is_match(&m.net));
+    if matches.is_empty() {
+        return Ok(0);
+    }
+
+    match params.output_style {
+        OutputStyle::ShowOnlyMatching => {
+            disp.print_path_matches(
+                writer,
+                &file.name,
+                params.rewrite_output_prefix,
+                &matches,
+            )?;
+        }
+        OutputStyle::ShowLinesAndContext => {
+            disp.print_path_line(writer, &file.name, &matches)?;
+        }
+        _ => {}
+    }
+    Ok(matches.len())
+}
+
+/// A line read by next_line().
+enum NextLine<'a> {
+    /// The line, and how many bytes to consume() from the reader once
+    /// done with it: its length if it is borrowed from the reader.
+    Line(&'a [u8], usize),
+    /// A line cut short by the limit, which is skipped: "10.0.0.12" cut
+    /// to "10.0.0.1" would match the wrong address.
+    CutShort,
+    Eof,
+}
+
+/// Read the next line, including its eol, of at most max bytes. A line
+/// that is in the reader's buffer completely is borrowed from there,
+/// instead of copied into buf. For a mapped file, that is every line
+/// but an unterminated last one.
+fn next_line<'a>(
+    reader: &'a mut dyn BufRead,
+    eol: u8,
+    max: u64,
+    buf: &'a mut Vec<u8>,
+) -> io::Result<NextLine<'a>> {
+    // Unlike read_until(), fill_buf() does not retry when interrupted.
+    while let Err(e) = reader.fill_buf() {
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    let in_buffer = {
+        let available = reader.fill_buf()?;
+        let window = usize::try_from(max)
+            .map_or(available, |max| &available[..available.len().min(max)]);
+        memchr::memchr(eol, window).map(|pos| pos + 1)
+    };
+    if let Some(len) = in_buffer {
+        let line = &reader.fill_buf()?[..len];
+        return Ok(NextLine::Line(line, len));
+    }
+
+    // The line continues past the buffer, or is the unterminated last
+    // line of the file.
+    buf.clear();
+    let n = reader.take(max).read_until(eol, buf)?;
+    if n == 0 {
+        Ok(NextLine::Eof)
+    } else if n as u64 == max
+        && buf.last() != Some(&eol)
+        && !reader.fill_buf().is_ok_and(|b| b.is_empty())
+    {
+        // Unless it is the unterminated last line of the file.
+        Ok(NextLine::CutShort)
+    } else {
+        Ok(NextLine::Line(buf, 0))
+    }
+}
+
+fn search_in_file(
+    disp: &Display,
+    file: &mut files::FileSource,
+    netcandidatescanner: &scanner::NetCandidateScanner,
+    matcher: &NeedleMatcher,
+    params: &params::Parameters,
+    collector: &mut Collector,
+    writer: &mut dyn Write,
+) -> io::Result<FileCounts> {
+    // The lines that are not in the reader's buffer completely.
+    let mut line_buf = Vec::new();
+    // The length of the line borrowed from the reader's buffer.
+    let mut consumed = 0;
+    let mut lineno = 0;
+    // Bytes read so far, for --scan-limit.
+    let mut scanned: u64 = 0;
+    // Scratch space for the --strip-ansi line.
+    let mut stripped = Vec::new();
+
+    // Reused for every line, instead of allocating per line.
+    let mut candidates = Vec::new();
+    let mut matches = Vec::new();
+    let mut match_count: usize = 0;
+    let mut kinds = [0; 3];
+    // The -o lines that wait for their --resolve hostnames.
+    let mut resolve_pending = Vec::new();
+    // Interfaces skipped by the scanner, for -I summary.
+    let mut skipped_ifaces: usize = 0;
+    // Selected lines so far, for --max-count.
+    let mut selected_count: usize = 0;
+    // The text of the last printed -o match, for --squeeze.
+    let mut last_emitted: Option<Vec<u8>> = None;
+
+    // The line terminator; with -z, NUL is not a sign of binary data.
+    let eol = if params.null_data { b'\0' } else { b'\n' };
+
+    // Like grep, a NUL in the first chunk makes it a binary file. For
+    // those, we only say that they match instead of printing lines.
+    let is_binary = params.binary_files != BinaryFiles::Text
+        && !params.null_data
+        && file.reader.fill_buf().is_ok_and(|buf| {
+            // A mapped file is a single chunk; only look at the start.
+            let head = &buf[..buf.len().min(files::BUFSIZ)];
+            memchr::memchr(0, head).is_some()
+        });
+    if is_binary && params.binary_files == BinaryFiles::WithoutMatch {
+        return Ok(FileCounts {
+            matches: 0,
+            lines: 0,
+            bytes: 0,
+            kinds,
+        });
+    }
+    let is_binary_hidden = is_binary
+        && matches!(
+            params.output_style,
+            OutputStyle::ShowJsonMatches
+                | OutputStyle::ShowOnlyMatching
+                | OutputStyle::ShowLinesAndContext
+        );
+
+    let mut context =
+        // The before-lines keep their byte offset and matches.
+        ContextBuffer::<(u64, Vec<scanner::NetCandidate>)>::from_show_context(
+            &params.show_context,
+        );
+
+    // Literal substrings that must (--also-literal) or may (--or-literal)
+    // be on the line for it to be selected.
+    let also_literals: Vec<memmem::Finder> = params
+        .also_literals
+        .iter()
+        .map(|s| memmem::Finder::new(s.as_bytes()))
+        .collect();
+    let or_literals: Vec<memmem::Finder> = params
+        .or_literals
+        .iter()
+        .map(|s| memmem::Finder::new(s.as_bytes()))
+        .collect();
+
+    loop {
+        // Once --max-count is reached, only read on for after-lines.
+        let is_max_reached =
+            params.max_count.is_some_and(|max| selected_count >= max);
+        if is_max_reached && !context.is_after_pending() {
+            break;
+        }
+
+        // Done with the previous line.
+        file.reader.consume(consumed);
+        consumed = 0;
+
+        let max = match params.scan_limit {
+            Some(limit) if scanned >= limit => {
+                // Only a notice if there was more to scan.
+                let at_eof = file.reader.fill_buf().is_ok_and(|b| b.is_empty());
+                if !at_eof && !params.no_messages {
+                    params.diagnostics.warn(
+                        &file.name,
+                        &format!(
+                            "scan limit of {limit} bytes reached (truncated)"
+                        ),
+                    );
+                }
+                break;
+            }
+            Some(limit) => limit - scanned,
+            None => u64::MAX,
+        };
+        let line = match next_line(&mut *file.reader, eol, max, &mut line_buf) {
+            Ok(NextLine::Line(line, borrowed)) => {
+                consumed = borrowed;
+                line
+            }
+            Ok(NextLine::CutShort) => {
+                scanned += max;
+                continue;
+            }
+            Ok(NextLine::Eof) => break,
+            // read_until() retries ErrorKind::Interrupted itself. Other
+            // errors skip the rest of the file.
+            Err(e) => {
+                if !params.no_messages {
+                    eprintln!("ipgrep: {}: {} (skipping)", file.name, e);
+                }
+                break;
+            }
+        };
+        scanned += line.len() as u64;
+        lineno += 1;
+        let pos = LinePos {
+            lineno,
+            offset: scanned - line.len() as u64,
+        };
+
+        // Remove color codes that might split up addresses. We show the
+        // stripped line too, so the match ranges stay valid.
+        let line =
+            if params.strip_ansi && memchr::memchr(b'\x1b', line).is_some() {
+                strip_ansi(line, &mut stripped);
+                &stripped[..]
+            } else {
+                line
+            };
+
+        // Check all possible candidates on the line.
+        skipped_ifaces += netcandidatescanner.find_all_into(
+            line,
+            &file.name,
+            &mut candidates,
+        );
+        if params.debug {
+            for (start, end) in find_near_misses(line) {
+                eprintln!(
+                    "ipgrep: debug: {}:{}: near-miss {:?}",
+                    file.name,
+                    lineno,
+                    String::from_utf8_lossy(&line[start..end]),
+                );
+            }
+        }
+        if let Some(nth) = params.nth {
+            keep_nth(line, &mut candidates, nth);
+        }
+        let mut has_needle_match = false;
+        for candidate in candidates.drain(..) {
+            if matcher.is_other_family(&candidate.net) {
+                // Other family, for --report-other-family.
+                matches.push(candidate);
+            } else if matcher.is_match(&candidate.net) {
+                // Candidate confirmed.
+                has_needle_match = true;
+                if !params.report_other_family {
+                    matches.push(candidate);
+                }
+            }
+        }
+        if params.report_other_family && !has_needle_match {
+            // Other family addresses only count next to a needle match.
+            matches.clear();
+        }
+        if params.match_all && !matcher.is_all_matched(&matches) {
+            // With --all, every needle needs a match on the line.
+            matches.clear();
+        }
+
+        // Address matches only count if all --also-literal strings are
+        // on the line. Drop them otherwise, so they are not highlighted.
+        if !matches.is_empty()
+            && !also_literals.iter().all(|f| f.find(line).is_some())
+        {
+            matches.clear();
+        }
+
+        if params.dedup_specific && matches.len() > 1 {
+            keep_most_specific(&mut matches);
+        }
+
+        // A line is matched by its addresses, or by any --or-literal.
+        let is_line_matched = !matches.is_empty()
+            || or_literals.iter().any(|f| f.find(line).is_some());
+
+        // Mirroring GNU grep, we simply invert the lines we show. This
+        // also means we now also store matches (for highlighting) in the
+        // context buffer.
+        let is_line_selected = if is_max_reached {
+            false // only context from here on
+        } else if params.invert_match {
+            !is_line_matched
+        } else {
+            is_line_matched
+        };
+
+        if !is_line_selected {
+            if context.is_used() {
+                if context.should_print_after_line() {
+                    assert_eq!(
+                        params.output_style,
+                        OutputStyle::ShowLinesAndContext
+                    );
+                    disp.print_context(
+                        writer, &file.name, pos, line, &matches,
+                    )?;
+                    context.update_last_printed(lineno);
+                } else {
+                    context.push_before_line(
+                        lineno,
+                        line,
+                        // We don't need the matches, let the buffer have them.
+                        (pos.offset, std::mem::take(&mut matches)),
+                    );
+                }
+            }
+        } else {
+            selected_count += 1;
+            // Like grep, -c counts lines, unless --count-matches.
+            let count_lines = params.output_style
+                == OutputStyle::ShowCountsPerFile
+                && !params.count_matches
+                && !params.split_kind;
+            match_count +=
+                if params.invert_match || matches.is_empty() || count_lines {
+                    1 // inverted, selected by --or-literal only, or -c
+                } else {
+                    matches.len()
+                };
+            if !params.exec_command.is_empty() {
+                collector
+                    .exec_nets
+                    .extend(matches.iter().map(|m| m.net.clone()));
+            }
+
+            if is_binary_hidden {
+                // Short circuit, like for -l.
+                disp.print_binary_matches(writer, &file.name)?;
+                break;
+            }
+
+            match params.output_style {
+                OutputStyle::JustExitCode
+                | OutputStyle::ShowFilesWithLf
+                | OutputStyle::ShowFilesWithNull
+                | OutputStyle::ShowFilesWithoutMatchLf
+                | OutputStyle::ShowFilesWithoutMatchNull => {
+                    // Short circuit. Don't trust the match_count, so
+                    // set it to 1.
+                    match_count = 1;
+                    break;
+                }
+                OutputStyle::ShowCountsPerFile => {
+                    if params.split_kind {
+                        for m in &matches {
+                            kinds[m.kind as usize] += 1;
+                        }
+                    }
+                }
+                OutputStyle::ShowCountsPerNeedle => {
+                    for m in &matches {
+                        if let Some(idx) = matcher.needle_index(&m.net) {
+                            collector.needle_counts[idx] += 1;
+                        }
+                    }
+                }
+                OutputStyle::ShowApproxUnique => {
+                    if let Some(estimator) = &mut collector.unique_estimator {
+                        for m in &matches {
+                            estimator.insert(&m.net);
+                        }
+                    }
+                }
+                OutputStyle::ShowComparison => {
+                    if let Some((a, b)) = &params.compare_needles {
+                        for m in &matches {
+                            let section = match (
+                                matcher.is_match_in(a, &m.net),
+                                matcher.is_match_in(b, &m.net),
+                            ) {
+                                (true, false) => 0,
+                                (false, true) => 1,
+                                (true, true) => 2,
+                                (false, false) => continue,
+                            };
+                            collector.compared_nets[section]
+                                .insert(m.net.clone());
+                        }
+                    }
+                }
+                OutputStyle::ShowTree | OutputStyle::ShowAggregated => {
+                    collector
+                        .matched_nets
+                        .extend(matches.iter().map(|m| m.net.clone()));
+                }
+                OutputStyle::ShowJsonMatches => {
+                    disp.print_json_matches(
+                        writer, &file.name, lineno, line, &matches,
+                    )?;
+                }
+                OutputStyle::ShowOnlyMatching => {
+                    // TODO: GNU grep 3 behaviour is kind of peculiar when
+                    // using -o and context. The -C context decides if "--"
+                    // delimiters will be shown or not: for -C3 you'll
+                    // get a delimiter if there is more than three lines
+                    // between the matches.
+                    // Do we want to implement this? Seems like an
+                    // implementation detail, and not something useful.
+                    if params.unique {
+                        // Only the first time we see each (truncated)
+                        // network. Beyond the --unique-cap, the new ones
+                        // are no longer remembered.
+                        matches.retain(|m| {
+                            let net = match params.rewrite_output_prefix {
+                                Some(prefix) => m.net.supernet(prefix),
+                                None => m.net.clone(),
+                            };
+                            let unique_nets = &mut collector.unique_nets;
+                            if unique_nets.contains(&net) {
+                                return false;
+                            }
+                            if params
+                                .unique_cap
+                                .is_none_or(|cap| unique_nets.len() < cap)
+                            {
+                                unique_nets.insert(net);
+                            } else if !collector.unique_capped {
+                                collector.unique_capped = true;
+                                params.diagnostics.warn(
+                                    &file.name,
+                                    "--unique-cap reached, later matches \
+                                     may repeat",
+                                );
+                            }
+                            true
+                        });
+                    }
+                    if params.squeeze {
+                        // Like uniq(1): drop matches whose output text
+                        // equals that of the match printed before it.
+                        matches.retain(|m| {
+                            let text = match_text(params, line, m);
+                            if last_emitted.as_ref() == Some(&text) {
+                                false
+                            } else {
+                                last_emitted = Some(text);
+                                true
+                            }
+                        });
+                    }
+                    if params.sort {
+                        // Printed at the end, by print_collected().
+                        collector.sorted_matches.extend(matches.iter().map(
+                            |m| {
+                                let text = match_text(params, line, m);
+                                let text = String::from_utf8_lossy(&text);
+                                (m.net.clone(), text.into_owned())
+                            },
+                        ));
+                    } else if let Some(resolver) = &mut collector.resolver {
+                        // Printed once the hostnames of several lines are
+                        // looked up together.
+                        resolve_pending.push((
+                            pos,
+                            line.to_vec(),
+                            std::mem::take(&mut matches),
+                        ));
+                        if resolve_pending.len() >= RESOLVE_BATCH_LINES {
+                            print_resolved_lines(
+                                disp,
+                                &file.name,
+                                resolver,
+                                &mut resolve_pending,
+                                writer,
+                            )?;
+                        }
+                    } else if params.host_index {
+                        let indexes: Vec<(usize, u128)> = matches
+                            .iter()
+                            .filter_map(|m| {
+                                let idx = matcher.needle_index(&m.net)?;
+                                Some((m.range.0, matcher.host_index(m, idx)?))
+                            })
+                            .collect();
+                        disp.print_host_indexes(
+                            writer, &file.name, pos, &indexes,
+                        )?;
+                    } else if let Some(rewrite_prefix) =
+                        params.rewrite_output_prefix
+                    {
+                        disp.print_network_matches(
+                            writer,
+                            &file.name,
+                            pos,
+                            rewrite_prefix,
+                            &matches,
+                        )?;
+                    } else {
+                        disp.print_matches(
+                            writer, &file.name, pos, line, &matches,
+                        )?;
+                    }
+                }
+                OutputStyle::ShowLinesAndContext => {
+                    if context.is_used() {
+                        // Print delimiter ("--")? Also between the lines
+                        // of different files, unless the --heading
+                        // separates those.
+                        if context.is_new_match_block(lineno)
+                            || (!context.has_printed()
+                                && collector.context_printed
+                                && !disp.is_heading())
+                        {
+                            disp.print_context_delimiter(
+                                writer,
+                                &file.name,
+                                lineno - 1,
+                            )?;
+                        }
+                        // Print before-lines?
+                        for (c_lineno, c_line, (c_offset, c_matches)) in
+                            context.get_before_lines()
+                        {
+                            let c_pos = LinePos {
+                                lineno: *c_lineno,
+                                offset: *c_offset,
+                            };
+                            disp.print_context(
+                                writer, &file.name, c_pos, c_line, c_matches,
+                            )?;
+                        }
+                        context.clear_before_lines();
+                    }
+
+                    // Print the line with the matches.
+                    disp.print_line(writer, &file.name, pos, line, &matches)?;
+
+                    if context.is_used() {
+                        // Record that we might need to print
+                        // after-lines in the future. And keep track of
+                        // what we printed, for the context-delimiter.
+                        context.request_after();
+                        context.update_last_printed(lineno);
+                        collector.context_printed = true;
+                    }
+                }
+            }
+        }
+
+        matches.clear();
+    }
+
+    if let Some(resolver) = &mut collector.resolver {
+        print_resolved_lines(
+            disp,
+            &file.name,
+            resolver,
+            &mut resolve_pending,
+            writer,
+        )?;
+    }
+
+    if matches!(params.interface_mode, InterfaceMode::SummarizeAndSkip)
+        && skipped_ifaces != 0
+    {
+        params.diagnostics.warn(
+            &file.name,
+            &format!(
+                "skipped {skipped_ifaces} interface addresses with host \
+                 bits set"
+            ),
+        );
+    }
+
+    Ok(FileCounts {
+        matches: match_count,
+        lines: lineno,
+        bytes: scanned,
+        kinds,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    use clap::Parser;
+
+    use crate::cli::Args;
+    use crate::diagnostics::Collect;
+    use crate::files::make_test_dir;
+
+    /// Make Parameters from command line arguments (without argv[0]).
+    fn parse(args: &[&str]) -> params::Parameters {
+        <Args as Parser>::try_parse_from(
+            std::iter::once("ipgrep").chain(args.iter().copied()),
+        )
+        .expect("valid args")
+        .into_parameters()
+    }
+
+    /// Run search_in_file on input, using the command line arguments
+    /// (needles and options) in args. Returns the output and the count.
+    fn search(args: &[&str], input: &[u8]) -> (String, usize) {
+        let (output, counts) = search_bytes(args, input);
+        (String::from_utf8(output).expect("utf8"), counts.matches)
+    }
+
+    /// Like search(), but returns the output as raw bytes.
+    fn search_bytes(args: &[&str], input: &[u8]) -> (Vec<u8>, FileCounts) {
+        search_params(&parse(args), input)
+    }
+
+    /// Like search_bytes(), with the parameters already parsed.
+    fn search_params(
+        params: &params::Parameters,
+        input: &[u8],
+    ) -> (Vec<u8>, FileCounts) {
+        let disp = Display::new()
+            .show_lineno(params.show_lineno)
+            .show_byte_offset(params.show_byte_offset)
+            .print0(params.print0)
+            .canonical(params.canonical);
+        let mut file = files::FileSource {
+            name: "(stdin)".into(),
+            reader: Box::new(Cursor::new(input.to_vec())),
+        };
+        let mut output = Vec::new();
+        let mut collector = Collector::new(params);
+        let counts = search_in_file(
+            &disp,
+            &mut file,
+            &make_scanner(params),
+            &NeedleMatcher::new(params),
+            params,
+            &mut collector,
+            &mut output,
+        )
+        .expect("write failed");
+        print_collected(&disp, &collector, params, &mut output)
+            .expect("write failed");
+        (output, counts)
+    }
+
+    /// Run a whole invocation on the given files, like main() would,
+    /// and return the exact output, with the directory stripped from the
+    /// filenames.
+    fn run_golden(name: &str, args: &[&str], files: &[(&str, &str)]) -> String {
+        let dir = make_test_dir(name);
+        let mut args: Vec<String> = args.iter().map(|&a| a.into()).collect();
+        for (filename, contents) in files {
+            std::fs::write(dir.join(filename), contents).unwrap();
+            args.push(dir.join(filename).display().to_string());
+        }
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let params = parse(&args);
+        let mut output = Vec::new();
+        run_with(&params, make_file_iter(&params), false, &mut output)
+            .expect("write failed");
+        std::fs::remove_dir_all(&dir).unwrap();
+        let prefix = format!("{}/", dir.display());
+        String::from_utf8(output).unwrap().replace(&prefix, "")
+    }
+
+    const FIREWALL_LOG: &[u8] = b"\
+DROP 10.0.0.1 -> 192.168.1.1
+ACCEPT 10.0.0.2 -> 192.168.1.1
+DROP 172.16.0.1 -> 192.168.1.1
+REJECT 10.0.0.3 -> 192.168.1.1
+";
+
+    #[test]
+    fn test_non_utf8_input() {
+        // Latin-1 and other binary bytes around the addresses.
+        let input = b"caf\xe9 10.0.0.1\xff from \xfe\xfe192.168.1.1\xe9\n\
+                      no address \xc3\x28 here\n";
+        let (out, counts) = search_bytes(&["ip4"], input);
+        assert_eq!(out, b"caf\xe9 10.0.0.1\xff from \xfe\xfe192.168.1.1\xe9\n");
+        assert_eq!(counts.matches, 2);
+        let (out, _) = search_bytes(&["-o", "ip4"], input);
+        assert_eq!(out, b"10.0.0.1\n192.168.1.1\n");
+        let (out, _) = search_bytes(&["-v", "ip4"], input);
+        assert_eq!(out, b"no address \xc3\x28 here\n");
+    }
+
+    #[test]
+    fn test_also_literal() {
+        let (out, count) =
+            search(&["10.0.0.0/8", "--also-literal", "DROP"], FIREWALL_LOG);
+        assert_eq!(out, "DROP 10.0.0.1 -> 192.168.1.1\n");
+        assert_eq!(count, 1);
+
+        // All literals must be present.
+        let (out, _) = search(
+            &["10.0.0.0/8", "--also-literal=DROP", "--also-literal=0.1 "],
+            FIREWALL_LOG,
+        );
+        assert_eq!(out, "DROP 10.0.0.1 -> 192.168.1.1\n");
+        let (out, _) = search(
+            &["10.0.0.0/8", "--also-literal=DROP", "--also-literal=X"],
+            FIREWALL_LOG,
+        );
+        assert_eq!(out, "");
+
+        // Inverted: all lines except the one above.
+        let (out, count) = search(
+            &["-v", "10.0.0.0/8", "--also-literal", "DROP"],
+            FIREWALL_LOG,
+        );
+        assert_eq!(
+            out,
+            "ACCEPT 10.0.0.2 -> 192.168.1.1\n\
+             DROP 172.16.0.1 -> 192.168.1.1\n\
+             REJECT 10.0.0.3 -> 192.168.1.1\n"
+        );
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn test_or_literal() {
+        let (out, count) = search(
+            &["10.0.0.2", "--or-literal", "REJECT", "--or-literal", "XX"],
+            FIREWALL_LOG,
+        );
+        assert_eq!(
+            out,
+            "ACCEPT 10.0.0.2 -> 192.168.1.1\n\
+             REJECT 10.0.0.3 -> 192.168.1.1\n"
+        );
+        assert_eq!(count, 2);
+
+        // Literal-only lines have no matches to show.
+        let (out, _) =
+            search(&["-o", "10.0.0.2", "--or-literal", "DROP"], FIREWALL_LOG);
+        assert_eq!(out, "10.0.0.2\n");
+    }
+
+    #[test]
+    fn test_squeeze() {
+        let input = b"\
+10.0.0.1 10.0.0.1
+10.0.0.1 10.0.0.2 10.0.0.1
+10.0.0.3 10.0.0.3
+";
+        let (out, _) = search(&["-o", "--squeeze", "10.0.0.0/8"], input);
+        assert_eq!(out, "10.0.0.1\n10.0.0.2\n10.0.0.1\n10.0.0.3\n");
+
+        // Three identical followed by a different one.
+        let input = b"1.2.3.4\n1.2.3.4\n1.2.3.4\n1.2.3.5\n";
+        let (out, count) = search(&["-o", "--squeeze", "-n", "ip4"], input);
+        assert_eq!(out, "1:1.2.3.4\n4:1.2.3.5\n");
+        assert_eq!(count, 4, "squeezing does not affect the count");
+
+        // The raw text is compared, not the network.
+        let input = b"::1 ::0001 ::0001\n";
+        let (out, _) = search(&["-o", "--squeeze", "ip6"], input);
+        assert_eq!(out, "::1\n::0001\n");
+
+        // With -O the truncated network is compared.
+        let input = b"10.0.0.1 10.0.0.2\n10.0.1.1\n";
+        let (out, _) = search(&["-O24", "--squeeze", "ip4"], input);
+        assert_eq!(out, "10.0.0.0/24\n10.0.1.0/24\n");
+    }
+
+    #[test]
+    fn test_byte_offset() {
+        let input = b"first line\nat 10.0.0.1 and 10.0.0.2\nlast\n";
+        let (out, _) = search(&["-b", "10.0.0.0/8"], input);
+        assert_eq!(out, "11:at 10.0.0.1 and 10.0.0.2\n");
+        let (out, _) = search(&["-bn", "-C1", "10.0.0.0/8"], input);
+        assert_eq!(
+            out,
+            "1-0-first line\n2:11:at 10.0.0.1 and 10.0.0.2\n3-36-last\n"
+        );
+
+        // With -o, the offset of the match.
+        let (out, _) = search(&["-o", "-b", "-n", "10.0.0.0/8"], input);
+        assert_eq!(out, "2:14:10.0.0.1\n2:27:10.0.0.2\n");
+        let (out, _) = search(&["-o", "-b", "-O24", "10.0.0.0/8"], input);
+        assert_eq!(out, "14:10.0.0.0/24\n27:10.0.0.0/24\n");
+    }
+
+    #[test]
+    fn test_canonical() {
+        let input = b"::0001 10.0.0.1/32 10.0.0.5/24 [2001:db8::0001]:443\n";
+        let (out, _) = search(&["-o", "--canonical", "ip4,ip6"], input);
+        assert_eq!(out, "::1\n10.0.0.1\n10.0.0.5\n2001:db8::1\n");
+        let (out, _) =
+            search(&["-o", "--canonical", "-I", "net", "ip4"], input);
+        assert_eq!(out, "10.0.0.1\n10.0.0.0/24\n");
+        let (out, _) =
+            search(&["-o", "--canonical", "--with-ports", "ip6"], input);
+        assert_eq!(out, "::1\n[2001:db8::1]:443\n");
+
+        // --squeeze compares the normalized text.
+        let input = b"::1 ::0001 ::0001\n";
+        let (out, _) =
+            search(&["-o", "--canonical", "--squeeze", "ip6"], input);
+        assert_eq!(out, "::1\n");
+
+        // Whole lines are printed as is.
+        let input = b"::0001\n";
+        let (out, _) = search(&["--canonical", "ip6"], input);
+        assert_eq!(out, "::0001\n");
+    }
+
+    #[test]
+    fn test_default_route_fast_path() {
+        let params = parse(&["0.0.0.0/0"]);
+        let matcher = NeedleMatcher::new(&params);
+        assert!(matcher.any_ipv4 && !matcher.any_ipv6);
+        let params = parse(&["ip4,ip6", "-m", "overlaps"]);
+        let matcher = NeedleMatcher::new(&params);
+        assert!(matcher.any_ipv4 && matcher.any_ipv6);
+        for (keywords, any_ipv4, any_ipv6) in [
+            ("any", true, true),
+            ("ipv4", true, false),
+            ("ipv6", false, true),
+            ("ip6,10.0.0.0/8", false, true),
+        ] {
+            let params = parse(&[keywords]);
+            let matcher = NeedleMatcher::new(&params);
+            assert_eq!(
+                (matcher.any_ipv4, matcher.any_ipv6),
+                (any_ipv4, any_ipv6),
+                "for {keywords}"
+            );
+        }
+        // For contains/equals, ::/0 is not "anything".
+        let params = parse(&["::/0", "-m", "contains"]);
+        let matcher = NeedleMatcher::new(&params);
+        assert!(!matcher.any_ipv4 && !matcher.any_ipv6);
+        assert!(!matcher.is_match(&Net::from_str_unchecked("::1")));
+        assert!(matcher.is_match(&Net::from_str_unchecked("::/0")));
+
+        let input = b"1.2.3.4 10.0.0.0/8 ::1 fe80::/10 192.168.1.1/24\n";
+        let (out, _) = search(&["-o", "0.0.0.0/0"], input);
+        assert_eq!(out, "1.2.3.4\n10.0.0.0/8\n192.168.1.1/24\n");
+        let (out, _) = search(&["-o", "0.0.0.0/0,::/0"], input);
+        assert_eq!(
+            out,
+            "1.2.3.4\n10.0.0.0/8\n::1\nfe80::/10\n192.168.1.1/24\n"
+        );
+        // Accept set filtering still applies.
+        let (out, _) = search(&["-O128", "-a", "ip", "0.0.0.0/0,::/0"], input);
+        assert_eq!(
+            out,
+            "1.2.3.4/32\n10.0.0.0/32\n::1/128\nfe80::/128\n192.168.1.1/32\n"
+        );
+        let (out, _) = search(&["-o", "-a", "net", "0.0.0.0/0,::/0"], input);
+        assert_eq!(out, "10.0.0.0/8\nfe80::/10\n");
+        // Negative needles still apply.
+        let (out, _) = search(&["-o", "ip4,!rfc1918"], input);
+        assert_eq!(out, "1.2.3.4\n");
+    }
+
+    #[test]
+    fn test_strip_ansi() {
+        let mut dst = Vec::new();
+        for (src, expected) in [
+            (&b"plain"[..], &b"plain"[..]),
+            (b"\x1b[1;31m10.0.0.1\x1b[0m\n", b"10.0.0.1\n"),
+            (b"10.\x1b[0m0.\x1b[K0.1", b"10.0.0.1"),
+            (b"\x1b[38;5;196mred\x1b[m", b"red"),
+            // Not CSI or incomplete: kept.
+            (b"a\x1bb\x1b[", b"a\x1bb\x1b["),
+            (b"a\x1b[12\n", b"a\x1b[12\n"),
+        ] {
+            strip_ansi(src, &mut dst);
+            assert_eq!(dst, expected, "for {src:?}");
+        }
+    }
+
+    #[test]
+    fn test_strip_ansi_option() {
+        let input = b"from 10.\x1b[0m0.0.1 to \x1b[1m192.168.1.1\x1b[0m\n";
+        let (out, _) = search(&["10.0.0.1"], input);
+        assert_eq!(out, "");
+        let (out, _) = search(&["--strip-ansi", "10.0.0.1"], input);
+        assert_eq!(out, "from 10.0.0.1 to 192.168.1.1\n");
+        let (out, _) = search(&["--strip-ansi", "-o", "ip4"], input);
+        assert_eq!(out, "10.0.0.1\n192.168.1.1\n");
+    }
+
+    #[test]
+    fn test_tree() {
+        let input = b"\
+route 10.1.2.0/24 via 10.0.0.1
+route 10.0.0.0/8 via 192.168.1.1
+route 10.1.3.0/24 via 10.0.0.1
+route 10.1.2.0/24 via 10.0.0.1
+";
+        let (out, count) = search(&["--tree", "ip4"], input);
+        assert_eq!(
+            out,
+            "10.0.0.0/8\n\
+             \x20 10.0.0.1/32\n\
+             \x20 10.1.2.0/24\n\
+             \x20 10.1.3.0/24\n\
+             192.168.1.1/32\n"
+        );
+        assert_eq!(count, 8);
+        let (out, _) =
+            search(&["--tree", "--tree-depth=0", "10.1.0.0/16"], input);
+        assert_eq!(out, "10.1.2.0/24\n10.1.3.0/24\n");
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let input = b"\
+route 10.0.0.0/25 via 10.0.0.1
+route 10.0.0.128/25 via 192.168.1.1
+route 2001:db8::/33 2001:db8:8000::/33 10.0.0.0/24
+";
+        let (out, count) = search(&["--aggregate", "ip"], input);
+        assert_eq!(out, "10.0.0.0/24\n192.168.1.1/32\n2001:db8::/32\n");
+        assert_eq!(count, 7);
+    }
+
+    #[test]
+    fn test_report_other_family() {
+        let input = b"\
+listen 10.0.0.1 and 10.0.0.2
+listen 10.0.0.1 and 2001:db8::1
+listen 192.168.1.1 and 2001:db8::2 and ::1 too
+listen 2001:db8::3
+";
+        let (out, count) =
+            search(&["--report-other-family", "10.0.0.0/8"], input);
+        assert_eq!(out, "listen 10.0.0.1 and 2001:db8::1\n");
+        assert_eq!(count, 1);
+        let (out, _) =
+            search(&["--report-other-family", "-o", "-n", "ip4"], input);
+        assert_eq!(out, "2:2001:db8::1\n3:2001:db8::2\n3:::1\n");
+        let (out, _) =
+            search(&["--report-other-family", "-o", "2001:db8::/32"], input);
+        assert_eq!(out, "10.0.0.1\n192.168.1.1\n");
+    }
+
+    #[test]
+    fn test_single_needle_fast_path() {
+        let candidates: Vec<Net> = [
+            "10.0.0.1",
+            "10.0.0.0/8",
+            "10.1.0.0/16",
+            "10.0.0.0/7",
+            "11.0.0.0/24",
+            "::1",
+            "0.0.0.0/0",
+        ]
+        .into_iter()
+        .map(Net::from_str_unchecked)
+        .collect();
+        for needle in ["10.0.0.0/8", "10.0.0.1", "::/0"] {
+            for mode in ["contains", "within", "equals", "overlaps"] {
+                let single = parse(&[needle, "-m", mode]);
+                let twice = parse(&[&format!("{needle},{needle}"), "-m", mode]);
+                let single_matcher = NeedleMatcher::new(&single);
+                let twice_matcher = NeedleMatcher::new(&twice);
+                assert!(single_matcher.single_needle.is_some());
+                assert!(twice_matcher.single_needle.is_none());
+                for net in &candidates {
+                    // The general path asserts on host bits for
+                    // within; our candidates have none.
+                    assert_eq!(
+                        single_matcher.is_match(net),
+                        twice_matcher.is_match(net),
+                        "{net} {mode} {needle}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_count_by_needle() {
+        let (out, count) = search(
+            &["-c", "--by-needle", "10.0.0.0/8,10.0.0.0/24,192.168.0.0/16"],
+            FIREWALL_LOG,
+        );
+        // 10.0.0.x is attributed to the first needle only; a needle
+        // without matches is listed too.
+        assert_eq!(
+            out,
+            "10.0.0.0/8: 3
+10.0.0.0/24: 0
+192.168.0.0/16: 4
+"
+        );
+        assert_eq!(count, 7);
+
+        // Negative needles are not listed, and still reject matches.
+        let (out, _) =
+            search(&["-c", "--by-needle", "ip4,!10.0.0.2"], FIREWALL_LOG);
+        assert_eq!(
+            out,
+            "0.0.0.0/0: 7
+"
+        );
+
+        // An IP range is one needle, not one per network it is split into.
+        let (out, _) = search(
+            &["-c", "--by-needle", "10.0.0.1-10.0.0.20,192.168.0.0/16"],
+            FIREWALL_LOG,
+        );
+        assert_eq!(
+            out,
+            "10.0.0.1-10.0.0.20: 3
+192.168.0.0/16: 4
+"
+        );
+
+        assert!(
+            <Args as Parser>::try_parse_from(["ipgrep", "--by-needle", "ip"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_match_paths() {
+        let params = parse(&["--match-paths", "10.0.0.0/8"]);
+        let disp = Display::new();
+        let file = files::FileSource {
+            name: "logs/10.0.0.5/app.log".into(),
+            reader: Box::new(Cursor::new(Vec::new())),
+        };
+        let path_search = |params: &params::Parameters| {
+            let mut output = Vec::new();
+            let count = search_in_path(
+                &disp,
+                &file,
+                &make_scanner(params),
+                &NeedleMatcher::new(params),
+                params,
+                &mut output,
+            )
+            .expect("write failed");
+            (String::from_utf8(output).expect("utf8"), count)
+        };
+
+        let (out, count) = path_search(&params);
+        assert_eq!(out, "(path):logs/10.0.0.5/app.log\n");
+        assert_eq!(count, 1);
+
+        let params = parse(&["--match-paths", "-O24", "10.0.0.0/8"]);
+        let (out, _) = path_search(&params);
+        assert_eq!(out, "(path):logs/10.0.0.5/app.log:10.0.0.0/24\n");
+
+        let params = parse(&["--match-paths", "-c", "10.0.0.0/8"]);
+        assert_eq!(path_search(&params), (String::new(), 1));
+
+        let params = parse(&["--match-paths", "192.168.0.0/16"]);
+        assert_eq!(path_search(&params), (String::new(), 0));
+    }
+
+    #[test]
+    fn test_dedup_specific() {
+        let input = b"\
+inet 10.0.0.5/24 brd 10.0.0.255 peer 10.0.0.0/24
+route 10.0.0.0/16 10.0.0.0/24 10.0.1.1 10.0.1.1
+";
+        let (out, count) =
+            search(&["-o", "-a", "ip,iface", "10.0.0.0/8"], input);
+        assert_eq!(
+            out,
+            "10.0.0.5/24\n10.0.0.255\n10.0.0.0/24\n\
+             10.0.0.0/16\n10.0.0.0/24\n10.0.1.1\n10.0.1.1\n"
+        );
+        assert_eq!(count, 7);
+
+        // The interface is an IP (10.0.0.5) by default, so the /24 peer
+        // network that contains it is dropped. On the second line the
+        // /16 contains the others and the second 10.0.1.1 is a repeat.
+        let (out, count) = search(
+            &["-o", "-a", "ip,iface,net", "--dedup=specific", "10.0.0.0/8"],
+            input,
+        );
+        assert_eq!(out, "10.0.0.5/24\n10.0.0.255\n10.0.0.0/24\n10.0.1.1\n");
+        assert_eq!(count, 4);
+    }
+
+    #[test]
+    fn test_find_near_misses() {
+        let near_misses = |line: &[u8]| -> Vec<String> {
+            find_near_misses(line)
+                .into_iter()
+                .map(|(s, e)| String::from_utf8_lossy(&line[s..e]).into())
+                .collect()
+        };
+        assert_eq!(near_misses(b"from 10,0.0.1 ok"), ["10,0.0.1"]);
+        assert_eq!(near_misses(b"from 10.0.0.1 ok"), [] as [&str; 0]);
+        assert_eq!(
+            near_misses(b"v1.2.3 and 1.2.3.4.5, x"),
+            ["1.2.3", "1.2.3.4.5,"]
+        );
+        assert_eq!(near_misses(b"5,10.0.0.1 .5 5. end."), ["5,10.0.0.1"]);
+        assert_eq!(
+            near_misses(b"a,10.0.0.1 ::1,10.0.0.2,10.0.0.3"),
+            [] as [&str; 0]
+        );
+        // Whatever the needles or --accept, real addresses are no misses.
+        assert_eq!(
+            near_misses(b"10.0.0.1 10.0.0.0/8 10.0.0.0/255.0.0.0 ::1"),
+            [] as [&str; 0]
+        );
+        assert_eq!(near_misses(b""), [] as [&str; 0]);
+        assert_eq!(near_misses(b".1.2"), [".1.2"]);
+    }
+
+    #[test]
+    fn test_count_lines() {
+        let (_, counts) =
+            search_bytes(&["-c", "--count-ratio", "10.0.0.0/8"], FIREWALL_LOG);
+        assert_eq!((counts.matches, counts.lines), (3, 4));
+        let (_, counts) =
+            search_bytes(&["-c", "--count-ratio", "::/0"], FIREWALL_LOG);
+        assert_eq!((counts.matches, counts.lines), (0, 4));
+        let (_, counts) =
+            search_bytes(&["-c", "ip4"], b"no trailing LF 1.2.3.4");
+        assert_eq!((counts.matches, counts.lines), (1, 1));
+    }
+
+    #[test]
+    fn test_count_matches() {
+        // -c counts lines, like grep.
+        let (_, count) =
+            search(&["-c", "ip4"], b"10.0.0.1 10.0.0.2 10.0.0.3\n");
+        assert_eq!(count, 1);
+
+        let input = b"10.0.0.1 10.0.0.2 10.0.0.3\nnone\n10.0.0.4\n";
+        let (_, count) = search(&["-c", "ip4"], input);
+        assert_eq!(count, 2);
+        let (_, count) = search(&["-c", "--count-matches", "ip4"], input);
+        assert_eq!(count, 4);
+        assert!(
+            <Args as Parser>::try_parse_from([
+                "ipgrep",
+                "--count-matches",
+                "ip"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_approx_unique() {
+        let (out, count) = search(&["--approx-unique", "ip4"], FIREWALL_LOG);
+        assert_eq!(out, "~5 unique (estimate)\n");
+        assert_eq!(count, 8);
+        let (out, _) = search(&["--approx-unique", "ip6"], FIREWALL_LOG);
+        assert_eq!(out, "~0 unique (estimate)\n");
+    }
+
+    #[test]
+    fn test_parallel_search() {
+        // Many small files, some with matches and some without.
+        let base = make_test_dir("parallel");
+        for i in 0..300 {
+            let dir = base.join(format!("d{}", i % 7));
+            std::fs::create_dir_all(&dir).unwrap();
+            let content = match i % 3 {
+                0 => format!("host 10.0.{}.{} up\nnothing\n", i % 5, i % 250),
+                1 => format!("nothing\nhost 192.168.{}.1 down\n", i % 11),
+                _ => "nothing at all\n".to_string(),
+            };
+            std::fs::write(dir.join(format!("f{i}.log")), content).unwrap();
+        }
+        let dirs = [base.display().to_string()];
+
+        let search_all = |args: &[&str], threads: &str| -> (String, usize) {
+            let mut args = args.to_vec();
+            args.extend(["--threads", threads, "-r"]);
+            let params = parse(&args);
+            let file_iter = files::FileSourceIter::new()
+                .set_recursion(params.recursive)
+                .add_files(&dirs);
+            let disp = Display::new().show_filename(true).csv(params.csv);
+            let mut output = Vec::new();
+            let count =
+                search_files(&params, file_iter, &disp, &mut output).unwrap();
+            (String::from_utf8(output).expect("utf8"), count)
+        };
+
+        for args in [
+            &["ip4"][..],
+            &["-c", "10.0.0.0/8"],
+            &["-c", "--count-ratio", "10.0.0.0/8"],
+            &["-c", "--csv", "--count-ratio", "10.0.0.0/8"],
+            &["-c", "--csv", "--by-needle", "10.0.0.0/8"],
+            &["-l", "192.168.0.0/16"],
+            &["-o", "-n", "ip4"],
+            &["-O16", "ip4"],
+            &["-C1", "10.0.0.0/8"],
+            &["--tree", "ip4"],
+            &["-c", "--by-needle", "10.0.0.0/8,192.168.0.0/16"],
+            &["--approx-unique", "ip4"],
+            &["-q", "ip4"],
+            &["-q", "ip6"],
+            &["-l", "172.16.0.0/12"],
+            &["-L", "172.16.0.0/12"],
+        ] {
+            let expected = search_all(args, "1");
+            for threads in ["2", "8"] {
+                assert_eq!(
+                    search_all(args, threads),
+                    expected,
+                    "{args:?} with {threads} threads"
+                );
+            }
+        }
+        assert_eq!(search_all(&["-l", "ip4"], "1").0.lines().count(), 200);
+
+        // The CSV header is printed once, and not for the human format.
+        let (out, _) = search_all(&["-c", "--csv", "ip4"], "4");
+        assert_eq!(out.lines().count(), 301);
+        assert_eq!(out.lines().filter(|l| l.starts_with("path,")).count(), 1);
+        assert!(out.starts_with("path,count\n"));
+        let (out, _) = search_all(&["-c", "--csv", "--no-header", "ip4"], "4");
+        assert_eq!(out.lines().count(), 300);
+        let (out, _) = search_all(&["-c", "ip4"], "1");
+        assert!(!out.contains("path,"));
+        let (out, _) = search_all(&["-l", "-c", "--csv", "ip4"], "1");
+        assert_eq!(out.lines().count(), 200);
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    /// A writer whose output another thread can look at.
+    #[derive(Clone, Default)]
+    struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Search a FIFO (after the other files), and return whether the
+    /// match on its first line was written before the FIFO was closed.
+    fn is_streamed(name: &str, args: &[&str], others: &[&str]) -> bool {
+        let dir = make_test_dir(name);
+        let fifo = dir.join("fifo");
+        let status = Command::new("mkfifo").arg(&fifo).status().unwrap();
+        assert!(status.success());
+        let mut args: Vec<String> = args.iter().map(|&a| a.into()).collect();
+        for other in others {
+            std::fs::write(dir.join(other), "").unwrap();
+            args.push(dir.join(other).display().to_string());
+        }
+        args.push(fifo.display().to_string());
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let params = parse(&args);
+
+        let output = SharedOutput::default();
+        let feeder = {
+            let output = output.clone();
+            thread::spawn(move || {
+                let mut fifo = std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(&fifo)
+                    .unwrap();
+                fifo.write_all(b"10.0.0.1\n").unwrap();
+                let deadline = Instant::now() + Duration::from_secs(5);
+                while output.0.lock().unwrap().is_empty()
+                    && Instant::now() < deadline
+                {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                let streamed = !output.0.lock().unwrap().is_empty();
+                fifo.write_all(b"10.0.0.2\n").unwrap();
+                streamed
+            })
+        };
+        run_with(&params, make_file_iter(&params), false, &mut output.clone())
+            .expect("write failed");
+        let streamed = feeder.join().unwrap();
+        assert_eq!(*output.0.lock().unwrap(), b"10.0.0.1\n10.0.0.2\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+        streamed
+    }
+
+    #[test]
+    fn test_line_buffered_threads() {
+        // With --line-buffered, a match is written right away, even
+        // when there are threads to search the files on.
+        let args = &["-h", "-j4", "--line-buffered", "10.0.0.0/8"];
+        assert!(is_streamed("line-buffered", args, &["a.log", "b.log"]));
+    }
+
+    #[test]
+    fn test_single_file_threads() {
+        // A single file (or stdin) is searched as it is read, like with
+        // -j1, so tail -f | ipgrep shows the matches right away.
+        let args = &["-j4", "10.0.0.0/8"];
+        assert!(is_streamed("single-file", args, &[]));
+    }
+
+    #[test]
+    fn test_heading() {
+        let files = &[
+            ("a.log", "x 10.0.0.1\ny\nz 10.0.0.2\n"),
+            ("b.log", "nothing\n"),
+            ("c.log", "10.0.0.3\n"),
+        ];
+        assert_eq!(
+            run_golden("heading", &["--heading", "-n", "ip"], files),
+            "a.log\n1:x 10.0.0.1\n3:z 10.0.0.2\nc.log\n1:10.0.0.3\n"
+        );
+        assert_eq!(
+            run_golden("heading", &["--heading", "-o", "ip"], files),
+            "a.log\n10.0.0.1\n10.0.0.2\nc.log\n10.0.0.3\n"
+        );
+        // The heading separates the files; no "--" between them.
+        assert_eq!(
+            run_golden("heading", &["--heading", "-C1", "10.0.0.2"], files),
+            "a.log\ny\nz 10.0.0.2\n"
+        );
+        assert_eq!(
+            run_golden("heading", &["--heading", "-A1", "ip"], files),
+            "a.log\nx 10.0.0.1\ny\nz 10.0.0.2\nc.log\n10.0.0.3\n"
+        );
+        // Other output styles are not affected.
+        assert_eq!(
+            run_golden("heading", &["--heading", "-c", "ip"], files),
+            "a.log:2\nb.log:0\nc.log:1\n"
+        );
+    }
+
+    #[test]
+    fn test_with_filename() {
+        let files = [("a.log", "from 10.0.0.1\n")];
+        let out = run_golden("with-filename", &["-H", "-n", "ip4"], &files);
+        assert_eq!(out, "a.log:1:from 10.0.0.1\n");
+        let out = run_golden("without-filename", &["-n", "ip4"], &files);
+        assert_eq!(out, "1:from 10.0.0.1\n");
+
+        // Also for stdin.
+        let params = parse(&["-H", "ip4"]);
+        assert!(show_filename(&params, &make_file_iter(&params)));
+    }
+
+    #[test]
+    fn test_count_no_filename() {
+        let base = make_test_dir("count-h");
+        std::fs::create_dir_all(base.join("sub")).unwrap();
+        std::fs::write(base.join("a.log"), "10.0.0.1 10.0.0.2\n").unwrap();
+        std::fs::write(base.join("sub/b.log"), "10.0.0.3\n").unwrap();
+        let dirs = [base.display().to_string()];
+
+        let count_all = |args: &[&str]| -> String {
+            let params = parse(args);
+            let file_iter = files::FileSourceIter::new()
+                .set_recursion(params.recursive)
+                .add_files(&dirs);
+            let disp = Display::new()
+                .show_filename(show_filename(&params, &file_iter));
+            let mut output = Vec::new();
+            search_files(&params, file_iter, &disp, &mut output).unwrap();
+            let mut lines: Vec<String> = String::from_utf8(output)
+                .unwrap()
+                .lines()
+                .map(Into::into)
+                .collect();
+            lines.sort();
+            lines.join("\n")
+        };
+
+        // Recursion shows filenames, unless -h is given.
+        let prefix = format!("{}/", base.display());
+        assert_eq!(
+            count_all(&["-rc", "10.0.0.0/8"]),
+            format!("{prefix}a.log:1\n{prefix}sub/b.log:1")
+        );
+        assert_eq!(count_all(&["-rc", "-h", "10.0.0.0/8"]), "1\n1");
+        assert_eq!(count_all(&["-r", "-c", "-h", "192.168.0.0/16"]), "0\n0");
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_compare() {
+        // Overlapping lists.
+        let (out, count) = search(
+            &["--compare", "10.0.0.0/8", "10.0.0.3,192.168.0.0/16"],
+            FIREWALL_LOG,
+        );
+        assert_eq!(
+            out,
+            "only in A:\n\
+             \x20 10.0.0.1/32\n\
+             \x20 10.0.0.2/32\n\
+             only in B:\n\
+             \x20 192.168.1.1/32\n\
+             in both:\n\
+             \x20 10.0.0.3/32\n"
+        );
+        assert_eq!(count, 7);
+
+        // Disjoint lists, with a negative needle.
+        let (out, _) = search(
+            &["--compare", "172.16.0.0/12", "ip4,!rfc1918"],
+            FIREWALL_LOG,
+        );
+        assert_eq!(out, "only in A:\n  172.16.0.1/32\nonly in B:\nin both:\n");
+
+        // A needle list file; the needles positional is a haystack.
+        let dir = make_test_dir("compare");
+        let list = dir.join("a.txt");
+        std::fs::write(&list, "10.0.0.1\n10.0.0.2\n").unwrap();
+        let list = list.display().to_string();
+        let params = parse(&["--compare", &list, "10.0.0.3", "file1", "file2"]);
+        assert_eq!(params.haystack_filenames, ["file1", "file2"]);
+        let (a, b) = params.compare_needles.as_ref().unwrap();
+        assert_eq!((a.len(), b.len()), (2, 1));
+        let (out, _) = search(&["--compare", &list, "10.0.0.3"], FIREWALL_LOG);
+        assert_eq!(
+            out,
+            "only in A:\n  10.0.0.1/32\n  10.0.0.2/32\n\
+             only in B:\n  10.0.0.3/32\nin both:\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unique_prefix() {
+        let input = b"\
+10.0.0.1 10.0.0.2 10.0.0.0/24
+10.0.1.1 and 10.0.0.3 and 10.0.0.0/16
+2001:db8::1 2001:db8::2
+";
+        let (out, count) = search(&["--unique-prefix", "24", "ip"], input);
+        assert_eq!(
+            out,
+            "10.0.0.0/24\n10.0.1.0/24\n10.0.0.0/16\n2001:d00::/24\n"
+        );
+        assert_eq!(count, 8, "the count is not affected");
+        let (out, _) = search(&["--unique-prefix", "8", "-n", "ip4"], input);
+        assert_eq!(out, "1:10.0.0.0/8\n");
+
+        let args = ["ipgrep", "--unique-prefix=24", "-O24", "ip"];
+        assert!(<Args as Parser>::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn test_unique() {
+        let input = b"\
+10.0.0.2 10.0.0.1 10.0.0.2
+2001:db8::1 10.0.0.1 2001:DB8:0::1 10.0.0.3
+";
+        let (out, count) = search(&["--unique", "ip"], input);
+        assert_eq!(out, "10.0.0.2\n10.0.0.1\n2001:db8::1\n10.0.0.3\n");
+        assert_eq!(count, 7, "the count is not affected");
+        let (out, _) = search(&["--unique", "-O16", "ip4"], input);
+        assert_eq!(out, "10.0.0.0/16\n");
+    }
+
+    #[test]
+    fn test_unique_cap() {
+        let input = b"\
+10.0.0.2 10.0.0.1 10.0.0.2
+10.0.0.3 10.0.0.1 10.0.0.3 10.0.0.2
+";
+        let (out, _) = search(&["--unique", "--unique-cap=2", "ip"], input);
+        assert_eq!(out, "10.0.0.2\n10.0.0.1\n10.0.0.3\n10.0.0.3\n");
+        let (out, _) = search(&["--unique", "--unique-cap=9", "ip"], input);
+        assert_eq!(out, "10.0.0.2\n10.0.0.1\n10.0.0.3\n");
+        let (out, _) =
+            search(&["--unique-prefix=24", "--unique-cap=1", "ip"], input);
+        assert_eq!(out, "10.0.0.0/24\n");
+    }
+
+    #[test]
+    fn test_resolve() {
+        let input =
+            b"from 127.0.0.1 to 192.0.2.1 in 127.0.0.0/8\nx\n127.0.0.1\n";
+        let params = parse(&["--resolve", "-n", "ip4"]);
+        let disp = Display::new().show_lineno(true);
+        let mut file = files::FileSource {
+            name: "(stdin)".into(),
+            reader: Box::new(Cursor::new(input.to_vec())),
+        };
+        let mut collector = Collector::new(&params);
+        // Resolve without /etc/hosts or DNS.
+        collector.resolver =
+            Some(Resolver::new(params.diagnostics.clone()).with_lookup(
+                |addr| Ok(addr.is_loopback().then(|| "localhost".into())),
+            ));
+        let mut output = Vec::new();
+        let counts = search_in_file(
+            &disp,
+            &mut file,
+            &make_scanner(&params),
+            &NeedleMatcher::new(&params),
+            &params,
+            &mut collector,
+            &mut output,
+        )
+        .unwrap();
+        // The lines are printed in order, once their hostnames are known.
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "1:127.0.0.1 (localhost)\n1:192.0.2.1\n1:127.0.0.0/8\n\
+             3:127.0.0.1 (localhost)\n"
+        );
+        assert_eq!(counts.matches, 4);
+    }
+
+    #[test]
+    fn test_sort() {
+        let input = b"\
+10.0.0.10 ::1 10.0.0.9
+2001:db8::/32 10.0.0.9 10.0.0.0/8 9.255.255.255
+";
+        let (out, count) = search(&["--sort", "ip"], input);
+        assert_eq!(
+            out,
+            "9.255.255.255\n10.0.0.0/8\n10.0.0.9\n10.0.0.9\n10.0.0.10\n\
+             ::1\n2001:db8::/32\n"
+        );
+        assert_eq!(count, 7);
+        let (out, _) = search(&["--sort", "--unique", "-n", "ip4"], input);
+        assert_eq!(out, "9.255.255.255\n10.0.0.0/8\n10.0.0.9\n10.0.0.10\n");
+        let (out, _) = search(&["--sort", "--unique", "-O16", "ip4"], input);
+        assert_eq!(out, "9.255.0.0/16\n10.0.0.0/8\n10.0.0.0/16\n");
+    }
+
+    #[test]
+    fn test_only_matching_per_line() {
+        // Each match on its own line, in source order, each with its own
+        // prefix. Downstream sort/uniq pipelines rely on this.
+        let input = b"x\n10.0.0.3 10.0.0.1, 10.0.0.2\n";
+        let (out, count) = search(&["-o", "ip4"], input);
+        assert_eq!(out, "10.0.0.3\n10.0.0.1\n10.0.0.2\n");
+        assert_eq!(count, 3);
+        let (out, _) = search(&["-on", "ip4"], input);
+        assert_eq!(out, "2:10.0.0.3\n2:10.0.0.1\n2:10.0.0.2\n");
+
+        // With filenames, like when searching more than one file.
+        let params = parse(&["-on", "ip4"]);
+        let disp = Display::new().show_filename(true).show_lineno(true);
+        let mut file = files::FileSource {
+            name: "log.txt".into(),
+            reader: Box::new(Cursor::new(input.to_vec())),
+        };
+        let mut output = Vec::new();
+        search_in_file(
+            &disp,
+            &mut file,
+            &make_scanner(&params),
+            &NeedleMatcher::new(&params),
+            &params,
+            &mut Collector::new(&params),
+            &mut output,
+        )
+        .expect("write failed");
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "log.txt:2:10.0.0.3\nlog.txt:2:10.0.0.1\nlog.txt:2:10.0.0.2\n"
+        );
+    }
+
+    #[test]
+    fn test_next_line() {
+        // Return all lines, and how many of them were borrowed.
+        let read_all = |reader: &mut dyn BufRead, max: u64| {
+            let (mut lines, mut borrowed) = (Vec::new(), 0);
+            let mut buf = Vec::new();
+            loop {
+                match next_line(reader, b'\n', max, &mut buf).unwrap() {
+                    NextLine::Line(line, len) => {
+                        lines.push(String::from_utf8(line.to_vec()).unwrap());
+                        borrowed += usize::from(len != 0);
+                        reader.consume(len);
+                    }
+                    NextLine::CutShort => lines.push("(cut)".into()),
+                    NextLine::Eof => break,
+                }
+            }
+            (lines, borrowed)
+        };
+        let input = b"10.0.0.1\nsome longer line\n\nlast";
+        let expected = ["10.0.0.1\n", "some longer line\n", "\n", "last"];
+
+        // A mapped file is a single buffer: only the last line is copied.
+        let (lines, borrowed) = read_all(&mut Cursor::new(input), u64::MAX);
+        assert_eq!(lines, expected);
+        assert_eq!(borrowed, 3);
+
+        // Lines that cross the buffer are copied, with the same result.
+        let mut reader = io::BufReader::with_capacity(4, &input[..]);
+        let (lines, borrowed) = read_all(&mut reader, u64::MAX);
+        assert_eq!(lines, expected);
+        assert_eq!(borrowed, 1);
+
+        // A limit in the middle of a line cuts it short, both ways. The
+        // limit is per call, so the rest of it is the next line.
+        let expected = ["10.0.0.1\n", "(cut)", "line\n", "\n", "last"];
+        let (lines, _) = read_all(&mut Cursor::new(input), 12);
+        assert_eq!(lines, expected);
+        let mut reader = io::BufReader::with_capacity(4, &input[..]);
+        let (lines, _) = read_all(&mut reader, 12);
+        assert_eq!(lines, expected);
+    }
+
+    #[test]
+    fn test_scan_limit() {
+        let input = b"10.0.0.1\n10.0.0.2\n10.0.0.3\n";
+        let (out, count) = search(&["--scan-limit=18", "ip"], input);
+        assert_eq!(out, "10.0.0.1\n10.0.0.2\n");
+        assert_eq!(count, 2, "a truncated file still has its matches");
+        // A line cut short by the limit is skipped, not matched partially.
+        let (out, _) = search(&["--scan-limit=17", "-o", "ip"], input);
+        assert_eq!(out, "10.0.0.1\n");
+        let (out, _) = search(&["--scan-limit=16", "-o", "ip"], input);
+        assert_eq!(out, "10.0.0.1\n");
+        // An unterminated last line that fits is scanned whole.
+        let (out, _) =
+            search(&["--scan-limit=17", "ip"], b"10.0.0.1\n10.0.0.2");
+        assert_eq!(out, "10.0.0.1\n10.0.0.2");
+        let (out, _) = search(&["-s", "--scan-limit=1000", "ip"], input);
+        assert_eq!(out, "10.0.0.1\n10.0.0.2\n10.0.0.3\n");
+
+        // The notice is a warning, which -q silences; -s drops it too.
+        let diagnostics = Arc::new(Collect::default());
+        let mut params = parse(&["--scan-limit=18", "ip"]);
+        params.diagnostics = diagnostics.clone();
+        search_params(&params, input);
+        assert_eq!(
+            diagnostics.take(),
+            ["(stdin): scan limit of 18 bytes reached (truncated)"]
+        );
+        params.no_messages = true;
+        search_params(&params, input);
+        assert!(diagnostics.take().is_empty());
+    }
+
+    #[test]
+    fn test_no_messages_skipped_files() {
+        use std::os::unix::net::UnixListener;
+
+        // The warning about a skipped special file is dropped by -s, like
+        // the messages about unreadable files.
+        let base = make_test_dir("no-messages");
+        let _listener = UnixListener::bind(base.join("sock")).unwrap();
+        let dir = base.display().to_string();
+        let diagnostics = Arc::new(Collect::default());
+        let warnings = |args: &[&str]| {
+            let mut params = parse(args);
+            params.diagnostics = diagnostics.clone();
+            make_file_iter(&params).for_each(drop);
+            diagnostics.take()
+        };
+        let sock = base.join("sock").display().to_string();
+        assert_eq!(
+            warnings(&["-r", "ip", &dir]),
+            [format!("{sock}: special file (skipping)")]
+        );
+        assert!(warnings(&["-r", "-s", "ip", &dir]).is_empty());
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_output_format_json() {
+        let input = b"x\nfrom 10.0.0.1 to 192.168.1.1\n";
+        let (out, count) =
+            search(&["--output-format=json", "-m", "overlaps", "ip4"], input);
+        assert_eq!(
+            out,
+            "{\"file\":\"(stdin)\",\"line\":2,\"column\":5,\"match\":\"10.0.0.1\",\
+             \"network\":\"10.0.0.1/32\",\"family\":\"ipv4\"}\n\
+             {\"file\":\"(stdin)\",\"line\":2,\"column\":17,\"match\":\"192.168.1.1\",\
+             \"network\":\"192.168.1.1/32\",\"family\":\"ipv4\"}\n"
+        );
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn test_binary_files() {
+        let input = b"\x7fELF\0\0 10.0.0.1\nx\n10.0.0.2\n";
+        let (out, count) = search(&["ip4"], input);
+        assert_eq!(out, "Binary file (stdin) matches\n");
+        assert_eq!(count, 1);
+        let (out, _) = search(&["-o", "10.0.0.2"], input);
+        assert_eq!(out, "Binary file (stdin) matches\n");
+        let (out, count) = search(&["-c", "ip4"], input);
+        assert_eq!((out.as_str(), count), ("", 2), "counts are unaffected");
+        let (out, count) =
+            search(&["--binary-files=without-match", "ip4"], input);
+        assert_eq!((out.as_str(), count), ("", 0));
+        let (out, count) = search(&["--binary-files=text", "-o", "ip4"], input);
+        assert_eq!((out.as_str(), count), ("10.0.0.1\n10.0.0.2\n", 2));
+        let (out, _) = search(&["192.168.0.0/16"], input);
+        assert_eq!(out, "", "no message without a match");
+    }
+
+    #[test]
+    fn test_max_count() {
+        let input = b"10.0.0.1 10.0.0.2\nx\n10.0.0.3\ny\nz\n10.0.0.4\n";
+        let (out, counts) = search_bytes(&["-M2", "ip"], input);
+        assert_eq!(out, b"10.0.0.1 10.0.0.2\n10.0.0.3\n");
+        assert_eq!(counts.matches, 3, "the cap is on lines, not addresses");
+        assert_eq!(counts.lines, 3, "reading stops at the last match");
+        // -c counts at most the capped lines; --count-matches counts all
+        // addresses on them.
+        let (_, count) = search(&["-c", "-M2", "ip"], input);
+        assert_eq!(count, 2);
+        let (_, count) = search(&["-c", "--count-matches", "-M2", "ip"], input);
+        assert_eq!(count, 3);
+        // Trailing context is still printed.
+        let (out, _) = search(&["-M1", "-A1", "ip"], input);
+        assert_eq!(out, "10.0.0.1 10.0.0.2\nx\n");
+        let (out, count) = search(&["-M2", "-v", "ip"], input);
+        assert_eq!((out.as_str(), count), ("x\ny\n", 2));
+        let (_, count) = search(&["-M0", "ip"], input);
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn test_ipv4_mapped() {
+        let input = b"::FFFF:10.0.0.1\n::ffff:10.0.0.2\n10.0.0.3\n::1\n";
+        let (out, _) = search(&["--ipv4-mapped", "-o", "10.0.0.0/8"], input);
+        assert_eq!(out, "::FFFF:10.0.0.1\n::ffff:10.0.0.2\n10.0.0.3\n");
+        let (out, _) = search(&["-o", "10.0.0.0/8"], input);
+        assert_eq!(out, "10.0.0.3\n");
+        let (out, _) = search(&["-o", "::ffff:0:0/96"], input);
+        assert_eq!(out, "::FFFF:10.0.0.1\n::ffff:10.0.0.2\n");
+    }
+
+    #[test]
+    fn test_map_v4() {
+        let input = b"::FFFF:10.0.0.1\n10.0.0.2\n::1\n192.168.0.1\n";
+        let (out, _) = search(&["--map-v4", "-o", "10.0.0.0/8"], input);
+        assert_eq!(out, "::FFFF:10.0.0.1\n10.0.0.2\n");
+        let (out, _) =
+            search(&["--map-v4", "-o", "::ffff:10.0.0.0/104"], input);
+        assert_eq!(out, "::FFFF:10.0.0.1\n10.0.0.2\n");
+        // IPv4 is not in ::/0, but mapped IPv4 still is.
+        let (out, _) = search(&["--map-v4", "-o", "ip6,!10.0.0.0/8"], input);
+        assert_eq!(out, "::1\n");
+        let (out, _) = search(&["--map-v4", "-o", "ip6"], input);
+        assert_eq!(out, "::FFFF:10.0.0.1\n::1\n");
+        let (out, _) = search(&["-o", "::ffff:10.0.0.0/104"], input);
+        assert_eq!(out, "::FFFF:10.0.0.1\n");
+    }
+
+    #[test]
+    fn test_ports() {
+        let input = b"ssh 10.0.0.1:22\nweb [2001:db8::1]:443\nno 10.0.0.2\n";
+        let (out, _) = search(&["--with-ports", "-o", "ip"], input);
+        assert_eq!(out, "10.0.0.1:22\n[2001:db8::1]:443\n10.0.0.2\n");
+        let (out, count) = search(&["--port=1-100", "ip"], input);
+        assert_eq!((out.as_str(), count), ("ssh 10.0.0.1:22\n", 1));
+        let (out, _) = search(&["--port=443", "-o", "ip"], input);
+        assert_eq!(out, "[2001:db8::1]:443\n");
+    }
+
+    #[test]
+    fn test_match_all() {
+        let input = b"a 10.0.0.1\n\
+                      b 10.0.0.2 2001:db8::2\n\
+                      c 2001:db8::3 192.168.0.3 10.0.0.3\n\
+                      d 2001:db8::4\n";
+        let needles = "10.0.0.0/8,2001:db8::/32";
+        let (_, count) = search(&["--any", needles], input);
+        assert_eq!(count, 6);
+        let (out, _) = search(&["--all", needles], input);
+        assert_eq!(
+            out,
+            "b 10.0.0.2 2001:db8::2\nc 2001:db8::3 192.168.0.3 10.0.0.3\n"
+        );
+        let (out, _) = search(&["--all", "-o", needles], input);
+        assert_eq!(out, "10.0.0.2\n2001:db8::2\n2001:db8::3\n10.0.0.3\n");
+        let (out, _) = search(&["--all", "-v", needles], input);
+        assert_eq!(out, "a 10.0.0.1\nd 2001:db8::4\n");
+        // The last of --all and --any wins.
+        let (_, count) = search(&["--all", "--any", needles], input);
+        assert_eq!(count, 6);
+
+        // An alias or a range needs one match, not one per network.
+        let input = b"a 10.0.0.1
+b 192.168.0.2 1.1.1.1
+c 10.0.0.3 1.1.1.1
+";
+        let (out, _) = search(&["--all", "rfc1918"], input);
+        assert_eq!(
+            out,
+            "a 10.0.0.1
+b 192.168.0.2 1.1.1.1
+c 10.0.0.3 1.1.1.1
+"
+        );
+        let (out, _) = search(&["--all", "rfc1918,1.1.1.1"], input);
+        assert_eq!(
+            out,
+            "b 192.168.0.2 1.1.1.1
+c 10.0.0.3 1.1.1.1
+"
+        );
+        let (out, _) = search(&["--all", "10.0.0.1-10.0.0.20,1.1.1.1"], input);
+        assert_eq!(
+            out,
+            "c 10.0.0.3 1.1.1.1
+"
+        );
+    }
+
+    #[test]
+    fn test_outside() {
+        let input = b"a 10.1.2.3 8.8.8.8\nb 10.0.0.1\nc 192.168.0.1\n";
+        let (out, _) = search(&["--outside", "10.0.0.0/8"], input);
+        assert_eq!(out, "a 10.1.2.3 8.8.8.8\nc 192.168.0.1\n");
+        let (out, _) = search(&["--outside", "-o", "10.0.0.0/8"], input);
+        assert_eq!(out, "8.8.8.8\n192.168.0.1\n");
+        // Unlike -v, which selects the lines without a match.
+        let (out, _) = search(&["-v", "10.0.0.0/8"], input);
+        assert_eq!(out, "c 192.168.0.1\n");
+        // Addresses of the other family are outside too.
+        let input = b"a ::1\nb 10.0.0.1 2001:db8::1\nc 10.0.0.2\n";
+        let (out, _) = search(&["--outside", "-o", "10.0.0.0/8"], input);
+        assert_eq!(out, "::1\n2001:db8::1\n");
+        let (out, _) = search(&["--outside", "-o", "ip6"], input);
+        assert_eq!(out, "10.0.0.1\n10.0.0.2\n");
+    }
+
+    #[test]
+    fn test_stats() {
+        let input = b"a 10.0.0.1 10.0.0.2\nb\nc 10.0.0.3\n";
+        let (_, counts) = search_bytes(&["--stats", "ip4"], input);
+        assert_eq!((counts.lines, counts.bytes), (3, input.len() as u64));
+
+        let mut stats = Stats::default();
+        stats.add(&counts, counts.matches);
+        stats.merge(&Stats {
+            files: 2,
+            lines: 10,
+            bytes: 100,
+            matches: 1,
+        });
+        assert_eq!(
+            stats.summary(Duration::from_millis(1500)),
+            "stats: 3 files, 13 lines, 133 bytes, 4 matches in 1.500s"
+        );
+    }
+
+    #[test]
+    fn test_nth() {
+        let input = b"a 10.0.0.1 b 192.168.0.1 c 10.0.0.3\nd 10.0.0.4\n";
+        let (out, _) = search(&["--nth=first", "-o", "ip"], input);
+        assert_eq!(out, "10.0.0.1\n10.0.0.4\n");
+        let (out, _) = search(&["--nth=last", "-o", "ip"], input);
+        assert_eq!(out, "10.0.0.3\n10.0.0.4\n");
+        // The position is taken before the needle comparison.
+        let (out, count) = search(&["--nth=2", "-o", "10.0.0.0/8"], input);
+        assert_eq!((out.as_str(), count), ("", 0));
+        let (out, _) = search(&["--nth=-2", "-o", "ip"], input);
+        assert_eq!(out, "192.168.0.1\n");
+        let (out, _) = search(&["--nth=4", "ip"], input);
+        assert_eq!(out, "");
+        let (out, _) = search(&["--nth=-4", "ip"], input);
+        assert_eq!(out, "");
+
+        // Addresses of the other family and of other forms count too.
+        let input = b"::1 10.0.0.1\n10.0.0.0/24 10.0.0.2\n10.0.0.3 ::2\n";
+        let (out, _) = search(&["--nth=first", "-o", "10.0.0.0/8"], input);
+        assert_eq!(out, "10.0.0.0/24\n10.0.0.3\n");
+        let (out, _) = search(&["--nth=2", "-o", "ip4"], input);
+        assert_eq!(out, "10.0.0.1\n10.0.0.2\n");
+        let input = b"10.0.0.1 10.0.0.0/24\n";
+        let (out, _) = search(&["--nth=1", "-o", "-a", "net", "ip4"], input);
+        assert_eq!(out, "");
+        let (out, _) = search(&["--nth=2", "-o", "-a", "net", "ip4"], input);
+        assert_eq!(out, "10.0.0.0/24\n");
+    }
+
+    #[test]
+    fn test_total() {
+        let files = &[
+            ("a.log", "10.0.0.1 10.0.0.2\n"),
+            ("b.log", "x\n"),
+            ("c.log", "10.0.0.3\n"),
+        ];
+        assert_eq!(run_golden("total", &["-c", "--total", "ip"], files), "2\n");
+        assert_eq!(
+            run_golden(
+                "total",
+                &["-c", "--count-matches", "--total", "ip"],
+                files
+            ),
+            "3\n"
+        );
+        assert_eq!(
+            run_golden("total-csv", &["-c", "--total", "--csv", "ip"], files),
+            "count\n2\n"
+        );
+        assert_eq!(
+            run_golden("total-none", &["-c", "--total", "::1"], files),
+            "0\n"
+        );
+    }
+
+    #[test]
+    fn test_label() {
+        let params = parse(&["--label=access.log", "-H", "ip"]);
+        let file = make_file_iter(&params).next().unwrap().unwrap();
+        assert_eq!(file.name, "access.log");
+        let params = parse(&["--label=access.log", "ip", "/dev/null"]);
+        let file = make_file_iter(&params).next().unwrap().unwrap();
+        assert_eq!(file.name, "/dev/null", "no stdin, no label");
+    }
+
+    #[test]
+    fn test_null_data() {
+        let input = b"./a 10.0.0.1\0./b\n10.0.0.2\0./c\0";
+        let (out, counts) = search_bytes(&["-z", "-n", "ip"], input);
+        assert_eq!(out, b"1:./a 10.0.0.1\x002:./b\n10.0.0.2\0");
+        assert_eq!(counts.matches, 2);
+        let (out, _) = search_bytes(&["-z", "-o", "ip"], input);
+        assert_eq!(out, b"10.0.0.1\x0010.0.0.2\0");
+        // Without -z, the NUL makes it a binary file.
+        let (out, _) = search_bytes(&["ip"], input);
+        assert_eq!(out, b"Binary file (stdin) matches\n");
+    }
+
+    #[test]
+    fn test_count_to_stderr() {
+        let base = make_test_dir("count-stderr");
+        std::fs::write(base.join("a.log"), "10.0.0.1 10.0.0.2\n").unwrap();
+        std::fs::write(base.join("b.log"), "x\n10.0.0.3\n::1\n").unwrap();
+        let dirs = [base.display().to_string()];
+
+        // Stdout has only the matches; the total goes to stderr, after
+        // the output is flushed.
+        let params = parse(&["--count-to-stderr", "-o", "-h", "-r", "ip4"]);
+        let file_iter = files::FileSourceIter::new()
+            .set_recursion(params.recursive)
+            .add_files(&dirs);
+        let mut output = BufWriter::new(Vec::new());
+        let mut errors = Vec::new();
+        let count =
+            search_files(&params, file_iter, &Display::new(), &mut output)
+                .unwrap();
+        finish(&params, count, &mut output, &mut errors).unwrap();
+        let mut lines: Vec<&str> = std::str::from_utf8(output.get_ref())
+            .unwrap()
+            .lines()
+            .collect();
+        lines.sort();
+        assert_eq!(lines, ["10.0.0.1", "10.0.0.2", "10.0.0.3"]);
+        assert_eq!(errors, b"3\n");
+
+        // Without the option, stderr stays empty.
+        let params = parse(&["-o", "ip4"]);
+        let mut errors = Vec::new();
+        finish(&params, count, &mut Vec::new(), &mut errors).unwrap();
+        assert!(errors.is_empty());
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_leading_zeros() {
+        let input = b"from 010.000.000.001 and 010.000.000.010\n";
+        let (out, _) = search(&["--leading-zeros", "-o", "10.0.0.0/8"], input);
+        assert_eq!(out, "010.000.000.001\n010.000.000.010\n");
+        let (out, count) = search(&["-o", "10.0.0.0/8"], input);
+        assert_eq!((out.as_str(), count), ("", 0));
+        // Decimal, not octal: 010 is not 8.
+        let (out, _) = search(&["--leading-zeros", "-O32", "ip"], input);
+        assert_eq!(out, "10.0.0.1/32\n10.0.0.10/32\n");
+        let (_, count) = search(&["--leading-zeros", "8.0.0.0/8"], input);
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn test_make_exec_args() {
+        let nets = [
+            Net::from_str_unchecked("10.0.0.1"),
+            Net::from_str_unchecked("10.1.0.0/16"),
+        ];
+        let nets: Vec<&Net> = nets.iter().collect();
+        let template = |cmd: &str| -> Vec<String> {
+            cmd.split_whitespace().map(String::from).collect()
+        };
+        assert_eq!(
+            make_exec_args(&template("fw add {addr} -m {}"), &nets[..1]),
+            ["fw", "add", "10.0.0.1", "-m", "10.0.0.1/32"]
+        );
+        assert_eq!(
+            make_exec_args(&template("fw --net={net}"), &nets),
+            ["fw", "--net=10.0.0.1/32", "--net=10.1.0.0/16"]
+        );
+        assert_eq!(
+            make_exec_args(&template("echo"), &nets),
+            ["echo", "10.0.0.1/32", "10.1.0.0/16"]
+        );
+    }
+
+    #[test]
+    fn test_exec() {
+        let base = make_test_dir("exec");
+        let input = base.join("input.log");
+        std::fs::write(&input, "10.0.0.1 10.0.0.2\n10.0.0.1 ::1\n").unwrap();
+        let files = [input.display().to_string()];
+
+        for (option, subdir) in [("--exec", "one"), ("--exec-batch", "batch")] {
+            let dir = base.join(subdir);
+            std::fs::create_dir(&dir).unwrap();
+            let cmd = format!("touch {}/{{addr}}", dir.display());
+            let params = parse(&[option, &cmd, "-c", "ip4"]);
+            let file_iter = files::FileSourceIter::new().add_files(&files);
+            let mut output = Vec::new();
+            search_files(&params, file_iter, &Display::new(), &mut output)
+                .unwrap();
+            let mut touched: Vec<String> = std::fs::read_dir(&dir)
+                .unwrap()
+                .map(|e| e.unwrap().file_name().into_string().unwrap())
+                .collect();
+            touched.sort();
+            assert_eq!(touched, ["10.0.0.1", "10.0.0.2"], "{option}");
+        }
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_host_index() {
+        let input = b"10.0.0.5 10.0.1.1 192.168.1.200\n2001:db8::ff\n";
+        let (out, _) = search(
+            &["--host-index", "-n", "10.0.0.0/16,192.168.1.0/24"],
+            input,
+        );
+        assert_eq!(out, "1:5\n1:257\n1:200\n");
+        let (out, _) = search(&["--host-index", "2001:db8::/64"], input);
+        assert_eq!(out, "255\n");
+        // An IP range counts from its first address, across the networks
+        // it is split into.
+        let (out, _) = search(&["--host-index", "10.0.0.3-10.0.1.10"], input);
+        assert_eq!(out, "2\n254\n");
+    }
+
+    #[test]
+    fn test_split_kind() {
+        let input = b"\
+host 10.0.0.1 and 10.0.0.2/32
+net 10.1.0.0/16 iface 10.2.0.1/16
+old 10.3.0.0/255.255.0.0 and 10.4.0.1/255.255.0.0
+";
+        let args = ["-c", "--split-kind", "-a", "ip,net,oldnet,iface", "ip"];
+        let (_, counts) = search_bytes(&args, input);
+        assert_eq!(counts.kinds, [2, 2, 2]);
+        assert_eq!(counts.matches, 6);
+
+        let mut output = Vec::new();
+        Display::new()
+            .show_filename(true)
+            .print_kind_counts(&mut output, "f", counts.kinds)
+            .unwrap();
+        assert_eq!(output, b"f:hosts=2 nets=2 ifaces=2\n");
+    }
+
+    #[test]
+    fn test_read_interrupted() {
+        // Returns Interrupted before each chunk, like a read on a network
+        // filesystem that gets a signal.
+        struct InterruptedReader {
+            chunks: Vec<&'static [u8]>,
+            interrupt: bool,
+        }
+        impl io::Read for InterruptedReader {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.interrupt = !self.interrupt;
+                if self.interrupt {
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                let Some(chunk) = self.chunks.pop() else {
+                    return Ok(0);
+                };
+                buf[..chunk.len()].copy_from_slice(chunk);
+                Ok(chunk.len())
+            }
+        }
+
+        let params = parse(&["-o", "ip4"]);
+        let reader = InterruptedReader {
+            chunks: vec![b"0.0.0.3\n", b"10.0.0.2\n1", b"10.0.0.1\n"],
+            interrupt: false,
+        };
+        let mut file = files::FileSource {
+            name: "(stdin)".into(),
+            reader: Box::new(io::BufReader::new(reader)),
+        };
+        let mut output = Vec::new();
+        let counts = search_in_file(
+            &Display::new(),
+            &mut file,
+            &make_scanner(&params),
+            &NeedleMatcher::new(&params),
+            &params,
+            &mut Collector::new(&params),
+            &mut output,
+        )
+        .expect("write failed");
+        assert_eq!(output, b"10.0.0.1\n10.0.0.2\n10.0.0.3\n");
+        assert_eq!(counts.lines, 3);
+    }
+
+    #[test]
+    fn test_print0() {
+        use std::process::Stdio;
+
+        let input = b"10.0.0.1 10.0.0.2\n10.0.0.0/8\n";
+        let (out, _) = search_bytes(&["--print0", "-o", "ip4"], input);
+        assert_eq!(out, b"10.0.0.1\x0010.0.0.2\x0010.0.0.0/8\x00");
+        let (out, _) = search_bytes(&["--print0", "-O24", "-n", "ip4"], input);
+        assert_eq!(out, b"1:10.0.0.0/24\x001:10.0.0.0/24\x002:10.0.0.0/8\x00");
+
+        // Round trip through xargs -0.
+        let (out, _) = search_bytes(&["--print0", "-o", "ip4"], input);
+        let mut xargs = Command::new("xargs")
+            .args(["-0", "echo"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("xargs");
+        xargs.stdin.take().unwrap().write_all(&out).unwrap();
+        let echoed = xargs.wait_with_output().unwrap().stdout;
+        assert_eq!(echoed, b"10.0.0.1 10.0.0.2 10.0.0.0/8\n");
+    }
+
+    #[test]
+    fn test_oldnet() {
+        let files = [(
+            "routes",
+            "route 10.0.0.0/255.255.254.0\n\
+             route 10.0.2.0/255.255.255.0\n\
+             host 10.0.1.1\n\
+             bad 10.0.0.0/255.0.255.0\n",
+        )];
+        let out = run_golden(
+            "oldnet",
+            &["-a", "oldnet", "-m", "equals", "10.0.0.0/23"],
+            &files,
+        );
+        assert_eq!(out, "route 10.0.0.0/255.255.254.0\n");
+        let out = run_golden(
+            "oldnet-o",
+            &["-a", "oldnet", "-o", "10.0.0.0/8"],
+            &files,
+        );
+        assert_eq!(out, "10.0.0.0/255.255.254.0\n10.0.2.0/255.255.255.0\n");
+        let out =
+            run_golden("oldnet-O", &["-a", "oldnet", "-O16", "ip4"], &files);
+        assert_eq!(out, "10.0.0.0/16\n10.0.0.0/16\n");
+    }
+
+    #[test]
+    fn test_golden_output() {
+        const LOG1: &str = "\
+start
+from 10.0.0.1 to 192.168.1.1
+nothing
+from 10.0.0.2 to 10.0.0.3
+end
+";
+        const LOG2: &str = "only 172.16.0.1 here\n";
+        let files = [("a.log", LOG1), ("b.log", LOG2)];
+
+        for (args, expected) in [
+            (
+                &["10.0.0.0/8"][..],
+                "a.log:from 10.0.0.1 to 192.168.1.1\n\
+                 a.log:from 10.0.0.2 to 10.0.0.3\n",
+            ),
+            (
+                &["-o", "-n", "10.0.0.0/8"],
+                "a.log:2:10.0.0.1\na.log:4:10.0.0.2\na.log:4:10.0.0.3\n",
+            ),
+            (&["-c", "ip4"], "a.log:2\nb.log:1\n"),
+            (&["-c", "--count-matches", "ip4"], "a.log:4\nb.log:1\n"),
+            (&["-l", "172.16.0.0/12"], "b.log\n"),
+            (&["-L", "172.16.0.0/12"], "a.log\n"),
+            (&["-L", "-Z", "10.0.0.0/8"], "b.log\0"),
+            (&["-L", "ip4"], ""),
+            (
+                &["-n", "-C1", "10.0.0.2"],
+                "a.log-3-nothing\n\
+                 a.log:4:from 10.0.0.2 to 10.0.0.3\n\
+                 a.log-5-end\n",
+            ),
+            (
+                // Overlapping context is not repeated.
+                &["-n", "-C1", "10.0.0.0/8"],
+                "a.log-1-start\n\
+                 a.log:2:from 10.0.0.1 to 192.168.1.1\n\
+                 a.log-3-nothing\n\
+                 a.log:4:from 10.0.0.2 to 10.0.0.3\n\
+                 a.log-5-end\n",
+            ),
+            (
+                &["-n", "-B1", "-A0", "192.168.0.0/16,10.0.0.3"],
+                "a.log-1-start\n\
+                 a.log:2:from 10.0.0.1 to 192.168.1.1\n\
+                 a.log-3-nothing\n\
+                 a.log:4:from 10.0.0.2 to 10.0.0.3\n",
+            ),
+            (
+                &["-n", "-B1", "ip4"],
+                "a.log-1-start\n\
+                 a.log:2:from 10.0.0.1 to 192.168.1.1\n\
+                 a.log-3-nothing\n\
+                 a.log:4:from 10.0.0.2 to 10.0.0.3\n\
+                 --\n\
+                 b.log:1:only 172.16.0.1 here\n",
+            ),
+            (
+                &["-n", "-A1", "ip4"],
+                "a.log:2:from 10.0.0.1 to 192.168.1.1\n\
+                 a.log-3-nothing\n\
+                 a.log:4:from 10.0.0.2 to 10.0.0.3\n\
+                 a.log-5-end\n\
+                 --\n\
+                 b.log:1:only 172.16.0.1 here\n",
+            ),
+        ] {
+            assert_eq!(
+                run_golden("golden", args, &files),
+                expected,
+                "{args:?}"
+            );
+        }
+
+        // A single file has no filename prefix.
+        assert_eq!(
+            run_golden("golden", &["-o", "192.168.0.0/16"], &files[..1]),
+            "192.168.1.1\n"
+        );
+    }
 }
@@ -0,0 +1,46 @@
+use std::fmt;
+use std::sync::Mutex;
+
+/// Where the warnings about the input go: skipped special files,
+/// directory loops, interfaces with host bits set and the --classful
+/// needles. The source is the file (or directory, or needle) the warning
+/// is about.
+pub trait Diagnostics: fmt::Debug + Send + Sync {
+    fn warn(&self, source: &str, message: &str);
+}
+
+/// Print the warnings on stderr. The default.
+#[derive(Debug, Default)]
+pub struct Stderr;
+
+impl Diagnostics for Stderr {
+    fn warn(&self, source: &str, message: &str) {
+        eprintln!("ipgrep: {source}: warning: {message}");
+    }
+}
+
+/// Drop the warnings, for -q/--quiet.
+#[derive(Debug, Default)]
+pub struct Silent;
+
+impl Diagnostics for Silent {
+    fn warn(&self, _source: &str, _message: &str) {}
+}
+
+/// Keep the warnings, as "source: message", to inspect them later.
+#[derive(Debug, Default)]
+pub struct Collect(Mutex<Vec<String>>);
+
+impl Collect {
+    /// Return the warnings collected so far, and forget them.
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut self.0.lock().expect("poisoned"))
+    }
+}
+
+impl Diagnostics for Collect {
+    fn warn(&self, source: &str, message: &str) {
+        let warning = format!("{source}: {message}");
+        self.0.lock().expect("poisoned").push(warning);
+    }
+}
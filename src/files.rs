@@ -1,12 +1,28 @@
 use std::collections::{HashSet, VecDeque};
+use std::ffi::OsStr;
 use std::fs::{self, File};
 use std::io::{self, BufRead, BufReader};
-use std::os::unix::fs::MetadataExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
 use std::path::PathBuf;
+use std::sync::Arc;
+
+use flate2::bufread::MultiGzDecoder;
+use memmap2::Mmap;
+
+use crate::diagnostics::{Diagnostics, Stderr};
 
 // Attempt at fixing that last bit of performance, but does not change wall
 // clock time in my /etc tests.
-const BUFSIZ: usize = 128 * 1024;
+pub const BUFSIZ: usize = 128 * 1024;
+
+// Regular files of at least this size are mapped into memory instead of
+// read in BUFSIZ chunks. For small files, the mmap setup costs more than
+// the few read() calls it saves.
+const MMAP_MIN_SIZE: u64 = 1024 * 1024;
+
+// The first bytes of a gzip file.
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum RecurseHaystacks {
@@ -15,9 +31,29 @@ pub enum RecurseHaystacks {
     FollowDirectorySymlinks,
 }
 
+/// What to do with binary files: files with a NUL in the first chunk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryFiles {
+    // Only say that the file matches, instead of printing lines
+    Binary,
+    // Treat as text
+    Text,
+    // Skip the file
+    WithoutMatch,
+}
+
+/// What to do with FIFOs, sockets and devices named on the command line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Devices {
+    // Open and read them like regular files
+    Read,
+    // Skip them
+    Skip,
+}
+
 pub struct FileSource {
     pub name: String,
-    pub reader: Box<dyn BufRead>,
+    pub reader: Box<dyn BufRead + Send>,
 }
 
 enum FileEntry {
@@ -35,7 +71,17 @@ struct DirId {
 pub struct FileSourceIter {
     stack: VecDeque<FileEntry>,
     recurse: RecurseHaystacks,
+    follow_arg_symlinks: bool,
     dirs_seen: HashSet<DirId>,
+    // Filename globs for the files found while recursing.
+    include_globs: Vec<String>,
+    exclude_globs: Vec<String>,
+    // The name of stdin, as shown with the matches.
+    stdin_label: String,
+    devices: Devices,
+    // Files found while recursing that are larger are skipped.
+    max_filesize: Option<u64>,
+    diagnostics: Arc<dyn Diagnostics>,
 }
 
 #[allow(clippy::new_without_default)]
@@ -45,7 +91,14 @@ impl FileSourceIter {
         FileSourceIter {
             stack: VecDeque::new(),
             recurse: RecurseHaystacks::No,
+            follow_arg_symlinks: true,
             dirs_seen: HashSet::<DirId>::new(),
+            include_globs: Vec::new(),
+            exclude_globs: Vec::new(),
+            stdin_label: "(stdin)".into(),
+            devices: Devices::Read,
+            max_filesize: None,
+            diagnostics: Arc::new(Stderr),
         }
     }
 
@@ -80,6 +133,51 @@ impl FileSourceIter {
         self
     }
 
+    /// Whether to follow symlinks in the files added with add_files().
+    /// Set this before adding the files. Defaults to true, like GNU grep.
+    pub fn set_follow_arg_symlinks(mut self, follow: bool) -> Self {
+        self.follow_arg_symlinks = follow;
+        self
+    }
+
+    /// Only search the files found while recursing whose name matches
+    /// any of the include globs (if there are any) and none of the
+    /// exclude globs. Directories are always searched.
+    pub fn set_globs(mut self, include: &[String], exclude: &[String]) -> Self {
+        self.include_globs = include.to_vec();
+        self.exclude_globs = exclude.to_vec();
+        self
+    }
+
+    /// Show stdin as label instead of "(stdin)".
+    pub fn set_stdin_label(mut self, label: &str) -> Self {
+        self.stdin_label = label.into();
+        self
+    }
+
+    /// Whether to read or skip the special files named with add_files().
+    /// Those found while recursing are always skipped.
+    pub fn set_devices(mut self, devices: Devices) -> Self {
+        self.devices = devices;
+        self
+    }
+
+    /// Skip the files found while recursing that are larger than size
+    /// bytes. Files named with add_files() are always searched.
+    pub fn set_max_filesize(mut self, size: Option<u64>) -> Self {
+        self.max_filesize = size;
+        self
+    }
+
+    /// Where the warnings about skipped files and directories go.
+    pub fn set_diagnostics(
+        mut self,
+        diagnostics: Arc<dyn Diagnostics>,
+    ) -> Self {
+        self.diagnostics = diagnostics;
+        self
+    }
+
     /// Add stdin ("-") to the stack.
     pub fn add_stdin(mut self) -> Self {
         self.stack.push_back(FileEntry::Stdin);
@@ -93,8 +191,11 @@ impl FileSourceIter {
         S: AsRef<str>,
     {
         for f in files {
-            self.stack
-                .push_back(FileEntry::FollowPath(PathBuf::from(f.as_ref())));
+            let path = PathBuf::from(f.as_ref());
+            self.stack.push_back(match self.follow_arg_symlinks {
+                true => FileEntry::FollowPath(path),
+                false => FileEntry::NoFollowPath(path),
+            });
         }
         self
     }
@@ -126,7 +227,7 @@ impl FileSourceIter {
     /// Return Stdin file handle as item.
     fn next_stdin(&self) -> Option<<Self as Iterator>::Item> {
         Some(Ok(FileSource {
-            name: "(stdin)".into(),
+            name: self.stdin_label.clone(),
             reader: Box::new(BufReader::with_capacity(BUFSIZ, io::stdin())),
         }))
     }
@@ -157,14 +258,14 @@ impl FileSourceIter {
             if self.dirs_seen.insert(dir_id) {
                 self.next_path_dir(path)
             } else {
-                eprintln!(
-                    "ipgrep: {}: warning: recursive directory loop",
-                    path.display()
+                self.diagnostics.warn(
+                    &path.display().to_string(),
+                    "recursive directory loop",
                 );
                 None
             }
         } else {
-            self.next_path_file(path)
+            self.next_path_file(path, stat.file_type())
         }
     }
 
@@ -210,6 +311,23 @@ impl FileSourceIter {
                 // entries.sort_by_key(|e| e.file_name());
                 for entry in entries.flatten() {
                     let child_path = entry.path();
+                    if !self.is_glob_selected(&entry.file_name())
+                        && !self.is_followed_dir(&entry)
+                    {
+                        continue;
+                    }
+                    // Reading a FIFO or device could block forever. Only
+                    // search those when they're named explicitly.
+                    if self.is_special_file(&entry) {
+                        self.diagnostics.warn(
+                            &child_path.display().to_string(),
+                            "special file (skipping)",
+                        );
+                        continue;
+                    }
+                    if self.is_too_large(&entry) {
+                        continue; // silently, like the globs
+                    }
                     self.stack.push_back(match self.recurse {
                         RecurseHaystacks::FollowDirectories => {
                             FileEntry::NoFollowPath(child_path)
@@ -229,21 +347,186 @@ impl FileSourceIter {
         }
     }
 
-    /// Return real file handle.
+    /// Whether a filename passes the --include/--exclude globs.
+    fn is_glob_selected(&self, name: &OsStr) -> bool {
+        let name = name.as_bytes();
+        let matches = |glob: &String| glob_match(glob.as_bytes(), name);
+        (self.include_globs.is_empty()
+            || self.include_globs.iter().any(matches))
+            && !self.exclude_globs.iter().any(matches)
+    }
+
+    /// Whether a directory entry is (or, with -R, links to) a FIFO,
+    /// socket or device node.
+    fn is_special_file(&self, entry: &fs::DirEntry) -> bool {
+        let file_type = match entry.file_type() {
+            Ok(ft)
+                if ft.is_symlink()
+                    && self.recurse
+                        == RecurseHaystacks::FollowDirectorySymlinks =>
+            {
+                match fs::metadata(entry.path()) {
+                    Ok(stat) => stat.file_type(),
+                    Err(_) => return false, // reported when opened
+                }
+            }
+            Ok(ft) => ft,
+            Err(_) => return false,
+        };
+        is_special(file_type)
+    }
+
+    /// Whether a directory entry is (or, with -R, links to) a regular
+    /// file larger than --max-filesize.
+    fn is_too_large(&self, entry: &fs::DirEntry) -> bool {
+        let Some(max_filesize) = self.max_filesize else {
+            return false;
+        };
+        let stat = match self.recurse {
+            RecurseHaystacks::FollowDirectorySymlinks => {
+                fs::metadata(entry.path())
+            }
+            _ => entry.metadata(),
+        };
+        // Stat errors are reported when opened.
+        stat.is_ok_and(|stat| stat.is_file() && stat.len() > max_filesize)
+    }
+
+    /// Whether a directory entry is a directory we would recurse into.
+    fn is_followed_dir(&self, entry: &fs::DirEntry) -> bool {
+        match entry.file_type() {
+            Ok(ft) if ft.is_dir() => true,
+            Ok(ft) if ft.is_symlink() => {
+                self.recurse == RecurseHaystacks::FollowDirectorySymlinks
+                    && entry.path().is_dir()
+            }
+            _ => false,
+        }
+    }
+
+    /// Return real file handle. Gzipped files are decompressed, and large
+    /// files are mapped into memory.
     fn next_path_file(
         &mut self,
         path: &PathBuf,
+        file_type: fs::FileType,
     ) -> Option<<Self as Iterator>::Item> {
-        match File::open(path) {
-            Ok(f) => Some(Ok(FileSource {
-                name: path.display().to_string(),
-                reader: Box::new(BufReader::with_capacity(BUFSIZ, f)),
-            })),
-            Err(e) => Some(Err(format!("{}: {e}", path.display()))),
+        if self.devices == Devices::Skip && is_special(file_type) {
+            return None; // silently continue to next
         }
+        let mut reader = match File::open(path) {
+            Ok(f) => BufReader::with_capacity(BUFSIZ, f),
+            Err(e) => return Some(Err(format!("{}: {e}", path.display()))),
+        };
+        // Read errors are reported when searching, not here.
+        let is_gzip = reader
+            .fill_buf()
+            .is_ok_and(|buf| buf.starts_with(GZIP_MAGIC));
+        let reader: Box<dyn BufRead + Send> = if is_gzip {
+            // Multi: logrotate and friends may append gzip members.
+            let decoder = MultiGzDecoder::new(reader);
+            Box::new(BufReader::with_capacity(BUFSIZ, decoder))
+        } else if let Some(mmap) = map_large_file(reader.get_ref()) {
+            // The whole file is one buffer, so the lines are not copied
+            // out of it, and there are no read() calls.
+            Box::new(io::Cursor::new(mmap))
+        } else {
+            Box::new(reader)
+        };
+        Some(Ok(FileSource {
+            name: path.display().to_string(),
+            reader,
+        }))
     }
 }
 
+/// Whether the file type is a FIFO, socket or device node.
+fn is_special(file_type: fs::FileType) -> bool {
+    file_type.is_fifo()
+        || file_type.is_socket()
+        || file_type.is_block_device()
+        || file_type.is_char_device()
+}
+
+/// Map a large regular file into memory. Returns None for small files,
+/// for non-regular files (pipes, devices) and if mmap fails, so the caller
+/// falls back to reading.
+fn map_large_file(file: &File) -> Option<Mmap> {
+    let metadata = file.metadata().ok()?;
+    if !metadata.is_file() || metadata.len() < MMAP_MIN_SIZE {
+        return None;
+    }
+    // SAFETY: The map is only read. Like grep, we accept that another
+    // process truncating the file while we scan it gets us a SIGBUS.
+    unsafe { Mmap::map(file) }.ok()
+}
+
+/// Shell-style glob match of a whole filename: "*" matches any run of
+/// bytes, "?" any single byte, and "[a-z]" or "[!a-z]" a byte (not) in
+/// the set.
+fn glob_match(glob: &[u8], name: &[u8]) -> bool {
+    let (mut g, mut n) = (0, 0);
+    // Where to resume after the last "*": the glob and name positions.
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        let step = match glob.get(g) {
+            Some(b'*') => {
+                star = Some((g + 1, n));
+                g += 1;
+                continue;
+            }
+            Some(b'?') => Some(1),
+            Some(b'[') => match_class(&glob[g..], name[n]),
+            Some(&ch) => (ch == name[n]).then_some(1),
+            None => None,
+        };
+        match (step, star) {
+            (Some(len), _) => {
+                g += len;
+                n += 1;
+            }
+            (None, Some((star_g, star_n))) => {
+                // Let the "*" take one more byte, and try again.
+                star = Some((star_g, star_n + 1));
+                g = star_g;
+                n = star_n + 1;
+            }
+            (None, None) => return false,
+        }
+    }
+    glob[g..].iter().all(|&ch| ch == b'*')
+}
+
+/// Match a byte against the "[...]" class at the start of glob. Returns
+/// the length of the class if the byte is in it. An unclosed "[" only
+/// matches itself.
+fn match_class(glob: &[u8], ch: u8) -> Option<usize> {
+    let negated = matches!(glob.get(1), Some(b'!' | b'^'));
+    let start = if negated { 2 } else { 1 };
+    // A "]" right at the start is part of the set.
+    let Some(end) = glob
+        .iter()
+        .skip(start + 1)
+        .position(|&c| c == b']')
+        .map(|pos| pos + start + 1)
+    else {
+        return (ch == b'[').then_some(1);
+    };
+    let set = &glob[start..end];
+    let mut found = false;
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == b'-' {
+            found |= (set[i]..=set[i + 2]).contains(&ch);
+            i += 3;
+        } else {
+            found |= set[i] == ch;
+            i += 1;
+        }
+    }
+    (found != negated).then_some(end + 1)
+}
+
 impl Iterator for FileSourceIter {
     type Item = Result<FileSource, String>;
 
@@ -251,3 +534,281 @@ impl Iterator for FileSourceIter {
         self.next_impl()
     }
 }
+
+/// Create a unique empty directory for a test.
+#[cfg(test)]
+pub fn make_test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join(format!("ipgrep-test-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::Collect;
+    use std::os::unix::fs::symlink;
+    use std::path::Path;
+
+    /// Return the sorted names of all files found, relative to base.
+    fn found_names(iter: FileSourceIter, base: &Path) -> Vec<String> {
+        let prefix = format!("{}/", base.display());
+        let mut names: Vec<String> = iter
+            .flatten()
+            .map(|f| f.name.strip_prefix(&prefix).unwrap().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_symlink_following() {
+        // base/dir/a.txt
+        // base/dir/sub/b.txt
+        // base/dir/sub/link_a -> ../a.txt
+        // base/dirlink -> dir
+        let base = make_test_dir("symlinks");
+        fs::create_dir_all(base.join("dir/sub")).unwrap();
+        fs::write(base.join("dir/a.txt"), "a\n").unwrap();
+        fs::write(base.join("dir/sub/b.txt"), "b\n").unwrap();
+        symlink("../a.txt", base.join("dir/sub/link_a")).unwrap();
+        symlink("dir", base.join("dirlink")).unwrap();
+        let arg = [base.join("dirlink").display().to_string()];
+
+        // No following at all.
+        let iter = FileSourceIter::new()
+            .set_recursion(RecurseHaystacks::FollowDirectories)
+            .set_follow_arg_symlinks(false)
+            .add_files(&arg);
+        assert!(found_names(iter, &base).is_empty());
+
+        // Follow the command line symlinks only (-r).
+        let iter = FileSourceIter::new()
+            .set_recursion(RecurseHaystacks::FollowDirectories)
+            .add_files(&arg);
+        assert_eq!(
+            found_names(iter, &base),
+            ["dirlink/a.txt", "dirlink/sub/b.txt"]
+        );
+
+        // Follow all symlinks (-R).
+        let iter = FileSourceIter::new()
+            .set_recursion(RecurseHaystacks::FollowDirectorySymlinks)
+            .add_files(&arg);
+        assert_eq!(
+            found_names(iter, &base),
+            ["dirlink/a.txt", "dirlink/sub/b.txt", "dirlink/sub/link_a"]
+        );
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_skip_special_files() {
+        use std::os::unix::net::UnixListener;
+
+        // base/a.txt
+        // base/sock
+        // base/sock_link -> sock
+        let base = make_test_dir("special");
+        fs::write(base.join("a.txt"), "a\n").unwrap();
+        let _listener = UnixListener::bind(base.join("sock")).unwrap();
+        symlink("sock", base.join("sock_link")).unwrap();
+        let arg = [base.display().to_string()];
+
+        let diagnostics = Arc::new(Collect::default());
+        let iter = FileSourceIter::new()
+            .set_recursion(RecurseHaystacks::FollowDirectories)
+            .set_diagnostics(diagnostics.clone())
+            .add_files(&arg);
+        assert_eq!(found_names(iter, &base), ["a.txt"]);
+        let sock = base.join("sock").display().to_string();
+        assert_eq!(
+            diagnostics.take(),
+            [format!("{sock}: special file (skipping)")]
+        );
+
+        let iter = FileSourceIter::new()
+            .set_recursion(RecurseHaystacks::FollowDirectorySymlinks)
+            .set_diagnostics(diagnostics.clone())
+            .add_files(&arg);
+        assert_eq!(found_names(iter, &base), ["a.txt"]);
+        assert_eq!(diagnostics.take().len(), 2); // sock and sock_link
+
+        // Named explicitly, it is not skipped (but opening it fails).
+        let arg = [
+            base.join("sock").display().to_string(),
+            base.join("a.txt").display().to_string(),
+        ];
+        let mut iter = FileSourceIter::new().add_files(&arg);
+        assert!(iter.next().unwrap().is_err());
+
+        // Unless --devices=skip.
+        let iter = FileSourceIter::new()
+            .set_devices(Devices::Skip)
+            .add_files(&arg);
+        assert_eq!(found_names(iter, &base), ["a.txt"]);
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_gzip() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use std::io::{Read, Write};
+
+        let gzip = |data: &[u8]| {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        };
+        let base = make_test_dir("gzip");
+        let mut two_members = gzip(b"10.0.0.1\n");
+        two_members.extend(gzip(b"10.0.0.2\n"));
+        fs::write(base.join("a.log.gz"), &two_members).unwrap();
+        let truncated = gzip(&b"10.0.0.3\n".repeat(1000));
+        fs::write(base.join("b.log.gz"), &truncated[..20]).unwrap();
+        fs::write(base.join("c.log"), b"\x1f not gzip\n").unwrap();
+        fs::write(base.join("d.log"), b"").unwrap();
+
+        let read = |name: &str| {
+            let arg = [base.join(name).display().to_string()];
+            let mut file =
+                FileSourceIter::new().add_files(&arg).next()?.ok()?;
+            let mut contents = String::new();
+            file.reader.read_to_string(&mut contents).ok()?;
+            Some(contents)
+        };
+        assert_eq!(read("a.log.gz").as_deref(), Some("10.0.0.1\n10.0.0.2\n"));
+        assert_eq!(read("b.log.gz"), None, "a read error, no panic");
+        assert_eq!(read("c.log").as_deref(), Some("\x1f not gzip\n"));
+        assert_eq!(read("d.log").as_deref(), Some(""));
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_mmap() {
+        use std::io::Read;
+
+        let base = make_test_dir("mmap");
+        let line = b"10.0.0.1 and some more text to fill the file\n";
+        let count = MMAP_MIN_SIZE as usize / line.len() + 1;
+        let mut large = line.repeat(count);
+        large.extend(b"10.0.0.2 without trailing newline");
+        fs::write(base.join("large.log"), &large).unwrap();
+        fs::write(base.join("small.log"), line).unwrap();
+
+        let arg = [base.join("large.log").display().to_string()];
+        let file = FileSourceIter::new().add_files(&arg).next().unwrap();
+        let mut reader = file.unwrap().reader;
+        assert_eq!(reader.fill_buf().unwrap().len(), large.len(), "mapped");
+
+        let mut lines = 0;
+        let mut last = Vec::new();
+        loop {
+            last.clear();
+            if reader.read_until(b'\n', &mut last).unwrap() == 0 {
+                break;
+            }
+            lines += 1;
+        }
+        assert_eq!(lines, count + 1);
+
+        let arg = [base.join("small.log").display().to_string()];
+        let file = FileSourceIter::new().add_files(&arg).next().unwrap();
+        let mut contents = Vec::new();
+        file.unwrap().reader.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, line);
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_glob_match() {
+        for (glob, name, expected) in [
+            ("*.log", "syslog.log", true),
+            ("*.log", "syslog.log.gz", false),
+            ("*.log*", "syslog.log.gz", true),
+            ("*", "", true),
+            ("a*b*c", "aXbYbZc", true),
+            ("a*b*c", "aXbYbZ", false),
+            ("?.txt", "a.txt", true),
+            ("?.txt", "ab.txt", false),
+            ("log.[0-9]", "log.7", true),
+            ("log.[!0-9]", "log.7", false),
+            ("log.[!0-9]", "log.x", true),
+            ("[]x]", "]", true),
+            ("[ab", "[ab", true),
+            ("exact", "exact", true),
+            ("exact", "exactly", false),
+        ] {
+            let got = glob_match(glob.as_bytes(), name.as_bytes());
+            assert_eq!(got, expected, "{glob} vs {name}");
+        }
+    }
+
+    #[test]
+    fn test_globs() {
+        // base/a.log
+        // base/a.log.gz
+        // base/b.txt
+        // base/sub.txt/c.log (a directory that does not match)
+        let base = make_test_dir("globs");
+        fs::create_dir_all(base.join("sub.txt")).unwrap();
+        for name in ["a.log", "a.log.gz", "b.txt", "sub.txt/c.log"] {
+            fs::write(base.join(name), "x\n").unwrap();
+        }
+        let arg = [base.display().to_string()];
+        let find = |include: &[&str], exclude: &[&str]| {
+            let include: Vec<String> =
+                include.iter().map(|&s| s.into()).collect();
+            let exclude: Vec<String> =
+                exclude.iter().map(|&s| s.into()).collect();
+            let iter = FileSourceIter::new()
+                .set_recursion(RecurseHaystacks::FollowDirectories)
+                .set_globs(&include, &exclude)
+                .add_files(&arg);
+            found_names(iter, &base)
+        };
+
+        assert_eq!(find(&["*.log"], &[]), ["a.log", "sub.txt/c.log"]);
+        assert_eq!(find(&[], &["*.gz", "*.txt"]), ["a.log", "sub.txt/c.log"]);
+        // Includes accumulate; exclude wins.
+        assert_eq!(
+            find(&["*.log", "*.txt", "*.gz"], &["a.*"]),
+            ["b.txt", "sub.txt/c.log"]
+        );
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_max_filesize() {
+        // base/big.log (100 bytes)
+        // base/small.log (2 bytes)
+        let base = make_test_dir("max-filesize");
+        fs::write(base.join("big.log"), [b'x'; 100]).unwrap();
+        fs::write(base.join("small.log"), "x\n").unwrap();
+        let find = |files: &[String], max_filesize| {
+            let iter = FileSourceIter::new()
+                .set_recursion(RecurseHaystacks::FollowDirectories)
+                .set_max_filesize(max_filesize)
+                .add_files(files);
+            found_names(iter, &base)
+        };
+
+        let dir = [base.display().to_string()];
+        assert_eq!(find(&dir, None), ["big.log", "small.log"]);
+        assert_eq!(find(&dir, Some(100)), ["big.log", "small.log"]);
+        assert_eq!(find(&dir, Some(99)), ["small.log"]);
+        // Files named explicitly are searched regardless.
+        let big = [base.join("big.log").display().to_string()];
+        assert_eq!(find(&big, Some(1)), ["big.log"]);
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+// 2^14 registers: 16 KiB of memory and a standard error of about 0.8%.
+const PRECISION: u32 = 14;
+const REGISTERS: usize = 1 << PRECISION;
+
+/// HyperLogLog cardinality estimator: estimates the number of distinct
+/// values added, in constant memory.
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        Self {
+            registers: vec![0; REGISTERS],
+        }
+    }
+
+    pub fn insert<T: Hash>(&mut self, value: &T) {
+        // DefaultHasher::new() uses fixed keys, so this is repeatable.
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        // The top bits select the register, the rest counts zeroes.
+        let idx = (hash >> (64 - PRECISION)) as usize;
+        let rest = (hash << PRECISION) | (1 << (PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        if rank > self.registers[idx] {
+            self.registers[idx] = rank;
+        }
+    }
+
+    /// Add the values of another estimator, as if they were inserted
+    /// here.
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (reg, &other_reg) in self.registers.iter_mut().zip(&other.registers)
+        {
+            *reg = (*reg).max(other_reg);
+        }
+    }
+
+    pub fn estimate(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&r| 1.0 / (1u64 << r) as f64)
+            .sum();
+        let estimate = alpha * m * m / sum;
+
+        // Small range correction: use linear counting while there are
+        // empty registers. (With a 64-bit hash, no large range
+        // correction is needed.)
+        let zeroes = self.registers.iter().filter(|&&r| r == 0).count();
+        if estimate <= 2.5 * m && zeroes != 0 {
+            (m * (m / zeroes as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_and_small() {
+        let mut hll = HyperLogLog::new();
+        assert_eq!(hll.estimate(), 0);
+        for i in 0..10u32 {
+            hll.insert(&i);
+            hll.insert(&i); // duplicates do not count
+        }
+        assert_eq!(hll.estimate(), 10);
+    }
+
+    #[test]
+    fn test_large() {
+        let mut hll = HyperLogLog::new();
+        for i in 0..200_000u32 {
+            hll.insert(&i);
+        }
+        for i in 0..1000u32 {
+            hll.insert(&i);
+        }
+        let estimate = hll.estimate() as f64;
+        assert!(
+            (estimate - 200_000.0).abs() / 200_000.0 < 0.03,
+            "estimate {estimate} too far off"
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut hll = HyperLogLog::new();
+        let mut other = HyperLogLog::new();
+        for i in 0..1000u32 {
+            hll.insert(&i);
+            other.insert(&(i + 500));
+        }
+        let before = hll.estimate();
+        hll.merge(&other);
+        let estimate = hll.estimate();
+        assert!(estimate > before, "{estimate} <= {before}");
+        assert!((1440..1560).contains(&estimate), "{estimate} not ~1500");
+    }
+}
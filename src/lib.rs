@@ -1,26 +1,46 @@
+//! ipgrep searches for IP addresses and networks in text.
+//!
+//! Next to the command line tool, the scanner can be used on its own.
+//! [`scanner::scan_bytes`] finds the IPs and networks in a buffer:
+//!
+//! ```
+//! use ipgrep::matching::AcceptSet;
+//! use ipgrep::scanner::scan_bytes;
+//!
+//! let accept = AcceptSet {
+//!     ip: true,
+//!     net: true,
+//!     ..Default::default()
+//! };
+//! let found: Vec<String> = scan_bytes(b"to 10.0.0.1 via 10.0.0.0/8", accept)
+//!     .map(|candidate| candidate.net.to_string())
+//!     .collect();
+//! assert_eq!(found, ["10.0.0.1/32", "10.0.0.0/8"]);
+//! ```
+//!
+//! Use a [`scanner::NetCandidateScanner`] for more control, like which
+//! address families to look for and what to do with interface
+//! addresses (10.0.0.1/24). The candidates hold a [`net::Net`], which
+//! can be compared with [`net::Net::supernet_of`] and friends.
+
 pub mod cli;
 pub mod core;
+pub mod diagnostics;
+pub mod matching;
+pub mod net;
+pub mod scanner;
 
 mod context;
 mod files;
+mod hll;
+mod mmdb;
 mod needle;
 mod output;
 mod params;
+mod resolve;
 
-#[cfg(not(feature = "bench"))]
-mod matching;
-#[cfg(not(feature = "bench"))]
-mod net;
 #[cfg(not(feature = "bench"))]
 mod netlike;
-#[cfg(not(feature = "bench"))]
-mod scanner;
 
-#[cfg(feature = "bench")]
-pub mod matching;
-#[cfg(feature = "bench")]
-pub mod net;
 #[cfg(feature = "bench")]
 pub mod netlike;
-#[cfg(feature = "bench")]
-pub mod scanner;
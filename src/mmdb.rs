@@ -0,0 +1,145 @@
+use std::fmt;
+use std::net::IpAddr;
+
+#[cfg(feature = "mmdb")]
+use maxminddb::{Reader, geoip2};
+
+/// The --mmdb filter: only accept addresses that a MaxMind DB places in
+/// one of the --country countries and/or one of the --asn networks.
+pub struct GeoFilter {
+    #[cfg(feature = "mmdb")]
+    reader: Reader<Vec<u8>>,
+    criteria: GeoCriteria,
+}
+
+/// The --country and --asn values that GeoFilter accepts. Kept apart
+/// from the database, so they can be checked without one.
+#[derive(Debug)]
+struct GeoCriteria {
+    // Uppercase ISO 3166-1 codes. Empty means any country.
+    countries: Vec<String>,
+    // Empty means any AS.
+    asns: Vec<u32>,
+}
+
+impl GeoCriteria {
+    // Without the "mmdb" feature, only the tests make a GeoCriteria.
+    #[cfg_attr(not(feature = "mmdb"), allow(dead_code))]
+    fn new(countries: &[String], asns: &[u32]) -> Self {
+        Self {
+            countries: countries.iter().map(|c| c.to_uppercase()).collect(),
+            asns: asns.to_vec(),
+        }
+    }
+
+    /// Whether the looked up country and AS number pass the filter. A
+    /// missed lookup (None) only passes if any value is accepted.
+    fn accepts(&self, country: Option<&str>, asn: Option<u32>) -> bool {
+        (self.countries.is_empty()
+            || country.is_some_and(|c| {
+                self.countries.iter().any(|f| f.eq_ignore_ascii_case(c))
+            }))
+            && (self.asns.is_empty()
+                || asn.is_some_and(|a| self.asns.contains(&a)))
+    }
+}
+
+impl GeoFilter {
+    /// Load the database at path. Fails if it cannot be read or is not a
+    /// MaxMind DB.
+    #[cfg(feature = "mmdb")]
+    pub fn open(
+        path: &str,
+        countries: &[String],
+        asns: &[u32],
+    ) -> Result<Self, String> {
+        let reader = Reader::open_readfile(path).map_err(|e| e.to_string())?;
+        Ok(Self {
+            reader,
+            criteria: GeoCriteria::new(countries, asns),
+        })
+    }
+
+    /// Always fails: this build has no MaxMind DB support.
+    #[cfg(not(feature = "mmdb"))]
+    pub fn open(
+        _path: &str,
+        _countries: &[String],
+        _asns: &[u32],
+    ) -> Result<Self, String> {
+        Err("ipgrep was built without the \"mmdb\" feature".into())
+    }
+
+    /// Whether the database places addr in an accepted country and AS.
+    /// Addresses that are not in the database are rejected.
+    #[cfg(feature = "mmdb")]
+    pub fn matches(&self, addr: IpAddr) -> bool {
+        let country = match self.criteria.countries.is_empty() {
+            true => None,
+            false => self
+                .reader
+                .lookup::<geoip2::Country>(addr)
+                .ok()
+                .and_then(|found| found.country?.iso_code),
+        };
+        let asn = match self.criteria.asns.is_empty() {
+            true => None,
+            false => self
+                .reader
+                .lookup::<geoip2::Asn>(addr)
+                .ok()
+                .and_then(|found| found.autonomous_system_number),
+        };
+        self.criteria.accepts(country, asn)
+    }
+
+    #[cfg(not(feature = "mmdb"))]
+    pub fn matches(&self, _addr: IpAddr) -> bool {
+        self.criteria.accepts(None, None)
+    }
+}
+
+impl fmt::Debug for GeoFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GeoFilter")
+            .field("countries", &self.criteria.countries)
+            .field("asns", &self.criteria.asns)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_error() {
+        let err = GeoFilter::open("/nonexistent.mmdb", &["NL".into()], &[]);
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_accepts() {
+        let criteria = GeoCriteria::new(&["nl".into(), "BE".into()], &[]);
+        assert!(criteria.accepts(Some("NL"), None));
+        assert!(criteria.accepts(Some("be"), Some(1136)));
+        assert!(!criteria.accepts(Some("DE"), None));
+        // An address that is not in the database is rejected.
+        assert!(!criteria.accepts(None, None));
+
+        let criteria = GeoCriteria::new(&[], &[1136, 8075]);
+        assert!(criteria.accepts(None, Some(8075)));
+        assert!(!criteria.accepts(Some("NL"), Some(64496)));
+        assert!(!criteria.accepts(Some("NL"), None));
+
+        // Both must match.
+        let criteria = GeoCriteria::new(&["NL".into()], &[1136]);
+        assert!(criteria.accepts(Some("NL"), Some(1136)));
+        assert!(!criteria.accepts(Some("NL"), Some(8075)));
+        assert!(!criteria.accepts(Some("DE"), Some(1136)));
+        assert!(!criteria.accepts(Some("NL"), None));
+
+        // Without criteria, anything passes, even missed lookups.
+        assert!(GeoCriteria::new(&[], &[]).accepts(None, None));
+    }
+}
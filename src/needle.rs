@@ -1,10 +1,18 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use ipnet::{IpNet, Ipv4Subnets, Ipv6Subnets};
+
+use crate::matching::MatchMode;
 use crate::net::{Net, NetError};
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Needle {
     pub src: String,
     pub net: Net,
     pub is_negated: bool,
+    /// The needles parsed from one token (like an alias or an IP range)
+    /// share a group. The lists number them in order.
+    pub group: usize,
 }
 
 /// Known IP aliases and their corresponding network representations.
@@ -78,7 +86,76 @@ const IP_ALIASES: &[(&[&str], &[&str])] = &[
     (&["doc6", "documentation6"], &["2001:db8::/32"]),
 ];
 
+/// The IP_ALIASES that --builtin accepts. Not all aliases are builtin
+/// sets: those with !needles (like global) are not.
+pub const BUILTINS: &[&str] = &[
+    "rfc1918",
+    "loopback",
+    "linklocal",
+    "multicast",
+    "documentation",
+];
+
 impl Needle {
+    /// Returns the needles of the --builtin set name, or None if there
+    /// is no such set.
+    pub fn builtin(name: &str) -> Option<Vec<Self>> {
+        let name = BUILTINS
+            .iter()
+            .find(|builtin| name.eq_ignore_ascii_case(builtin))?;
+        Some(Self::parse(name).expect("valid builtin"))
+    }
+
+    /// Parses a list of needles, separated by commas, semicolons or
+    /// whitespace. Empty tokens (from trailing or doubled separators) are
+    /// skipped. On failure, returns the errors for all bad tokens.
+    pub fn parse_list(s: &str) -> Result<Vec<Self>, Vec<NetError>> {
+        let mut needles = Vec::new();
+        let mut errors = Vec::new();
+        for tok in s.split([',', ';', ' ', '\n']) {
+            let trimmed = tok.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            match Self::parse(trimmed) {
+                Ok(parsed_needles) => {
+                    extend_needles(&mut needles, parsed_needles)
+                }
+                Err(err) => errors.push(err),
+            }
+        }
+        if errors.is_empty() {
+            Ok(needles)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parses a needle file (for -f): one needle per line. Blank lines
+    /// and "#" comments are skipped. On failure, returns the line numbers
+    /// and errors for all bad lines.
+    pub fn parse_lines(s: &str) -> Result<Vec<Self>, Vec<(usize, NetError)>> {
+        let mut needles = Vec::new();
+        let mut errors = Vec::new();
+        for (idx, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            match Self::parse(line) {
+                Ok(parsed_needles) => {
+                    extend_needles(&mut needles, parsed_needles)
+                }
+                Err(err) => errors.push((idx + 1, err)),
+            }
+        }
+        if errors.is_empty() {
+            Ok(needles)
+        } else {
+            Err(errors)
+        }
+    }
+
     /// Parses a string into one or more Needles.
     /// Recursively handles 1-to-N aliases.
     pub fn parse(s: &str) -> Result<Vec<Self>, NetError> {
@@ -107,6 +184,12 @@ impl Needle {
                 }
                 needles.extend(child_needles);
             }
+        } else if let Some((first, last)) = input.split_once('-') {
+            // An IP range, like 10.0.0.1-10.0.0.20.
+            for mut needle in Self::parse_range(input, first, last)? {
+                needle.is_negated ^= is_negated;
+                needles.push(needle);
+            }
         } else {
             // Not in IP_ALIASES, parse IP/network.
             let mut needle = Self::try_from(input)?;
@@ -117,6 +200,69 @@ impl Needle {
         Ok(needles)
     }
 
+    /// Parses an IP range (first-last, inclusive) into the fewest
+    /// networks that cover it exactly. All get the range as src.
+    fn parse_range(
+        src: &str,
+        first: &str,
+        last: &str,
+    ) -> Result<Vec<Self>, NetError> {
+        let parse_ip = |s: &str| {
+            s.trim()
+                .parse::<IpAddr>()
+                .map_err(|_| NetError::NotAnIp(src.to_string()))
+        };
+        let nets: Vec<IpNet> = match (parse_ip(first)?, parse_ip(last)?) {
+            (IpAddr::V4(a), IpAddr::V4(b)) if a <= b => {
+                Ipv4Subnets::new(a, b, 0).map(IpNet::V4).collect()
+            }
+            (IpAddr::V6(a), IpAddr::V6(b)) if a <= b => {
+                Ipv6Subnets::new(a, b, 0).map(IpNet::V6).collect()
+            }
+            (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => {
+                return Err(NetError::ReversedRange(src.to_string()));
+            }
+            _ => return Err(NetError::MixedRange(src.to_string())),
+        };
+        Ok(nets
+            .into_iter()
+            .map(|net| Needle {
+                src: src.to_string(),
+                net: Net(net),
+                is_negated: false,
+                group: 0,
+            })
+            .collect())
+    }
+
+    /// Returns an error unless the needle is a single host address
+    /// (/32 or /128), for --hosts-only.
+    pub fn require_host(&self) -> Result<(), NetError> {
+        if self.net.is_single_ip() {
+            Ok(())
+        } else {
+            Err(NetError::NotAHost(self.src.clone()))
+        }
+    }
+
+    /// Returns the needle widened to its legacy classful network, for
+    /// --classful: a bare class A, B or C address (like 10.5.6.7) becomes
+    /// its /8, /16 or /24 network (10.0.0.0/8). Returns None for other
+    /// needles: those written with a prefix, IPv6 and class D/E.
+    pub fn to_classful(&self) -> Option<Self> {
+        let addr = self.src.parse::<Ipv4Addr>().ok()?;
+        let prefix = match addr.octets()[0] {
+            0..=127 => 8,    // class A
+            128..=191 => 16, // class B
+            192..=223 => 24, // class C
+            _ => return None,
+        };
+        Some(Needle {
+            net: self.net.with_prefix(prefix)?,
+            ..self.clone()
+        })
+    }
+
     pub fn try_from(s: &str) -> Result<Self, NetError> {
         let (input, is_negated) = if let Some(rest) = s.strip_prefix('!') {
             (rest, true)
@@ -135,8 +281,58 @@ impl Needle {
             src: input.to_string(),
             net,
             is_negated,
+            group: 0,
         })
     }
+
+    /// Whether both needles are (networks of) the same needle: the
+    /// networks that an IP range was split into count as one needle,
+    /// for --by-needle, --host-index and --warn-redundant-needles.
+    pub fn is_same_needle(&self, other: &Needle) -> bool {
+        self.group == other.group && self.src == other.src
+    }
+}
+
+/// Appends the needles to the list, numbering their groups after the
+/// groups already in it.
+pub fn extend_needles(needles: &mut Vec<Needle>, other: Vec<Needle>) {
+    let first = needles.last().map_or(0, |n| n.group + 1);
+    needles.extend(other.into_iter().map(|needle| Needle {
+        group: first + needle.group,
+        ..needle
+    }));
+}
+
+/// Finds the needles that never add matches under the match mode,
+/// because other needles already cover them. A needle split into
+/// several networks (an IP range) is redundant if all its networks are
+/// covered. Returns pairs of the redundant needle index and the
+/// (first) covering needle index. Of identical needles, the later ones
+/// are redundant. This compares all pairs, so it is O(n²).
+pub fn find_redundant(
+    needles: &[Needle],
+    match_mode: &MatchMode,
+) -> Vec<(usize, usize)> {
+    let mut redundant = Vec::new();
+    let mut start = 0;
+    for same in needles.chunk_by(Needle::is_same_needle) {
+        let own = start..start + same.len();
+        start = own.end;
+        let covering: Option<Vec<usize>> = same
+            .iter()
+            .map(|needle| {
+                needles.iter().enumerate().position(|(other, n)| {
+                    !own.contains(&other)
+                        && match_mode.covers(&n.net, &needle.net)
+                        && (other < own.start || n.net != needle.net)
+                })
+            })
+            .collect();
+        if let Some(covering) = covering {
+            redundant.push((own.start, covering[0]));
+        }
+    }
+    redundant
 }
 
 #[cfg(test)]
@@ -148,8 +344,23 @@ mod tests {
         for (keys, _) in IP_ALIASES {
             let ns = Needle::parse(keys[0]).unwrap();
             // If it's 0 it will behave as "any", so that would not be good.
-            assert!(ns.len() > 0);
+            assert!(!ns.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_builtins() {
+        for name in BUILTINS {
+            let ns = Needle::builtin(name).unwrap();
+            assert!(!ns.is_empty());
+            assert!(ns.iter().all(|n| !n.is_negated));
         }
+        let ns = Needle::builtin("RFC1918").unwrap();
+        let srcs: Vec<&str> = ns.iter().map(|n| n.src.as_str()).collect();
+        assert_eq!(srcs, ["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16"]);
+        // The IP_ALIASES are not builtins.
+        assert!(Needle::builtin("global").is_none());
+        assert!(Needle::builtin("10.0.0.0/8").is_none());
     }
 
     #[test]
@@ -206,6 +417,25 @@ mod tests {
         assert!(!ns[1].is_negated);
     }
 
+    #[test]
+    fn test_needle_class_wildcards() {
+        let v4 = Net::from_str_unchecked("0.0.0.0/0");
+        let v6 = Net::from_str_unchecked("::/0");
+        for (keyword, expected) in [
+            ("any", &[&v4, &v6][..]),
+            ("ip", &[&v4, &v6]),
+            ("ip4", &[&v4]),
+            ("IPv4", &[&v4]),
+            ("ip6", &[&v6]),
+            ("ipv6", &[&v6]),
+        ] {
+            let ns = Needle::parse(keyword).unwrap();
+            let nets: Vec<&Net> = ns.iter().map(|n| &n.net).collect();
+            assert_eq!(nets, expected, "for {keyword}");
+            assert!(ns.iter().all(|n| !n.is_negated));
+        }
+    }
+
     #[test]
     fn test_needle_class_negated_multicast() {
         let ns = Needle::parse("!multicast").unwrap();
@@ -254,4 +484,162 @@ mod tests {
         assert_eq!(n.src, "192.168.32.0/255.255.224.0");
         assert_eq!(n.net, Net::from_str_unchecked("192.168.32.0/19"));
     }
+
+    #[test]
+    fn test_needle_range() {
+        let nets = |s| {
+            Needle::parse(s)
+                .unwrap()
+                .iter()
+                .map(|n| n.net.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            nets("10.0.0.1-10.0.0.20"),
+            [
+                "10.0.0.1/32",
+                "10.0.0.2/31",
+                "10.0.0.4/30",
+                "10.0.0.8/29",
+                "10.0.0.16/30",
+                "10.0.0.20/32"
+            ]
+        );
+        assert_eq!(nets("10.0.0.0-10.0.1.255"), ["10.0.0.0/23"]);
+
+        // The networks of a range (or alias) are one group of the list.
+        let mut ns = Needle::parse_list("10.0.0.1-10.0.0.3,rfc1918").unwrap();
+        let groups =
+            |ns: &[Needle]| ns.iter().map(|n| n.group).collect::<Vec<_>>();
+        assert_eq!(groups(&ns), [0, 0, 1, 1, 1]);
+        assert!(ns[0].is_same_needle(&ns[1]));
+        assert!(!ns[2].is_same_needle(&ns[3]));
+        extend_needles(&mut ns, Needle::parse_list("::1,::2").unwrap());
+        assert_eq!(groups(&ns), [0, 0, 1, 1, 1, 2, 3]);
+        assert_eq!(nets("::1-::1"), ["::1/128"]);
+
+        // A single address range is the bare IP.
+        let ns = Needle::parse("!10.0.0.5-10.0.0.5").unwrap();
+        assert_eq!(ns.len(), 1);
+        assert_eq!(ns[0].src, "10.0.0.5-10.0.0.5");
+        assert_eq!(ns[0].net, Needle::try_from("10.0.0.5").unwrap().net);
+        assert!(ns[0].is_negated);
+
+        for (bad, msg) in [
+            ("10.0.0.9-10.0.0.1", "needle range ends before it starts"),
+            ("10.0.0.1-::1", "needle range cannot mix IPv4 and IPv6"),
+            ("10.0.0.1-", "invalid ip/net as needle"),
+            ("10.0.0.0/8-10.0.0.9", "invalid ip/net as needle"),
+        ] {
+            let err = Needle::parse(bad).unwrap_err().to_string();
+            assert_eq!(err, format!("{msg}: {bad}"));
+        }
+    }
+
+    #[test]
+    fn test_to_classful() {
+        let classful = |s: &str| {
+            let needle = &Needle::parse(s).unwrap()[0];
+            needle
+                .to_classful()
+                .map(|n| (n.net.to_string(), n.is_negated))
+        };
+        assert_eq!(classful("10.5.6.7"), Some(("10.0.0.0/8".into(), false)));
+        assert_eq!(
+            classful("172.16.5.4"),
+            Some(("172.16.0.0/16".into(), false))
+        );
+        assert_eq!(
+            classful("!192.168.1.1"),
+            Some(("192.168.1.0/24".into(), true))
+        );
+        assert_eq!(classful("224.0.0.1"), None, "class D");
+        assert_eq!(classful("10.5.6.7/32"), None, "explicit prefix");
+        assert_eq!(classful("10.0.0.0/8"), None);
+        assert_eq!(classful("::1"), None);
+        assert_eq!(classful("10.0.0.1-10.0.0.2"), None);
+    }
+
+    #[test]
+    fn test_parse_list_errors() {
+        let errors = Needle::parse_list("10.0.0.1,foo;10.0.0.1/8 bar,::1")
+            .unwrap_err()
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                "invalid ip/net as needle: foo",
+                "needle cannot have host bits set: 10.0.0.1/8",
+                "invalid ip/net as needle: bar",
+            ]
+        );
+        assert_eq!(Needle::parse_list(" ,; ").unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_parse_lines() {
+        let needles = Needle::parse_lines(
+            "# blocklist\n\n10.0.0.0/8\n  !10.2.0.0/16  # but not this\nrfc1918\n",
+        )
+        .unwrap();
+        let srcs: Vec<&str> = needles.iter().map(|n| n.src.as_str()).collect();
+        assert_eq!(
+            srcs,
+            [
+                "10.0.0.0/8",
+                "10.2.0.0/16",
+                "10.0.0.0/8",
+                "172.16.0.0/12",
+                "192.168.0.0/16"
+            ]
+        );
+        assert!(needles[1].is_negated);
+
+        let errors: Vec<(usize, String)> =
+            Needle::parse_lines("10.0.0.1\nfoo\n::1\n10.0.0.1 10.0.0.2\n")
+                .unwrap_err()
+                .into_iter()
+                .map(|(lineno, err)| (lineno, err.to_string()))
+                .collect();
+        assert_eq!(
+            errors,
+            [
+                (2, "invalid ip/net as needle: foo".into()),
+                (4, "invalid ip/net as needle: 10.0.0.1 10.0.0.2".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_redundant() {
+        let needles = Needle::parse_list(
+            "10.0.0.0/8,10.1.0.0/16,192.168.0.0/16,10.1.2.3,192.168.0.0/16",
+        )
+        .unwrap();
+        assert_eq!(
+            find_redundant(&needles, &MatchMode::Within),
+            [(1, 0), (3, 0), (4, 2)]
+        );
+        // Under contains, the larger networks are the redundant ones.
+        assert_eq!(
+            find_redundant(&needles, &MatchMode::Contains),
+            [(0, 1), (1, 3), (4, 2)]
+        );
+        assert_eq!(find_redundant(&needles, &MatchMode::Equals), [(4, 2)]);
+
+        let disjoint = Needle::parse_list("10.0.0.0/8,::1,192.168.0.0/16");
+        let disjoint = disjoint.unwrap();
+        assert!(find_redundant(&disjoint, &MatchMode::Within).is_empty());
+        assert!(find_redundant(&disjoint, &MatchMode::Contains).is_empty());
+
+        // A range is reported once, and only if all of it is covered.
+        let range = Needle::parse_list("10.0.0.1-10.0.0.20,10.0.0.0/24");
+        let range = range.unwrap();
+        assert_eq!(find_redundant(&range, &MatchMode::Within), [(0, 6)]);
+        let range = Needle::parse_list("10.0.0.1-10.0.0.20,10.0.0.0/28");
+        let range = range.unwrap();
+        assert!(find_redundant(&range, &MatchMode::Within).is_empty());
+    }
 }
@@ -1,6 +1,6 @@
 use std::convert::TryFrom;
 use std::fmt;
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::str::from_utf8;
 
 pub use ipnet::IpNet; // re-export
@@ -10,6 +10,9 @@ pub enum NetError {
     InvalidUtf8,
     NotAnIp(String),
     HostBitsSet(String),
+    ReversedRange(String),
+    MixedRange(String),
+    NotAHost(String),
 }
 
 impl fmt::Display for NetError {
@@ -24,11 +27,20 @@ impl fmt::Display for NetError {
             NetError::HostBitsSet(s) => {
                 write!(f, "needle cannot have host bits set: {s}")
             }
+            NetError::ReversedRange(s) => {
+                write!(f, "needle range ends before it starts: {s}")
+            }
+            NetError::MixedRange(s) => {
+                write!(f, "needle range cannot mix IPv4 and IPv6: {s}")
+            }
+            NetError::NotAHost(s) => {
+                write!(f, "needle is not a single host address: {s}")
+            }
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Net(pub IpNet);
 
 impl fmt::Display for Net {
@@ -55,12 +67,177 @@ impl Net {
         matches!(self.0, IpNet::V6(_ipnet))
     }
 
+    /// Returns true if this is an IPv4-mapped IPv6 address or network:
+    /// one within ::ffff:0:0/96, like ::ffff:10.0.0.1.
+    pub fn is_ipv4_mapped(&self) -> bool {
+        match self.0 {
+            IpNet::V6(net) => {
+                net.prefix_len() >= 96 && net.addr().to_ipv4_mapped().is_some()
+            }
+            IpNet::V4(_) => false,
+        }
+    }
+
+    /// Returns the IPv4 address or network of an IPv4-mapped one, like
+    /// 10.0.0.0/24 for ::ffff:10.0.0.0/120. None if it is not mapped.
+    pub fn to_ipv4_unmapped(&self) -> Option<Self> {
+        match self.0 {
+            IpNet::V6(net) if self.is_ipv4_mapped() => {
+                let addr = net.addr().to_ipv4_mapped()?;
+                IpNet::new(IpAddr::V4(addr), net.prefix_len() - 96)
+                    .ok()
+                    .map(Net)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the IPv4-mapped IPv6 address or network of an IPv4 one,
+    /// like ::ffff:10.0.0.0/104 for 10.0.0.0/8. IPv4 prefix lengths /0
+    /// to /32 become /96 to /128. None if it is IPv6 already.
+    pub fn to_ipv6_mapped(&self) -> Option<Self> {
+        match self.0 {
+            IpNet::V4(net) => {
+                let addr = net.addr().to_ipv6_mapped();
+                IpNet::new(IpAddr::V6(addr), net.prefix_len() + 96)
+                    .ok()
+                    .map(Net)
+            }
+            IpNet::V6(_) => None,
+        }
+    }
+
     /// Returns true if the network is a single IP address (/32 for
     /// IPv4, /128 for IPv6)
     pub fn is_single_ip(&self) -> bool {
         self.0.prefix_len() == self.0.max_prefix_len()
     }
 
+    /// Returns true if the single address addr lies within this
+    /// network. Addresses of the other family are never contained.
+    pub fn contains_addr(&self, addr: &IpAddr) -> bool {
+        match (self.0, addr) {
+            (IpNet::V4(net), IpAddr::V4(addr)) => net.contains(addr),
+            (IpNet::V6(net), IpAddr::V6(addr)) => net.contains(addr),
+            _ => false,
+        }
+    }
+
+    /// Returns true if other lies entirely within this network.
+    /// Networks of the other family are never contained.
+    pub fn contains_net(&self, other: &Net) -> bool {
+        match (self.0, other.0) {
+            (IpNet::V4(a), IpNet::V4(b)) => {
+                a.contains(&b.network()) && a.contains(&b.broadcast())
+            }
+            (IpNet::V6(a), IpNet::V6(b)) => {
+                a.contains(&b.network()) && a.contains(&b.broadcast())
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns true if this network equals or contains other. Networks of
+    /// the other family are never contained.
+    ///
+    /// ```
+    /// use ipgrep::net::Net;
+    ///
+    /// let net = |s: &str| Net::try_from(s).unwrap();
+    /// assert!(net("10.0.0.0/8").supernet_of(&net("10.1.0.0/16")));
+    /// assert!(net("10.0.0.0/8").supernet_of(&net("10.0.0.0/8")));
+    /// assert!(!net("10.1.0.0/16").supernet_of(&net("10.0.0.0/8")));
+    /// assert!(net("2001:db8::/32").supernet_of(&net("2001:db8:1::1")));
+    /// assert!(!net("::/0").supernet_of(&net("10.0.0.0/8")));
+    /// ```
+    pub fn supernet_of(&self, other: &Net) -> bool {
+        self.contains_net(other)
+    }
+
+    /// Returns true if this network equals or lies within other. Networks
+    /// of the other family are never contained.
+    ///
+    /// ```
+    /// use ipgrep::net::Net;
+    ///
+    /// let net = |s: &str| Net::try_from(s).unwrap();
+    /// assert!(net("10.1.0.0/16").subnet_of(&net("10.0.0.0/8")));
+    /// assert!(net("10.0.0.0/8").subnet_of(&net("10.0.0.0/8")));
+    /// assert!(!net("10.0.0.0/8").subnet_of(&net("10.1.0.0/16")));
+    /// assert!(net("2001:db8:1::1").subnet_of(&net("2001:db8::/32")));
+    /// assert!(!net("10.0.0.0/8").subnet_of(&net("::/0")));
+    /// ```
+    pub fn subnet_of(&self, other: &Net) -> bool {
+        other.contains_net(self)
+    }
+
+    /// Returns the offset of our address from the network address of
+    /// network, like 5 for 10.0.0.5 in 10.0.0.0/24. None if our address
+    /// is not in network.
+    pub fn host_index(&self, network: &Net) -> Option<u128> {
+        match (self.0.addr(), network.0) {
+            (IpAddr::V4(addr), IpNet::V4(net)) if net.contains(&addr) => {
+                Some((u32::from(addr) - u32::from(net.network())).into())
+            }
+            (IpAddr::V6(addr), IpNet::V6(net)) if net.contains(&addr) => {
+                Some(u128::from(addr) - u128::from(net.network()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns how far our network address is past the network address
+    /// of first, like 8 for 10.0.0.8/29 from 10.0.0.0/30. None if it is
+    /// before it, or in the other family.
+    pub fn host_index_from(&self, first: &Net) -> Option<u128> {
+        match (self.0, first.0) {
+            (IpNet::V4(net), IpNet::V4(first)) => u32::from(net.network())
+                .checked_sub(u32::from(first.network()))
+                .map(u128::from),
+            (IpNet::V6(net), IpNet::V6(first)) => u128::from(net.network())
+                .checked_sub(u128::from(first.network())),
+            _ => None,
+        }
+    }
+
+    /// Returns the network of the given (shorter) prefix length that
+    /// contains this one. A prefix longer than ours returns self.
+    pub fn supernet(&self, prefix: u8) -> Self {
+        if prefix >= self.0.prefix_len() {
+            return self.clone();
+        }
+        Net(IpNet::new(self.0.addr(), prefix)
+            .expect("cannot fail")
+            .trunc())
+    }
+
+    /// Returns the network of the given prefix length that contains our
+    /// address. Unlike supernet(), the prefix may be longer than ours.
+    /// Returns None if it is too long for the address family.
+    pub fn with_prefix(&self, prefix: u8) -> Option<Self> {
+        IpNet::new(self.0.addr(), prefix)
+            .ok()
+            .map(|ipnet| Net(ipnet.trunc()))
+    }
+
+    /// Merges networks into the fewest networks that cover the same
+    /// addresses. IPv4 and IPv6 are merged separately. The result is
+    /// sorted, IPv4 first.
+    pub fn aggregate<'a, I>(nets: I) -> Vec<Self>
+    where
+        I: IntoIterator<Item = &'a Net>,
+    {
+        let (v4, v6): (Vec<IpNet>, Vec<IpNet>) = nets
+            .into_iter()
+            .map(|n| n.0)
+            .partition(|n| n.addr().is_ipv4());
+        IpNet::aggregate(&v4)
+            .into_iter()
+            .chain(IpNet::aggregate(&v6))
+            .map(Net)
+            .collect()
+    }
+
     pub fn as_ip(&self) -> Self {
         Net(IpNet::new(self.0.addr(), self.0.max_prefix_len())
             .expect("cannot fail"))
@@ -83,16 +260,20 @@ impl TryFrom<&str> for Net {
                 }
             } else if let (Ok(ip), Ok(mask)) =
                 (ip_part.parse::<Ipv4Addr>(), mask_part.parse::<Ipv4Addr>())
-            {
-                if let Ok(ipnet) =
+                && let Ok(ipnet) =
                     IpNet::with_netmask(IpAddr::V4(ip), IpAddr::V4(mask))
-                {
-                    return Ok(Net(ipnet));
-                }
+            {
+                return Ok(Net(ipnet));
             }
             Err(NetError::NotAnIp(s.to_string()))
         } else if let Ok(addr) = s.parse::<IpAddr>() {
             Ok(Net(IpNet::from(addr)))
+        } else if let Some((addr, zone)) = s.split_once('%')
+            && !zone.is_empty()
+            && let Ok(addr) = addr.parse::<Ipv6Addr>()
+        {
+            // The zone index (fe80::1%eth0) is not part of the address.
+            Ok(Net(IpNet::from(IpAddr::V6(addr))))
         } else {
             Err(NetError::NotAnIp(s.to_string()))
         }
@@ -121,6 +302,204 @@ mod tests {
         assert_ne!(old, old2);
     }
 
+    #[test]
+    fn test_contains_addr() {
+        let addr = |s: &str| s.parse::<IpAddr>().unwrap();
+        let n = Net::from_str_unchecked("10.0.0.0/24");
+        assert!(n.contains_addr(&addr("10.0.0.0")));
+        assert!(n.contains_addr(&addr("10.0.0.255")));
+        assert!(!n.contains_addr(&addr("10.0.1.0")));
+        assert!(!n.contains_addr(&addr("::ffff:10.0.0.1")));
+
+        let n = Net::from_str_unchecked("2001:db8::/32");
+        assert!(n.contains_addr(&addr("2001:db8:ffff::1")));
+        assert!(!n.contains_addr(&addr("2001:db9::")));
+        assert!(!n.contains_addr(&addr("10.0.0.1")));
+
+        // Everything (in the same family).
+        let any4 = Net::from_str_unchecked("0.0.0.0/0");
+        assert!(any4.contains_addr(&addr("0.0.0.0")));
+        assert!(any4.contains_addr(&addr("255.255.255.255")));
+        assert!(!any4.contains_addr(&addr("::")));
+        let any6 = Net::from_str_unchecked("::/0");
+        assert!(any6.contains_addr(&addr("::")));
+        assert!(any6.contains_addr(&addr("ffff:ffff:ffff:ffff::1")));
+        assert!(!any6.contains_addr(&addr("0.0.0.0")));
+
+        // Single IP.
+        let n = Net::from_str_unchecked("1.2.3.4");
+        assert!(n.contains_addr(&addr("1.2.3.4")));
+        assert!(!n.contains_addr(&addr("1.2.3.5")));
+    }
+
+    #[test]
+    fn test_contains_net() {
+        let net = Net::from_str_unchecked;
+        assert!(net("10.0.0.0/8").contains_net(&net("10.1.0.0/16")));
+        assert!(net("10.0.0.0/8").contains_net(&net("10.0.0.0/8")));
+        assert!(net("10.0.0.0/8").contains_net(&net("10.255.255.255")));
+        assert!(!net("10.1.0.0/16").contains_net(&net("10.0.0.0/8")));
+        assert!(!net("10.0.0.0/8").contains_net(&net("11.0.0.0/24")));
+        assert!(net("::/0").contains_net(&net("2001:db8::/32")));
+        assert!(!net("2001:db8::/32").contains_net(&net("2001:db9::/48")));
+        assert!(!net("0.0.0.0/0").contains_net(&net("::/0")));
+        assert!(!net("::/0").contains_net(&net("10.0.0.0/8")));
+    }
+
+    #[test]
+    fn test_ordering() {
+        let mut nets: Vec<Net> =
+            ["::1", "10.0.0.10", "10.0.0.0/24", "10.0.0.9", "10.0.0.0/8"]
+                .into_iter()
+                .map(Net::from_str_unchecked)
+                .collect();
+        nets.sort();
+        let got: Vec<String> = nets.iter().map(|n| n.to_string()).collect();
+        assert_eq!(
+            got,
+            [
+                "10.0.0.0/8",
+                "10.0.0.0/24",
+                "10.0.0.9/32",
+                "10.0.0.10/32",
+                "::1/128"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_supernet() {
+        let n = Net::from_str_unchecked("192.168.2.129");
+        assert_eq!(n.supernet(0), Net::from_str_unchecked("0.0.0.0/0"));
+        assert_eq!(n.supernet(24), Net::from_str_unchecked("192.168.2.0/24"));
+        assert_eq!(n.supernet(32), n);
+        assert_eq!(n.supernet(128), n);
+        let n = Net::from_str_unchecked("2001:db8:1:2::/64");
+        assert_eq!(n.supernet(32), Net::from_str_unchecked("2001:db8::/32"));
+        assert_eq!(n.supernet(96), n);
+    }
+
+    #[test]
+    fn test_is_ipv4_mapped() {
+        for (s, expected) in [
+            ("::ffff:10.0.0.1", true),
+            ("::FFFF:a00:1", true),
+            ("::ffff:10.0.0.0/120", true),
+            ("::ffff:0:0/96", true),
+            ("::ffff:0:0/95", false),
+            ("::1", false),
+            ("::10.0.0.1", false), // IPv4-compatible, deprecated
+            ("64:ff9b::10.0.0.1", false),
+            ("10.0.0.1", false),
+        ] {
+            let n = Net::from_str_unchecked(s);
+            assert_eq!(n.is_ipv4_mapped(), expected, "for {s}");
+        }
+    }
+
+    #[test]
+    fn test_to_ipv4_unmapped() {
+        for (s, expected) in [
+            ("::ffff:10.0.0.1", Some("10.0.0.1/32")),
+            ("::ffff:10.0.0.0/120", Some("10.0.0.0/24")),
+            ("::ffff:0:0/96", Some("0.0.0.0/0")),
+            ("::ffff:0:0/95", None),
+            ("::10.0.0.1", None),
+            ("10.0.0.1", None),
+        ] {
+            let n = Net::from_str_unchecked(s);
+            let got = n.to_ipv4_unmapped().map(|n| n.to_string());
+            assert_eq!(got.as_deref(), expected, "for {s}");
+        }
+    }
+
+    #[test]
+    fn test_to_ipv6_mapped() {
+        for (s, expected) in [
+            ("10.0.0.1", Some("::ffff:10.0.0.1/128")),
+            ("10.0.0.0/8", Some("::ffff:10.0.0.0/104")),
+            ("0.0.0.0/0", Some("::ffff:0.0.0.0/96")),
+            ("::ffff:10.0.0.1", None),
+        ] {
+            let n = Net::from_str_unchecked(s);
+            let got = n.to_ipv6_mapped().map(|n| n.to_string());
+            assert_eq!(got.as_deref(), expected, "for {s}");
+            if let Some(mapped) = n.to_ipv6_mapped() {
+                assert_eq!(mapped.to_ipv4_unmapped(), Some(n), "for {s}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let nets: Vec<Net> = ["::1", "10.0.1.0/24", "10.0.0.0/24", "10.0.0.7"]
+            .iter()
+            .map(|s| Net::from_str_unchecked(s))
+            .collect();
+        let aggregated: Vec<String> = Net::aggregate(&nets)
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        assert_eq!(aggregated, ["10.0.0.0/23", "::1/128"]);
+        assert!(Net::aggregate(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_host_index() {
+        for (s, network, expected) in [
+            ("10.0.0.5", "10.0.0.0/24", Some(5)),
+            ("10.0.0.0", "10.0.0.0/24", Some(0)),
+            ("10.0.1.255", "10.0.0.0/16", Some(511)),
+            ("10.1.0.0", "10.0.0.0/24", None),
+            ("10.0.0.64/26", "10.0.0.0/24", Some(64)),
+            ("2001:db8::1:0", "2001:db8::/32", Some(0x10000)),
+            ("2001:db8:ffff::", "2001:db8::/32", Some(0xffff << 80)),
+            ("::ffff", "2001:db8::/32", None),
+            ("10.0.0.5", "::/0", None),
+        ] {
+            let n = Net::from_str_unchecked(s);
+            let network = Net::from_str_unchecked(network);
+            assert_eq!(n.host_index(&network), expected, "for {s}");
+        }
+    }
+
+    #[test]
+    fn test_host_index_from() {
+        for (s, first, expected) in [
+            ("10.0.0.8/29", "10.0.0.0/30", Some(8)),
+            ("10.0.0.20", "10.0.0.1", Some(19)),
+            ("10.0.0.1", "10.0.0.20", None),
+            ("::1:0/112", "::1", Some(0xffff)),
+            ("10.0.0.5", "::/0", None),
+        ] {
+            let n = Net::from_str_unchecked(s);
+            let first = Net::from_str_unchecked(first);
+            assert_eq!(n.host_index_from(&first), expected, "for {s}");
+        }
+    }
+
+    #[test]
+    fn test_with_prefix() {
+        let n = Net::from_str_unchecked("10.1.2.3");
+        let with_prefix = |prefix| n.with_prefix(prefix).map(|n| n.to_string());
+        assert_eq!(with_prefix(16).as_deref(), Some("10.1.0.0/16"));
+        assert_eq!(with_prefix(24).as_deref(), Some("10.1.2.0/24"));
+        assert_eq!(with_prefix(32).as_deref(), Some("10.1.2.3/32"));
+        assert_eq!(with_prefix(33), None);
+        // Longer than the current prefix is allowed.
+        let n = Net::from_str_unchecked("10.1.2.3/8");
+        assert_eq!(
+            n.with_prefix(32),
+            Some(Net::from_str_unchecked("10.1.2.3"))
+        );
+        let n = Net::from_str_unchecked("2001:db8::1/64");
+        assert_eq!(
+            n.with_prefix(128),
+            Some(Net::from_str_unchecked("2001:db8::1"))
+        );
+        assert_eq!(n.with_prefix(129), None);
+    }
+
     #[test]
     fn test_v4_10_0_0_0_24() {
         let n = Net::from_str_unchecked("10.0.0.0/24");
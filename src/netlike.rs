@@ -12,6 +12,8 @@ pub struct NetLikeScanner<'a> {
     buf: &'a [u8],
     pos: usize,
     restrict: NetLikeRestriction,
+    // Include the "%zone" after IPv6 addresses, like fe80::1%eth0.
+    zoneid: bool,
 }
 
 const IPV46_START: &[u8; 23] = b"0123456789abcdefABCDEF:";
@@ -22,6 +24,7 @@ impl<'a> NetLikeScanner<'a> {
             buf,
             pos: 0,
             restrict: NetLikeRestriction::IpsAndCidrs,
+            zoneid: false,
         }
     }
 
@@ -33,6 +36,15 @@ impl<'a> NetLikeScanner<'a> {
         }
     }
 
+    /// Includes the zone index of IPv6 addresses in the match, like the
+    /// "%eth0" in fe80::1%eth0.
+    pub fn with_zoneid(self) -> Self {
+        Self {
+            zoneid: true,
+            ..self
+        }
+    }
+
     #[inline]
     fn next_impl(&mut self) -> Option<(usize, usize)> {
         let bytes = self.buf;
@@ -41,16 +53,7 @@ impl<'a> NetLikeScanner<'a> {
         // Shortest IPv4 is 7 ("1.1.1.1").
         let leftover = len.saturating_sub(self.pos);
         if leftover < 7 {
-            while self.pos < len {
-                if matches!(
-                    bytes[self.pos],
-                    b'0'..=b'9' | b'a'..=b'f' | b'A'..=b'F' | b':')
-                {
-                    return self.try_ipv6();
-                }
-                self.pos += 1;
-            }
-            return None;
+            return self.next_short_ipv6();
         }
 
         // Leftover is >=7
@@ -101,6 +104,15 @@ impl<'a> NetLikeScanner<'a> {
         }
 
         // Leftover is <7
+        self.next_short_ipv6()
+    }
+
+    /// Look for IPv6 in the last few (<7) bytes: too few for IPv4.
+    #[inline]
+    fn next_short_ipv6(&mut self) -> Option<(usize, usize)> {
+        let bytes = self.buf;
+        let len = bytes.len();
+
         while self.pos < len {
             if matches!(
                 bytes[self.pos],
@@ -109,8 +121,11 @@ impl<'a> NetLikeScanner<'a> {
                 if let Some(res) = self.try_ipv6() {
                     return Some(res);
                 }
+                // Retry now that self.pos is increased (beyond the
+                // token and its delimiter).
+            } else {
+                self.pos += 1;
             }
-            self.pos += 1;
         }
 
         None
@@ -322,14 +337,12 @@ impl<'a> NetLikeScanner<'a> {
                 // Reject all the matches.
                 return self.seek_to_non_letter(end + 1);
             }
-            b'.' => {
+            b'.' if end + 1 < len && bytes[end + 1].is_ascii_digit() => {
                 // Address stops being legal if we get another octet
                 // after the fourth.
                 // ["1.2.3.4".] <- legal
                 // ["1.2.3.4.5"] <- illegal
-                if end + 1 < len && bytes[end + 1].is_ascii_digit() {
-                    return self.seek_to_non_digit_period(end + 1);
-                }
+                return self.seek_to_non_digit_period(end + 1);
             }
             _ => {}
         }
@@ -346,6 +359,7 @@ impl<'a> NetLikeScanner<'a> {
         let start = self.pos;
         let mut end = start;
         let mut colons = 0;
+        let mut group_start = start;
 
         // Double check that the start was okay. We don't start IPv6 after [0-9A-Z:]
         if start > 0
@@ -365,16 +379,12 @@ impl<'a> NetLikeScanner<'a> {
                 b'0'..=b'9' | b'a'..=b'f' | b'A'..=b'F' => {}
                 b':' => {
                     colons += 1;
+                    group_start = end + 1;
                 }
                 b'.' => {
+                    // Only IPv4-mapped addresses get a dotted IPv4 tail.
                     if start + 7 < len
-                        && !(bytes[start] == b':'
-                            && bytes[start + 1] == b':'
-                            && matches!(bytes[start + 2], b'f' | b'F')
-                            && matches!(bytes[start + 3], b'f' | b'F')
-                            && matches!(bytes[start + 4], b'f' | b'F')
-                            && matches!(bytes[start + 5], b'f' | b'F')
-                            && bytes[start + 6] == b':')
+                        && !is_ipv4_mapped_prefix(&bytes[start..group_start])
                     {
                         self.pos = end + 1;
                         return Some((start, end));
@@ -402,6 +412,10 @@ impl<'a> NetLikeScanner<'a> {
             end += 1;
         }
 
+        if colons >= 2 && self.zoneid && end < len && bytes[end] == b'%' {
+            end = self.zone_end(end + 1).unwrap_or(end);
+        }
+
         self.pos = end + 1;
 
         if colons >= 2 {
@@ -411,6 +425,25 @@ impl<'a> NetLikeScanner<'a> {
         }
     }
 
+    /// Returns the end of the zone index (an interface name or number)
+    /// starting at start, or None if there is none. A trailing period
+    /// ends the sentence, not the zone.
+    #[inline]
+    fn zone_end(&self, start: usize) -> Option<usize> {
+        let bytes = self.buf;
+        let mut end = start;
+        while end < bytes.len()
+            && matches!(bytes[end], b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z'
+                | b'_' | b'-' | b'.')
+        {
+            end += 1;
+        }
+        while end > start && matches!(bytes[end - 1], b'.' | b'-') {
+            end -= 1;
+        }
+        (end > start).then_some(end)
+    }
+
     #[inline]
     fn try_ipv4_or_ipv6(&mut self) -> Option<(usize, usize)> {
         // We have at least 7 chars and the first token is 0..9.
@@ -460,6 +493,22 @@ impl<'a> NetLikeScanner<'a> {
     }
 }
 
+/// Returns true if prefix is the ::ffff: of IPv4-mapped addresses, in any
+/// of its forms, like ::FFFF:, 0::ffff: or 0:0:0:0:0:ffff:.
+#[inline]
+fn is_ipv4_mapped_prefix(prefix: &[u8]) -> bool {
+    let Some(rest) = prefix.strip_suffix(b":") else {
+        return false;
+    };
+    let Some(split) = rest.len().checked_sub(4) else {
+        return false;
+    };
+    let (zeros, ffff) = rest.split_at(split);
+    ffff.eq_ignore_ascii_case(b"ffff")
+        && zeros.ends_with(b":")
+        && zeros.iter().all(|&b| matches!(b, b'0' | b':'))
+}
+
 impl Iterator for NetLikeScanner<'_> {
     type Item = (usize, usize);
 
@@ -504,6 +553,11 @@ mod tests {
                 &["::1", "::ffff:10.0.0.1/127", "fd4e:3732:3033::1/64"][..],
                 &["::1", "::ffff:10.0.0.1/127", "fd4e:3732:3033::1/64"][..],
             ),
+            (
+                b"mapped 0:0:0:0:0:FFFF:10.0.0.1, 0::ffff:10.0.0.2/128",
+                &["0:0:0:0:0:FFFF:10.0.0.1", "0::ffff:10.0.0.2/128"][..],
+                &["0:0:0:0:0:FFFF:10.0.0.1", "0::ffff:10.0.0.2/128"][..],
+            ),
             (
                 b"no match: 1.2.3.4.5 and garbage 12a3::zz",
                 &[][..],
@@ -663,4 +717,80 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_short_leftover() {
+        // Fewer than 7 bytes left after the first token: all tokens
+        // must still be tried, and none of the bytes skipped.
+        let cases: &[(&[u8], &[&str])] = &[
+            (b"a ::1", &["::1"]),
+            (b"x and ::1", &["::1"]),
+            (b"fe ::1 ::", &["::1", "::"]),
+            (b"1.2.3.4 ::1", &["1.2.3.4", "::1"]),
+            (b"zz ::", &["::"]),
+        ];
+        for (input, expected) in cases {
+            let got: Vec<_> = NetLikeScanner::new(input)
+                .map(|(s, e)| str::from_utf8(&input[s..e]).unwrap())
+                .collect();
+            assert_eq!(&got, expected, "for input {input:?}");
+        }
+    }
+
+    #[test]
+    fn test_zoneid() {
+        let cases: &[(&[u8], &[&str], &[&str])] = &[
+            (b"fe80::1%eth0 up", &["fe80::1"], &["fe80::1%eth0"]),
+            (b"via fe80::1%2.", &["fe80::1"], &["fe80::1%2"]),
+            (b"[fe80::1%br-lan]:22", &["fe80::1"], &["fe80::1%br-lan"]),
+            (
+                b"vlan fe80::1%eth0.100",
+                &["fe80::1"],
+                &["fe80::1%eth0.100"],
+            ),
+            (
+                b"fe80::1% and ::1%",
+                &["fe80::1", "::1"],
+                &["fe80::1", "::1"],
+            ),
+            (b"1.2.3.4%eth0", &["1.2.3.4"], &["1.2.3.4"]),
+        ];
+        for (input, without, with) in cases {
+            let got: Vec<_> = NetLikeScanner::new(input)
+                .map(|(s, e)| str::from_utf8(&input[s..e]).unwrap())
+                .collect();
+            assert_eq!(&got, without, "for input {input:?}");
+            let got: Vec<_> = NetLikeScanner::new(input)
+                .with_zoneid()
+                .map(|(s, e)| str::from_utf8(&input[s..e]).unwrap())
+                .collect();
+            assert_eq!(&got, with, "for input {input:?} with zone ids");
+        }
+    }
+
+    #[test]
+    fn test_nul_bytes() {
+        let cases: &[(&[u8], &[&str])] = &[
+            (b"\x001.2.3.4\x00", &["1.2.3.4"]),
+            (b"\x00\x00\x00::1\x00\x00", &["::1"]),
+            (b"1.2.\x003.4 5.6.7.8", &["5.6.7.8"]),
+            (b"1.2.3.4\x00/24 ::1\x00/64", &["1.2.3.4", "::1"]),
+            (b"10.0.0.0/\x008", &["10.0.0.0"]),
+            (b"1.2.3.4\x00\x00::1", &["1.2.3.4", "::1"]),
+            (b"\x00", &[]),
+            (b"\x00\x00\x00\x00\x00\x00\x00\x00\x00", &[]),
+            (b"\x001\x00.\x002\x00", &[]),
+        ];
+        for (input, expected) in cases {
+            for scanner in [
+                NetLikeScanner::new(input),
+                NetLikeScanner::new(input).with_oldnet(),
+            ] {
+                let got: Vec<_> = scanner
+                    .map(|(s, e)| str::from_utf8(&input[s..e]).unwrap())
+                    .collect();
+                assert_eq!(&got, expected, "for input {input:?}");
+            }
+        }
+    }
 }
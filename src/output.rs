@@ -1,5 +1,6 @@
 use std::io::{self, Write};
 
+use crate::net::Net;
 use crate::scanner::NetCandidate;
 
 // From GNU grep 3 manual:
@@ -17,6 +18,83 @@ const COLOR_LINENO: &str = "\x1b[0;32m"; // green <number>
 const COLOR_SEPARATOR: &str = "\x1b[0;36m"; // cyan ":"/"-"
 const COLOR_RESET: &str = "\x1b[0m";
 
+/// The colors (SGR escape sequences) of the output parts, or None for
+/// no color. The defaults are above; GREP_COLORS can override them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Colors {
+    // Matches in selected lines ("ms")
+    pub selected_match: Option<String>,
+    // Matches in context lines ("mc")
+    pub context_match: Option<String>,
+    // Filenames ("fn")
+    pub filename: Option<String>,
+    // Line numbers ("ln")
+    pub lineno: Option<String>,
+    // Byte offsets ("bn")
+    pub byte_offset: Option<String>,
+    // Separators ("se")
+    pub separator: Option<String>,
+}
+
+impl Colors {
+    /// Override colors from a GREP_COLORS value, like
+    /// "ms=01;31:mc=01;31:fn=35:ln=32:se=36" ("mt" sets both "ms" and
+    /// "mc"). An empty value, like "fn=", means no color. Unknown keys
+    /// and malformed entries are ignored.
+    pub fn with_grep_colors(mut self, value: &str) -> Self {
+        for entry in value.split(':') {
+            let Some((key, sgr)) = entry.split_once('=') else {
+                continue; // boolean capabilities, like "ne"
+            };
+            if !sgr.bytes().all(|b| b.is_ascii_digit() || b == b';') {
+                continue;
+            }
+            let color = match sgr {
+                "" => None,
+                _ => Some(format!("\x1b[{sgr}m")),
+            };
+            match key {
+                "mt" => {
+                    self.selected_match = color.clone();
+                    self.context_match = color;
+                }
+                "ms" => self.selected_match = color,
+                "mc" => self.context_match = color,
+                "fn" => self.filename = color,
+                "ln" => self.lineno = color,
+                "bn" => self.byte_offset = color,
+                "se" => self.separator = color,
+                _ => {}
+            }
+        }
+        self
+    }
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Self {
+            selected_match: Some(COLOR_MATCH.to_string()),
+            context_match: Some(COLOR_MATCH.to_string()),
+            filename: Some(COLOR_FILENAME.to_string()),
+            lineno: Some(COLOR_LINENO.to_string()),
+            byte_offset: Some(COLOR_LINENO.to_string()),
+            separator: Some(COLOR_SEPARATOR.to_string()),
+        }
+    }
+}
+
+/// Where a line is in its file: its line number and the byte offset of
+/// its first byte, for -n and -b.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinePos {
+    pub lineno: usize,
+    pub offset: u64,
+}
+
+// Prefix for --match-paths output.
+const PATH_MARKER: &[u8] = b"(path):";
+
 /// Output modes, in order of precedence
 #[derive(Debug, PartialEq)]
 pub enum OutputStyle {
@@ -26,26 +104,118 @@ pub enum OutputStyle {
     ShowFilesWithLf,
     // Only files that have a match, NUL terminated
     ShowFilesWithNull,
+    // Only files that have no match
+    ShowFilesWithoutMatchLf,
+    // Only files that have no match, NUL terminated
+    ShowFilesWithoutMatchNull,
     // All files, and a count of matches
     ShowCountsPerFile,
+    // All positive needles, and a count of matches, at the end
+    ShowCountsPerNeedle,
+    // An estimate of the number of unique matches, at the end
+    ShowApproxUnique,
+    // The matches of two needle lists, compared, at the end
+    ShowComparison,
+    // Show the matched networks as a tree, at the end
+    ShowTree,
+    // Show the matched networks aggregated, at the end
+    ShowAggregated,
+    // Show the matches as JSON objects, one per line
+    ShowJsonMatches,
     // Show only the matches (no lines, no context)
     ShowOnlyMatching,
     // Show the lines (and optional context)
     ShowLinesAndContext,
 }
 
+/// When to show colors (--color)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShowColor {
+    // When writing to a terminal, unless NO_COLOR is set
+    Auto,
+    Always,
+    Never,
+}
+
 pub struct Display {
     show_filename: bool,
     show_lineno: bool,
+    show_byte_offset: bool,
     show_color: bool,
+    colors: Colors,
+    csv: bool,
+    // The color for matches printed on their own (None for no color).
+    match_color: Option<String>,
+    // End -o matches with a NUL instead of a linefeed.
+    print0: bool,
+    // Print -o matches normalized instead of as found.
+    canonical: bool,
+    // Print the filename once above the lines/matches of each file,
+    // instead of in front of each.
+    heading: bool,
 }
 
 impl Display {
     pub fn new() -> Self {
         Self {
             show_color: false,
+            colors: Colors::default(),
             show_filename: false,
             show_lineno: false,
+            show_byte_offset: false,
+            csv: false,
+            match_color: Some(COLOR_MATCH.to_string()),
+            print0: false,
+            canonical: false,
+            heading: false,
+        }
+    }
+
+    /// Print counts as CSV rows (always with the filename, never with
+    /// color).
+    pub fn csv(self, value: bool) -> Self {
+        Self { csv: value, ..self }
+    }
+
+    /// Use these colors (when showing color). This also sets the color
+    /// of matches printed on their own, so set match_color() after.
+    pub fn colors(self, colors: Colors) -> Self {
+        Self {
+            match_color: colors.selected_match.clone(),
+            colors,
+            ..self
+        }
+    }
+
+    /// Use these SGR parameters (like "01;32") for the color of matches
+    /// printed on their own (-o/-O), instead of the match color. An empty
+    /// string means no color.
+    pub fn match_color(self, sgr: &str) -> Self {
+        let match_color = match sgr {
+            "" => None,
+            _ => Some(format!("\x1b[{sgr}m")),
+        };
+        Self {
+            match_color,
+            ..self
+        }
+    }
+
+    /// Terminate the matches printed on their own (-o/-O) with a NUL
+    /// instead of a linefeed, for xargs -0.
+    pub fn print0(self, value: bool) -> Self {
+        Self {
+            print0: value,
+            ..self
+        }
+    }
+
+    /// Print the matches printed on their own (-o) the normalized way
+    /// (like ::1 for ::0001) instead of as found on the line.
+    pub fn canonical(self, value: bool) -> Self {
+        Self {
+            canonical: value,
+            ..self
         }
     }
 
@@ -70,6 +240,39 @@ impl Display {
         }
     }
 
+    /// Show the byte offset of the line (or, with -o, of the match).
+    pub fn show_byte_offset(self, value: bool) -> Self {
+        Self {
+            show_byte_offset: value,
+            ..self
+        }
+    }
+
+    /// Print the filename above the lines/matches of each file (with
+    /// print_heading()), instead of in front of each line/match.
+    pub fn heading(self, value: bool) -> Self {
+        Self {
+            heading: value,
+            ..self
+        }
+    }
+
+    pub fn is_heading(&self) -> bool {
+        self.heading
+    }
+
+    /// Print the filename on a line of its own, for --heading.
+    pub fn print_heading(
+        &self,
+        writer: &mut dyn Write,
+        filename: &str,
+    ) -> io::Result<()> {
+        self.write_filename(writer, filename)?;
+        self.write_no_color(writer)?;
+        self.write(writer, b"\n")?;
+        Ok(())
+    }
+
     pub fn print_filename(
         &self,
         writer: &mut dyn Write,
@@ -88,6 +291,9 @@ impl Display {
         filename: &str,
         count: usize,
     ) -> io::Result<()> {
+        if self.csv {
+            return self.write_csv_row(writer, &[filename, &count.to_string()]);
+        }
         if self.show_filename {
             self.write_filename(writer, filename)?;
             self.write_separator(writer, b":")?;
@@ -97,26 +303,148 @@ impl Display {
         Ok(())
     }
 
+    /// Print the grand total of matches over all files, for --total.
+    pub fn print_total(
+        &self,
+        writer: &mut dyn Write,
+        count: usize,
+    ) -> io::Result<()> {
+        if self.csv {
+            return self.write_csv_row(writer, &[&count.to_string()]);
+        }
+        self.write_count(writer, count)
+    }
+
+    pub fn print_count_ratio(
+        &self,
+        writer: &mut dyn Write,
+        filename: &str,
+        count: usize,
+        total: usize,
+    ) -> io::Result<()> {
+        if self.csv {
+            return self.write_csv_row(
+                writer,
+                &[filename, &count.to_string(), &total.to_string()],
+            );
+        }
+        if self.show_filename {
+            self.write_filename(writer, filename)?;
+            self.write_separator(writer, b":")?;
+            self.write_no_color(writer)?;
+        }
+        self.write(writer, format!("{count}/").as_bytes())?;
+        self.write_count(writer, total)?;
+        Ok(())
+    }
+
+    /// Print the -c --split-kind counts of hosts, networks and
+    /// interfaces.
+    pub fn print_kind_counts(
+        &self,
+        writer: &mut dyn Write,
+        filename: &str,
+        [hosts, nets, ifaces]: [usize; 3],
+    ) -> io::Result<()> {
+        if self.show_filename {
+            self.write_filename(writer, filename)?;
+            self.write_separator(writer, b":")?;
+            self.write_no_color(writer)?;
+        }
+        let counts = format!("hosts={hosts} nets={nets} ifaces={ifaces}\n");
+        self.write(writer, counts.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn print_approx_unique(
+        &self,
+        writer: &mut dyn Write,
+        estimate: u64,
+    ) -> io::Result<()> {
+        self.write(
+            writer,
+            format!("~{estimate} unique (estimate)\n").as_bytes(),
+        )
+    }
+
+    /// Print the CSV header row with the column names.
+    pub fn print_csv_header(
+        &self,
+        writer: &mut dyn Write,
+        columns: &[&str],
+    ) -> io::Result<()> {
+        self.write_csv_row(writer, columns)
+    }
+
+    pub fn print_needle_counts<'a, I>(
+        &self,
+        writer: &mut dyn Write,
+        needle_counts: I,
+    ) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (&'a str, usize)>,
+    {
+        for (needle, count) in needle_counts {
+            if self.csv {
+                self.write_csv_row(writer, &[needle, &count.to_string()])?;
+                continue;
+            }
+            self.write_match_color(writer, false)?;
+            self.write_match_manual(writer, needle)?;
+            self.write_separator(writer, b":")?;
+            self.write_no_color(writer)?;
+            self.write(writer, b" ")?;
+            self.write_count(writer, count)?;
+        }
+        Ok(())
+    }
+
     pub fn print_matches(
         &self,
         writer: &mut dyn Write,
         filename: &str,
-        lineno: usize,
+        pos: LinePos,
         line: &[u8],
         matches: &Vec<NetCandidate>,
     ) -> io::Result<()> {
         for match_ in matches {
-            if self.show_filename {
+            if self.prefix_filename() {
                 self.write_filename(writer, filename)?;
                 self.write_separator(writer, b":")?;
             }
-            if self.show_lineno {
-                self.write_linenumber(writer, lineno)?;
+            self.write_position(writer, pos, match_.range.0, b":")?;
+            self.write_match_color(writer, self.has_match_prefix())?;
+            self.write_match(writer, line, match_)?;
+            self.write_match_no_color(writer)?;
+            self.write(writer, self.match_end())?;
+        }
+        Ok(())
+    }
+
+    /// Print the matches like print_matches(), with the hostname (if
+    /// any) in parentheses after each, for --resolve.
+    pub fn print_resolved_matches(
+        &self,
+        writer: &mut dyn Write,
+        filename: &str,
+        pos: LinePos,
+        line: &[u8],
+        matches: &[NetCandidate],
+        names: &[Option<&str>],
+    ) -> io::Result<()> {
+        for (match_, name) in matches.iter().zip(names) {
+            if self.prefix_filename() {
+                self.write_filename(writer, filename)?;
                 self.write_separator(writer, b":")?;
             }
+            self.write_position(writer, pos, match_.range.0, b":")?;
+            self.write_match_color(writer, self.has_match_prefix())?;
             self.write_match(writer, line, match_)?;
-            self.write_no_color(writer)?;
-            self.write(writer, b"\n")?;
+            self.write_match_no_color(writer)?;
+            if let Some(name) = name {
+                self.write(writer, format!(" ({name})").as_bytes())?;
+            }
+            self.write(writer, self.match_end())?;
         }
         Ok(())
     }
@@ -125,32 +453,212 @@ impl Display {
         &self,
         writer: &mut dyn Write,
         filename: &str,
-        lineno: usize,
+        pos: LinePos,
         rewrite_prefix: u8,
         matches: &Vec<NetCandidate>,
     ) -> io::Result<()> {
         for match_ in matches {
-            if self.show_filename {
+            if self.prefix_filename() {
                 self.write_filename(writer, filename)?;
                 self.write_separator(writer, b":")?;
             }
-            if self.show_lineno {
-                self.write_linenumber(writer, lineno)?;
-                self.write_separator(writer, b":")?;
-            }
-            let mut network = match_.net.0;
+            self.write_position(writer, pos, match_.range.0, b":")?;
             // Only adjust downwards (i.e., making the network larger by
             // reducing the prefix length).
-            if rewrite_prefix <= network.prefix_len() {
-                if let Ok(new_net) =
-                    ipnet::IpNet::new(network.addr(), rewrite_prefix)
-                {
-                    network = new_net.trunc();
+            let network = match_.net.supernet(rewrite_prefix);
+            self.write_match_color(writer, self.has_match_prefix())?;
+            self.write_match_manual(writer, &network.to_string())?;
+            self.write_match_no_color(writer)?;
+            self.write(writer, self.match_end())?;
+        }
+        Ok(())
+    }
+
+    /// Print the matches as JSON objects, one per line (NDJSON). Each
+    /// object is written at once, so --line-buffered never splits one.
+    pub fn print_json_matches(
+        &self,
+        writer: &mut dyn Write,
+        filename: &str,
+        lineno: usize,
+        line: &[u8],
+        matches: &Vec<NetCandidate>,
+    ) -> io::Result<()> {
+        for match_ in matches {
+            let (start, end) = match_.range;
+            let text =
+                String::from_utf8_lossy(&line[start..end.min(line.len())]);
+            let family = if match_.net.is_ipv4() { "ipv4" } else { "ipv6" };
+            let port = match match_.port {
+                Some(port) => format!(",\"port\":{port}"),
+                None => String::new(),
+            };
+            let object = format!(
+                "{{\"file\":{},\"line\":{lineno},\"column\":{start},\
+                 \"match\":{},\"network\":\"{}\",\"family\":\"{family}\"\
+                 {port}}}\n",
+                json_string(filename),
+                json_string(&text),
+                match_.net,
+            );
+            writer.write_all(object.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Print that a binary file matches, instead of its lines.
+    pub fn print_binary_matches(
+        &self,
+        writer: &mut dyn Write,
+        filename: &str,
+    ) -> io::Result<()> {
+        self.write(writer, b"Binary file ")?;
+        self.write_filename(writer, filename)?;
+        self.write_no_color(writer)?;
+        self.write(writer, b" matches\n")?;
+        Ok(())
+    }
+
+    /// Print the match texts (collected for --sort), without filename
+    /// or line number.
+    pub fn print_match_texts<'a, I>(
+        &self,
+        writer: &mut dyn Write,
+        texts: I,
+    ) -> io::Result<()>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        for text in texts {
+            self.write_match_color(writer, false)?;
+            self.write_match_manual(writer, text)?;
+            self.write_match_no_color(writer)?;
+            self.write(writer, self.match_end())?;
+        }
+        Ok(())
+    }
+
+    /// Print the --host-index offsets of matches, one per line. Each
+    /// comes with the start of its match on the line, for -b.
+    pub fn print_host_indexes(
+        &self,
+        writer: &mut dyn Write,
+        filename: &str,
+        pos: LinePos,
+        indexes: &[(usize, u128)],
+    ) -> io::Result<()> {
+        for (column, index) in indexes {
+            if self.prefix_filename() {
+                self.write_filename(writer, filename)?;
+                self.write_separator(writer, b":")?;
+            }
+            self.write_position(writer, pos, *column, b":")?;
+            self.write_match_color(writer, self.has_match_prefix())?;
+            self.write_match_manual(writer, &index.to_string())?;
+            self.write_match_no_color(writer)?;
+            self.write(writer, self.match_end())?;
+        }
+        Ok(())
+    }
+
+    /// Print a labeled section of networks, indented below the label.
+    pub fn print_section<'a, I>(
+        &self,
+        writer: &mut dyn Write,
+        label: &str,
+        nets: I,
+    ) -> io::Result<()>
+    where
+        I: IntoIterator<Item = &'a Net>,
+    {
+        self.write(writer, label.as_bytes())?;
+        self.write_separator(writer, b":")?;
+        self.write_no_color(writer)?;
+        self.write(writer, b"\n")?;
+        for net in nets {
+            self.write(writer, b"  ")?;
+            self.write_match_color(writer, false)?;
+            self.write_match_manual(writer, &net.to_string())?;
+            self.write_match_no_color(writer)?;
+            self.write(writer, b"\n")?;
+        }
+        Ok(())
+    }
+
+    /// Print sorted networks as a tree, indenting networks below the
+    /// networks that contain them. Networks nested deeper than
+    /// max_depth are shown at max_depth.
+    pub fn print_tree<'a, I>(
+        &self,
+        writer: &mut dyn Write,
+        sorted_nets: I,
+        max_depth: usize,
+    ) -> io::Result<()>
+    where
+        I: IntoIterator<Item = &'a Net>,
+    {
+        let mut parents: Vec<&Net> = Vec::new();
+        for net in sorted_nets {
+            while let Some(parent) = parents.last() {
+                if parent.contains_net(net) {
+                    break;
                 }
+                parents.pop();
             }
-            self.write_match_manual(writer, &network.to_string())?;
-            self.write_no_color(writer)?;
+            let depth = parents.len().min(max_depth);
+            self.write(writer, "  ".repeat(depth).as_bytes())?;
+            self.write_match_color(writer, false)?;
+            self.write_match_manual(writer, &net.to_string())?;
+            self.write_match_no_color(writer)?;
             self.write(writer, b"\n")?;
+            parents.push(net);
+        }
+        Ok(())
+    }
+
+    /// Print a path (filename) that has matches in itself, marked with
+    /// "(path)" to tell it apart from the file contents.
+    pub fn print_path_line(
+        &self,
+        writer: &mut dyn Write,
+        path: &str,
+        matches: &Vec<NetCandidate>,
+    ) -> io::Result<()> {
+        self.write_separator(writer, PATH_MARKER)?;
+        self.write_no_color(writer)?;
+        self.write_line(
+            writer,
+            path.as_bytes(),
+            matches,
+            &self.colors.selected_match,
+        )?;
+        self.write(writer, self.match_end())?;
+        Ok(())
+    }
+
+    /// Print the matches in a path (filename), like print_matches() and
+    /// print_network_matches() do for lines.
+    pub fn print_path_matches(
+        &self,
+        writer: &mut dyn Write,
+        path: &str,
+        rewrite_prefix: Option<u8>,
+        matches: &Vec<NetCandidate>,
+    ) -> io::Result<()> {
+        for match_ in matches {
+            self.write_separator(writer, PATH_MARKER)?;
+            self.write_filename(writer, path)?;
+            self.write_separator(writer, b":")?;
+            self.write_match_color(writer, true)?;
+            match rewrite_prefix {
+                Some(prefix) => {
+                    let network = match_.net.supernet(prefix);
+                    self.write_match_manual(writer, &network.to_string())?;
+                }
+                None => self.write_match(writer, path.as_bytes(), match_)?,
+            }
+            self.write_match_no_color(writer)?;
+            self.write(writer, self.match_end())?;
         }
         Ok(())
     }
@@ -159,13 +667,11 @@ impl Display {
         &self,
         writer: &mut dyn Write,
         filename: &str,
-        lineno: usize,
+        pos: LinePos,
         line: &[u8],
         matches: &Vec<NetCandidate>,
     ) -> io::Result<()> {
-        self.print_line_with_matches(
-            writer, b"-", filename, lineno, line, matches,
-        )
+        self.print_line_with_matches(writer, b"-", filename, pos, line, matches)
     }
 
     pub fn print_context_delimiter(
@@ -183,13 +689,11 @@ impl Display {
         &self,
         writer: &mut dyn Write,
         filename: &str,
-        lineno: usize,
+        pos: LinePos,
         line: &[u8],
         matches: &Vec<NetCandidate>,
     ) -> io::Result<()> {
-        self.print_line_with_matches(
-            writer, b":", filename, lineno, line, matches,
-        )
+        self.print_line_with_matches(writer, b":", filename, pos, line, matches)
     }
 
     fn print_line_with_matches(
@@ -197,22 +701,23 @@ impl Display {
         writer: &mut dyn Write,
         separator: &[u8],
         filename: &str,
-        lineno: usize,
+        pos: LinePos,
         line: &[u8],
         matches: &Vec<NetCandidate>,
     ) -> io::Result<()> {
-        if self.show_filename {
+        if self.prefix_filename() {
             self.write_filename(writer, filename)?;
             self.write_separator(writer, separator)?;
         }
-        if self.show_lineno {
-            self.write_linenumber(writer, lineno)?;
-            self.write_separator(writer, separator)?;
-        }
-        if self.show_filename || self.show_lineno {
+        self.write_position(writer, pos, 0, separator)?;
+        if self.has_match_prefix() {
             self.write_no_color(writer)?;
         }
-        self.write_line(writer, line, matches)?;
+        let match_color = match separator {
+            b"-" => &self.colors.context_match,
+            _ => &self.colors.selected_match,
+        };
+        self.write_line(writer, line, matches, match_color)?;
         Ok(())
     }
 
@@ -222,6 +727,11 @@ impl Display {
         Ok(())
     }
 
+    #[inline]
+    fn match_end(&self) -> &'static [u8] {
+        if self.print0 { b"\0" } else { b"\n" }
+    }
+
     #[inline]
     fn write_no_color(&self, writer: &mut dyn Write) -> io::Result<()> {
         if self.show_color {
@@ -230,6 +740,26 @@ impl Display {
         Ok(())
     }
 
+    fn write_csv_row(
+        &self,
+        writer: &mut dyn Write,
+        fields: &[&str],
+    ) -> io::Result<()> {
+        for (idx, field) in fields.iter().enumerate() {
+            if idx != 0 {
+                writer.write_all(b",")?;
+            }
+            if field.contains([',', '"', '\n', '\r']) {
+                let quoted = field.replace('"', "\"\"");
+                writer.write_all(format!("\"{quoted}\"").as_bytes())?;
+            } else {
+                writer.write_all(field.as_bytes())?;
+            }
+        }
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+
     #[inline]
     fn write_count(
         &self,
@@ -246,26 +776,69 @@ impl Display {
         writer: &mut dyn Write,
         delim: &[u8],
     ) -> io::Result<()> {
-        if self.show_color {
-            writer.write_all(COLOR_SEPARATOR.as_bytes())?;
+        if self.show_color
+            && let Some(color) = &self.colors.separator
+        {
+            writer.write_all(color.as_bytes())?;
         }
         writer.write_all(delim)?;
         Ok(())
     }
 
+    /// Whether lines and matches get the filename in front.
+    #[inline]
+    fn prefix_filename(&self) -> bool {
+        self.show_filename && !self.heading
+    }
+
     #[inline]
     fn write_filename(
         &self,
         writer: &mut dyn Write,
         filename: &str,
     ) -> io::Result<()> {
-        if self.show_color {
-            writer.write_all(COLOR_FILENAME.as_bytes())?;
+        if self.show_color
+            && let Some(color) = &self.colors.filename
+        {
+            writer.write_all(color.as_bytes())?;
         }
         writer.write_all(filename.as_bytes())?;
         Ok(())
     }
 
+    /// Whether a (colored) filename or position is put before each match
+    /// or line.
+    #[inline]
+    fn has_match_prefix(&self) -> bool {
+        self.prefix_filename() || self.show_lineno || self.show_byte_offset
+    }
+
+    /// Start the color of a match. Uncolored matches (--match-color=none)
+    /// end the color of the prefix before them instead, if there is one.
+    #[inline]
+    fn write_match_color(
+        &self,
+        writer: &mut dyn Write,
+        after_prefix: bool,
+    ) -> io::Result<()> {
+        match &self.match_color {
+            Some(color) if self.show_color => {
+                writer.write_all(color.as_bytes())
+            }
+            None if after_prefix => self.write_no_color(writer),
+            _ => Ok(()),
+        }
+    }
+
+    /// End the color of a match, if it has one.
+    #[inline]
+    fn write_match_no_color(&self, writer: &mut dyn Write) -> io::Result<()> {
+        match &self.match_color {
+            Some(_) => self.write_no_color(writer),
+            None => Ok(()),
+        }
+    }
+
     #[inline]
     fn write_match(
         &self,
@@ -273,8 +846,9 @@ impl Display {
         line: &[u8],
         match_: &NetCandidate,
     ) -> io::Result<()> {
-        if self.show_color {
-            writer.write_all(COLOR_MATCH.as_bytes())?;
+        if self.canonical {
+            writer.write_all(match_.canonical().as_bytes())?;
+            return Ok(());
         }
         let start = match_.range.0;
         let end = match_.range.1;
@@ -288,21 +862,55 @@ impl Display {
         writer: &mut dyn Write,
         custom: &str,
     ) -> io::Result<()> {
-        if self.show_color {
-            writer.write_all(COLOR_MATCH.as_bytes())?;
-        }
         writer.write_all(custom.as_bytes())?;
         Ok(())
     }
 
+    /// Write the line number and/or the byte offset of column on the
+    /// line, each followed by separator.
+    fn write_position(
+        &self,
+        writer: &mut dyn Write,
+        pos: LinePos,
+        column: usize,
+        separator: &[u8],
+    ) -> io::Result<()> {
+        if self.show_lineno {
+            self.write_linenumber(writer, pos.lineno)?;
+            self.write_separator(writer, separator)?;
+        }
+        if self.show_byte_offset {
+            self.write_byte_offset(writer, pos.offset + column as u64)?;
+            self.write_separator(writer, separator)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn write_byte_offset(
+        &self,
+        writer: &mut dyn Write,
+        offset: u64,
+    ) -> io::Result<()> {
+        if self.show_color
+            && let Some(color) = &self.colors.byte_offset
+        {
+            writer.write_all(color.as_bytes())?;
+        }
+        writer.write_all(format!("{offset}").as_bytes())?;
+        Ok(())
+    }
+
     #[inline]
     fn write_linenumber(
         &self,
         writer: &mut dyn Write,
         lineno: usize,
     ) -> io::Result<()> {
-        if self.show_color {
-            writer.write_all(COLOR_LINENO.as_bytes())?;
+        if self.show_color
+            && let Some(color) = &self.colors.lineno
+        {
+            writer.write_all(color.as_bytes())?;
         }
         writer.write_all(format!("{lineno}").as_bytes())?;
         Ok(())
@@ -314,8 +922,11 @@ impl Display {
         writer: &mut dyn Write,
         line: &[u8],
         matches: &Vec<NetCandidate>,
+        match_color: &Option<String>,
     ) -> io::Result<()> {
-        if self.show_color {
+        if self.show_color
+            && let Some(match_color) = match_color
+        {
             let mut cursor = 0;
             for match_ in matches {
                 let start = match_.range.0;
@@ -327,7 +938,7 @@ impl Display {
                 }
 
                 // write the colored match itself
-                writer.write_all(COLOR_MATCH.as_bytes())?;
+                writer.write_all(match_color.as_bytes())?;
                 writer.write_all(&line[start..end.min(line.len())])?;
                 writer.write_all(COLOR_RESET.as_bytes())?;
 
@@ -345,6 +956,27 @@ impl Display {
     }
 }
 
+/// Quote and escape a string for JSON output.
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            ch if ch < ' ' => {
+                quoted.push_str(&format!("\\u{:04x}", ch as u32));
+            }
+            ch => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
 impl Default for Display {
     fn default() -> Self {
         Self::new()
@@ -357,6 +989,12 @@ mod tests {
     use regex::Regex;
 
     use crate::net::Net;
+    use crate::scanner::MatchKind;
+
+    /// A line position without byte offset.
+    fn pos(lineno: usize) -> LinePos {
+        LinePos { lineno, offset: 0 }
+    }
 
     /// Helper that runs a test for both color modes and compares output.
     fn check_display<F>(mut disp: Display, expected: &str, mut do_display: F)
@@ -391,6 +1029,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn display_print_heading() {
+        check_display(
+            Display::new().show_filename(true).heading(true),
+            "\u{1b}[0;35mfile\u{1b}[0m\n",
+            |d, o| d.print_heading(o, "file"),
+        );
+        // No filename prefix on the lines under the heading.
+        let line = b"ip 10.0.0.1\n";
+        let matches = vec![NetCandidate {
+            range: (3, 11),
+            net: Net::from_str_unchecked("10.0.0.1"),
+            kind: MatchKind::Host,
+            port: None,
+        }];
+        check_display(
+            Display::new().show_filename(true).heading(true),
+            "\u{1b}[1;31m10.0.0.1\u{1b}[0m\n",
+            |d, o| d.print_matches(o, "fn", pos(1), line, &matches),
+        );
+    }
+
     #[test]
     fn display_print_filename_null() {
         check_display(
@@ -416,6 +1076,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn display_print_count_ratio() {
+        check_display(Display::new(), "12/3000\n", |d, o| {
+            d.print_count_ratio(o, "filename not shown", 12, 3000)
+        });
+        check_display(
+            Display::new().show_filename(true),
+            "\u{1b}[0;35mfile\u{1b}[0;36m:\u{1b}[0m0/0\n",
+            |d, o| d.print_count_ratio(o, "file", 0, 0),
+        );
+    }
+
+    #[test]
+    fn display_print_counts_csv() {
+        // No color and always a filename.
+        check_display(
+            Display::new().csv(true),
+            "path,count\nfile.txt,42\n\"a,\"\"b\"\"\",0\n",
+            |d, o| {
+                d.print_csv_header(o, &["path", "count"])?;
+                d.print_counts(o, "file.txt", 42)?;
+                d.print_counts(o, "a,\"b\"", 0)
+            },
+        );
+        check_display(
+            Display::new().csv(true),
+            "file.txt,12,3000\n10.0.0.0/8,3\n",
+            |d, o| {
+                d.print_count_ratio(o, "file.txt", 12, 3000)?;
+                d.print_needle_counts(o, [("10.0.0.0/8", 3)])
+            },
+        );
+    }
+
     #[test]
     fn display_print_matches() {
         let line = b"nets: 10.20.30.1-10.20.30.20 <--\n";
@@ -423,29 +1117,33 @@ mod tests {
             NetCandidate {
                 range: (6, 16),
                 net: Net::from_str_unchecked("10.20.30.1"),
+                kind: MatchKind::Host,
+                port: None,
             },
             NetCandidate {
                 range: (17, 28),
                 net: Net::from_str_unchecked("10.20.30.20"),
+                kind: MatchKind::Host,
+                port: None,
             },
         ];
         check_display(
             Display::new(),
             "\u{1b}[1;31m10.20.30.1\u{1b}[0m\n\
              \u{1b}[1;31m10.20.30.20\u{1b}[0m\n",
-            |d, o| d.print_matches(o, "fn", 351, line, &matches),
+            |d, o| d.print_matches(o, "fn", pos(351), line, &matches),
         );
         check_display(
             Display::new().show_filename(true),
             "\u{1b}[0;35mfn\u{1b}[0;36m:\u{1b}[1;31m10.20.30.1\u{1b}[0m\n\
              \u{1b}[0;35mfn\u{1b}[0;36m:\u{1b}[1;31m10.20.30.20\u{1b}[0m\n",
-            |d, o| d.print_matches(o, "fn", 352, line, &matches),
+            |d, o| d.print_matches(o, "fn", pos(352), line, &matches),
         );
         check_display(
             Display::new().show_lineno(true),
             "\u{1b}[0;32m353\u{1b}[0;36m:\u{1b}[1;31m10.20.30.1\u{1b}[0m\n\
              \u{1b}[0;32m353\u{1b}[0;36m:\u{1b}[1;31m10.20.30.20\u{1b}[0m\n",
-            |d, o| d.print_matches(o, "fn", 353, line, &matches),
+            |d, o| d.print_matches(o, "fn", pos(353), line, &matches),
         );
         check_display(
             Display::new().show_filename(true).show_lineno(true),
@@ -453,8 +1151,106 @@ mod tests {
              :\u{1b}[1;31m10.20.30.1\u{1b}[0m\n\
              \u{1b}[0;35mfn\u{1b}[0;36m:\u{1b}[0;32m354\u{1b}[0;36m\
              :\u{1b}[1;31m10.20.30.20\u{1b}[0m\n",
-            |d, o| d.print_matches(o, "fn", 354, line, &matches),
+            |d, o| d.print_matches(o, "fn", pos(354), line, &matches),
+        );
+    }
+
+    #[test]
+    fn display_match_color() {
+        let line = b"ip 10.0.0.1\n";
+        let matches = vec![NetCandidate {
+            range: (3, 11),
+            net: Net::from_str_unchecked("10.0.0.1"),
+            kind: MatchKind::Host,
+            port: None,
+        }];
+        check_display(
+            Display::new().match_color("01;32"),
+            "\u{1b}[01;32m10.0.0.1\u{1b}[0m\n",
+            |d, o| d.print_matches(o, "fn", pos(1), line, &matches),
+        );
+        check_display(Display::new().match_color(""), "10.0.0.1\n", |d, o| {
+            d.print_matches(o, "fn", pos(1), line, &matches)
+        });
+        // Without a match color, only the prefix color is ended.
+        check_display(
+            Display::new().show_filename(true).match_color(""),
+            "\u{1b}[0;35mfn\u{1b}[0;36m:\u{1b}[0m10.0.0.1\n",
+            |d, o| d.print_matches(o, "fn", pos(1), line, &matches),
         );
+        // Matches inside lines keep the default color.
+        check_display(
+            Display::new().match_color(""),
+            "ip \u{1b}[1;31m10.0.0.1\u{1b}[0m\n",
+            |d, o| d.print_line(o, "fn", pos(1), line, &matches),
+        );
+    }
+
+    #[test]
+    fn display_print_resolved_matches() {
+        let line = b"from 10.0.0.1 to 10.0.0.2\n";
+        let matches = vec![
+            NetCandidate {
+                range: (5, 13),
+                net: Net::from_str_unchecked("10.0.0.1"),
+                kind: MatchKind::Host,
+                port: None,
+            },
+            NetCandidate {
+                range: (17, 25),
+                net: Net::from_str_unchecked("10.0.0.2"),
+                kind: MatchKind::Host,
+                port: None,
+            },
+        ];
+        check_display(
+            Display::new().show_lineno(true),
+            "\u{1b}[0;32m3\u{1b}[0;36m:\u{1b}[1;31m10.0.0.1\u{1b}[0m \
+             (host.example.com)\n\
+             \u{1b}[0;32m3\u{1b}[0;36m:\u{1b}[1;31m10.0.0.2\u{1b}[0m\n",
+            |d, o| {
+                let names = [Some("host.example.com"), None];
+                d.print_resolved_matches(
+                    o,
+                    "fn",
+                    pos(3),
+                    line,
+                    &matches,
+                    &names,
+                )
+            },
+        );
+    }
+
+    #[test]
+    fn display_print_json_matches() {
+        let line = b"at 10.0.0.1 and 2001:db8::/32\n";
+        let matches = vec![
+            NetCandidate {
+                range: (3, 11),
+                net: Net::from_str_unchecked("10.0.0.1"),
+                kind: MatchKind::Host,
+                port: None,
+            },
+            NetCandidate {
+                range: (16, 29),
+                net: Net::from_str_unchecked("2001:db8::/32"),
+                kind: MatchKind::Network,
+                port: None,
+            },
+        ];
+        // Never colored, and the filename is escaped.
+        check_display(
+            Display::new().show_filename(true),
+            "{\"file\":\"a \\\"b\\\\c\\n\",\"line\":7,\"column\":3,\
+             \"match\":\"10.0.0.1\",\"network\":\"10.0.0.1/32\",\
+             \"family\":\"ipv4\"}\n\
+             {\"file\":\"a \\\"b\\\\c\\n\",\"line\":7,\"column\":16,\
+             \"match\":\"2001:db8::/32\",\"network\":\"2001:db8::/32\",\
+             \"family\":\"ipv6\"}\n",
+            |d, o| d.print_json_matches(o, "a \"b\\c\n", 7, line, &matches),
+        );
+        assert_eq!(json_string("\x01\t"), "\"\\u0001\\t\"");
     }
 
     #[test]
@@ -464,14 +1260,20 @@ mod tests {
             NetCandidate {
                 range: (6, 16),
                 net: Net::from_str_unchecked("10.20.30.1"),
+                kind: MatchKind::Host,
+                port: None,
             },
             NetCandidate {
                 range: (17, 28),
                 net: Net::from_str_unchecked("10.20.30.20"),
+                kind: MatchKind::Host,
+                port: None,
             },
             NetCandidate {
                 range: (29, 42),
                 net: Net::from_str_unchecked("192.168.2.129"),
+                kind: MatchKind::Host,
+                port: None,
             },
         ];
         check_display(
@@ -479,14 +1281,54 @@ mod tests {
             "\u{1b}[1;31m10.20.30.0/24\u{1b}[0m\n\
              \u{1b}[1;31m10.20.30.0/24\u{1b}[0m\n\
              \u{1b}[1;31m192.168.2.0/24\u{1b}[0m\n",
-            |d, o| d.print_network_matches(o, "fn", 351, 24, &matches),
+            |d, o| d.print_network_matches(o, "fn", pos(351), 24, &matches),
         );
         check_display(
             Display::new(),
             "\u{1b}[1;31m10.20.30.0/29\u{1b}[0m\n\
              \u{1b}[1;31m10.20.30.16/29\u{1b}[0m\n\
              \u{1b}[1;31m192.168.2.128/29\u{1b}[0m\n",
-            |d, o| d.print_network_matches(o, "fn", 351, 29, &matches),
+            |d, o| d.print_network_matches(o, "fn", pos(351), 29, &matches),
+        );
+    }
+
+    #[test]
+    fn display_print_tree() {
+        let mut nets: Vec<Net> = [
+            "10.1.1.0/24",
+            "10.0.0.0/8",
+            "10.2.0.0/16",
+            "10.1.0.0/16",
+            "10.1.1.1",
+            "192.168.1.1",
+            "::/0",
+        ]
+        .into_iter()
+        .map(Net::from_str_unchecked)
+        .collect();
+        nets.sort();
+        check_display(
+            Display::new(),
+            "\u{1b}[1;31m10.0.0.0/8\u{1b}[0m\n\
+             \x20 \u{1b}[1;31m10.1.0.0/16\u{1b}[0m\n\
+             \x20   \u{1b}[1;31m10.1.1.0/24\u{1b}[0m\n\
+             \x20     \u{1b}[1;31m10.1.1.1/32\u{1b}[0m\n\
+             \x20 \u{1b}[1;31m10.2.0.0/16\u{1b}[0m\n\
+             \u{1b}[1;31m192.168.1.1/32\u{1b}[0m\n\
+             \u{1b}[1;31m::/0\u{1b}[0m\n",
+            |d, o| d.print_tree(o, &nets, 8),
+        );
+        // Depth is bounded.
+        check_display(
+            Display::new(),
+            "\u{1b}[1;31m10.0.0.0/8\u{1b}[0m\n\
+             \x20 \u{1b}[1;31m10.1.0.0/16\u{1b}[0m\n\
+             \x20 \u{1b}[1;31m10.1.1.0/24\u{1b}[0m\n\
+             \x20 \u{1b}[1;31m10.1.1.1/32\u{1b}[0m\n\
+             \x20 \u{1b}[1;31m10.2.0.0/16\u{1b}[0m\n\
+             \u{1b}[1;31m192.168.1.1/32\u{1b}[0m\n\
+             \u{1b}[1;31m::/0\u{1b}[0m\n",
+            |d, o| d.print_tree(o, &nets, 1),
         );
     }
 
@@ -494,23 +1336,65 @@ mod tests {
     fn display_print_context() {
         let line = b"whatever context\n";
         check_display(Display::new(), "whatever context\n", |d, o| {
-            d.print_context(o, "fn", 1231, line, &vec![])
+            d.print_context(o, "fn", pos(1231), line, &vec![])
         });
         check_display(
             Display::new().show_filename(true),
             "\u{1b}[0;35mfnX\u{1b}[0;36m-\u{1b}[0mwhatever context\n",
-            |d, o| d.print_context(o, "fnX", 1232, line, &vec![]),
+            |d, o| d.print_context(o, "fnX", pos(1232), line, &vec![]),
         );
         check_display(
             Display::new().show_lineno(true),
             "\u{1b}[0;32m1233\u{1b}[0;36m-\u{1b}[0mwhatever context\n",
-            |d, o| d.print_context(o, "fnY", 1233, line, &vec![]),
+            |d, o| d.print_context(o, "fnY", pos(1233), line, &vec![]),
         );
         check_display(
             Display::new().show_filename(true).show_lineno(true),
             "\u{1b}[0;35mfnZ\u{1b}[0;36m-\u{1b}[0;32m1234\
              \u{1b}[0;36m-\u{1b}[0mwhatever context\n",
-            |d, o| d.print_context(o, "fnZ", 1234, line, &vec![]),
+            |d, o| d.print_context(o, "fnZ", pos(1234), line, &vec![]),
+        );
+    }
+
+    #[test]
+    fn colors_with_grep_colors() {
+        let colors = Colors::default()
+            .with_grep_colors("ms=01;32:mc=:fn=x:ln=33:bn=34:ne:rv:zz=1");
+        assert_eq!(colors.selected_match.as_deref(), Some("\x1b[01;32m"));
+        assert_eq!(colors.context_match, None, "empty means no color");
+        assert_eq!(colors.filename.as_deref(), Some(COLOR_FILENAME));
+        assert_eq!(colors.lineno.as_deref(), Some("\x1b[33m"));
+        assert_eq!(colors.byte_offset.as_deref(), Some("\x1b[34m"));
+        assert_eq!(colors.separator.as_deref(), Some(COLOR_SEPARATOR));
+
+        let colors = Colors::default().with_grep_colors("mt=7");
+        assert_eq!(colors.selected_match.as_deref(), Some("\x1b[7m"));
+        assert_eq!(colors.context_match.as_deref(), Some("\x1b[7m"));
+        assert_eq!(Colors::default().with_grep_colors(""), Colors::default());
+    }
+
+    #[test]
+    fn display_grep_colors() {
+        let line = b"at 10.0.0.1\n";
+        let matches = vec![NetCandidate {
+            range: (3, 11),
+            net: Net::from_str_unchecked("10.0.0.1"),
+            kind: MatchKind::Host,
+            port: None,
+        }];
+        let colors =
+            Colors::default().with_grep_colors("ms=4:mc=:fn=:ln=33:se=");
+        let disp = Display::new().show_lineno(true).colors(colors);
+        check_display(
+            disp,
+            "\u{1b}[33m5:\u{1b}[0mat \u{1b}[4m10.0.0.1\u{1b}[0m\n\
+             \u{1b}[33m6-\u{1b}[0mat 10.0.0.1\n\
+             \u{1b}[33m7:\u{1b}[4m10.0.0.1\u{1b}[0m\n",
+            |d, o| {
+                d.print_line(o, "fn", pos(5), line, &matches)?;
+                d.print_context(o, "fn", pos(6), line, &matches)?;
+                d.print_matches(o, "fn", pos(7), line, &matches)
+            },
         );
     }
 
@@ -540,31 +1424,35 @@ mod tests {
             NetCandidate {
                 range: (1, 15),
                 net: Net::from_str_unchecked("::ffff:1.2.3.4"),
+                kind: MatchKind::Host,
+                port: None,
             },
             NetCandidate {
                 range: (16, 27),
                 net: Net::from_str_unchecked("255.255.0.0"),
+                kind: MatchKind::Host,
+                port: None,
             },
         ];
         check_display(
             Display::new(),
             "/\u{1b}[1;31m::ffff.1.2.3.4\u{1b}[0m\
              /\u{1b}[1;31m255.255.0.0\u{1b}[0m/\n",
-            |d, o| d.print_line(o, "fn", 1231, line, &matches),
+            |d, o| d.print_line(o, "fn", pos(1231), line, &matches),
         );
         check_display(
             Display::new().show_filename(true),
             "\u{1b}[0;35msome_fn\u{1b}[0;36m:\u{1b}[0m\
              /\u{1b}[1;31m::ffff.1.2.3.4\u{1b}[0m\
              /\u{1b}[1;31m255.255.0.0\u{1b}[0m/\n",
-            |d, o| d.print_line(o, "some_fn", 1232, line, &matches),
+            |d, o| d.print_line(o, "some_fn", pos(1232), line, &matches),
         );
         check_display(
             Display::new().show_lineno(true),
             "\u{1b}[0;32m1233\u{1b}[0;36m:\u{1b}[0m\
              /\u{1b}[1;31m::ffff.1.2.3.4\u{1b}[0m\
              /\u{1b}[1;31m255.255.0.0\u{1b}[0m/\n",
-            |d, o| d.print_line(o, "some_fn", 1233, line, &matches),
+            |d, o| d.print_line(o, "some_fn", pos(1233), line, &matches),
         );
         check_display(
             Display::new().show_filename(true).show_lineno(true),
@@ -572,7 +1460,7 @@ mod tests {
              \u{1b}[0;32m1234\u{1b}[0;36m:\u{1b}[0m\
              /\u{1b}[1;31m::ffff.1.2.3.4\u{1b}[0m\
              /\u{1b}[1;31m255.255.0.0\u{1b}[0m/\n",
-            |d, o| d.print_line(o, "some_fn", 1234, line, &matches),
+            |d, o| d.print_line(o, "some_fn", pos(1234), line, &matches),
         );
     }
 }
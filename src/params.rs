@@ -1,8 +1,13 @@
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
 pub use crate::context::ShowContext; // re-export
-pub use crate::files::RecurseHaystacks; // re-export
+pub use crate::diagnostics::Diagnostics; // re-export
+pub use crate::files::{BinaryFiles, Devices, RecurseHaystacks}; // re-export
 pub use crate::matching::{AcceptSet, InterfaceMode, MatchMode}; // re-export
+pub use crate::mmdb::GeoFilter; // re-export
 pub use crate::needle::Needle; // re-export
-pub use crate::output::OutputStyle; // re-export
+pub use crate::output::{OutputStyle, ShowColor}; // re-export
 
 #[derive(Debug)]
 pub struct Parameters {
@@ -10,21 +15,83 @@ pub struct Parameters {
     pub accept: AcceptSet,
     pub interface_mode: InterfaceMode,
     pub match_mode: MatchMode,
+    pub report_other_family: bool,
+    pub match_all: bool,
+    pub outside: bool,
+    pub also_literals: Vec<String>,
+    pub or_literals: Vec<String>,
+    pub ipv4_mapped: bool,
+    pub map_v4: bool,
+    // Some(ranges) for --with-ports; no ranges is any port (or none).
+    pub ports: Option<Vec<RangeInclusive<u16>>>,
+    // Some(n) for --nth: 1-based, or counting back from the end if < 0.
+    pub nth: Option<isize>,
+    // The accepted prefix lengths, for --prefix-len-min/max.
+    pub v4_prefix_lens: RangeInclusive<u8>,
+    pub v6_prefix_lens: RangeInclusive<u8>,
+    pub geo_filter: Option<GeoFilter>,
+    pub leading_zeros: bool,
+    pub strict_boundaries: bool,
+    pub dedup_specific: bool,
+    pub match_paths: bool,
     // General Output Control:
     pub output_style: OutputStyle,
     pub rewrite_output_prefix: Option<u8>,
+    pub canonical: bool,
+    pub unique: bool,
+    // Some(n) for --unique-cap: remember at most n unique networks.
+    pub unique_cap: Option<usize>,
+    pub sort: bool,
+    pub resolve: bool,
+    pub host_index: bool,
+    pub count_matches: bool,
+    pub count_ratio: bool,
+    pub count_total: bool,
+    pub count_to_stderr: bool,
+    pub split_kind: bool,
+    pub exec_command: Vec<String>,
+    pub exec_batch: bool,
+    pub csv: bool,
+    pub csv_header: bool,
+    pub squeeze: bool,
+    pub print0: bool,
+    pub color: ShowColor,
+    pub match_color: Option<String>,
+    pub tree_depth: usize,
     pub invert_match: bool,
+    pub max_count: Option<usize>,
+    pub no_messages: bool,
     // Output Line Prefix Control:
     pub hide_filename: bool,
+    pub force_filename: bool,
+    pub heading: bool,
+    pub label: Option<String>,
     pub show_lineno: bool,
+    pub show_byte_offset: bool,
     // Context Line Control:
     pub show_context: ShowContext,
     // File and Directory Selection:
     pub recursive: RecurseHaystacks,
+    pub follow_arg_symlinks: bool,
+    pub include_globs: Vec<String>,
+    pub exclude_globs: Vec<String>,
+    pub binary_files: BinaryFiles,
+    pub devices: Devices,
+    pub max_filesize: Option<u64>,
     // Other Options:
     pub line_buffered: bool,
+    pub null_data: bool,
+    pub strip_ansi: bool,
+    pub threads: usize,
+    pub scan_limit: Option<u64>,
+    pub debug: bool,
+    pub stats: bool,
+    // Where the warnings about the input go.
+    pub diagnostics: Arc<dyn Diagnostics>,
     // Positional arguments:
     pub positive_needles: Vec<Needle>,
     pub negative_needles: Vec<Needle>,
+    // Needle lists A and B, for --compare.
+    pub compare_needles: Option<(Vec<Needle>, Vec<Needle>)>,
     pub haystack_filenames: Vec<String>,
 }
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::net::IpAddr;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::diagnostics::Diagnostics;
+
+// At most this many lookups run at the same time.
+const RESOLVE_JOBS: usize = 8;
+// A slow DNS server should not hang the whole run.
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Looks up the hostname of an address. Returns an error if the lookup
+/// could not be done at all.
+pub type Lookup = fn(IpAddr) -> io::Result<Option<String>>;
+
+/// Reverse (PTR) lookups for --resolve, cached for the whole run. The
+/// lookups are done by getent(1), so they follow the system resolver
+/// configuration (/etc/hosts, DNS) without linking a resolver.
+pub struct Resolver {
+    cache: HashMap<IpAddr, Option<String>>,
+    lookup: Lookup,
+    diagnostics: Arc<dyn Diagnostics>,
+    // Whether a failed lookup was reported; only the first one is.
+    has_warned: bool,
+}
+
+impl Resolver {
+    pub fn new(diagnostics: Arc<dyn Diagnostics>) -> Self {
+        Self {
+            cache: HashMap::new(),
+            lookup: |addr| lookup_ptr(addr, RESOLVE_TIMEOUT),
+            diagnostics,
+            has_warned: false,
+        }
+    }
+
+    /// Use another lookup than getent(1), for the tests.
+    #[cfg(test)]
+    pub fn with_lookup(self, lookup: Lookup) -> Self {
+        Self { lookup, ..self }
+    }
+
+    /// Look up the addresses that are not cached yet, RESOLVE_JOBS at
+    /// a time.
+    pub fn resolve_all(&mut self, addrs: &[IpAddr]) {
+        let mut todo: Vec<IpAddr> = addrs
+            .iter()
+            .filter(|addr| !self.cache.contains_key(addr))
+            .copied()
+            .collect();
+        todo.sort();
+        todo.dedup();
+
+        let lookup = self.lookup;
+        for batch in todo.chunks(RESOLVE_JOBS) {
+            let results: Vec<io::Result<Option<String>>> =
+                thread::scope(|scope| {
+                    let handles: Vec<_> = batch
+                        .iter()
+                        .map(|&addr| scope.spawn(move || lookup(addr)))
+                        .collect();
+                    handles
+                        .into_iter()
+                        .map(|handle| handle.join().unwrap_or(Ok(None)))
+                        .collect()
+                });
+            for (&addr, result) in batch.iter().zip(results) {
+                let name = result.unwrap_or_else(|err| {
+                    // Like a missing getent: say so once, not per address.
+                    if !self.has_warned {
+                        self.has_warned = true;
+                        self.diagnostics.warn(
+                            "getent",
+                            &format!("cannot look up hostnames: {err}"),
+                        );
+                    }
+                    None
+                });
+                self.cache.insert(addr, name);
+            }
+        }
+    }
+
+    /// The hostname of a resolved address, if it has one.
+    pub fn name(&self, addr: &IpAddr) -> Option<&str> {
+        self.cache.get(addr)?.as_deref()
+    }
+}
+
+/// Look up the hostname of addr using "getent hosts". Returns None if
+/// there is none, or if the lookup takes longer than timeout, and an
+/// error if getent cannot be run.
+fn lookup_ptr(addr: IpAddr, timeout: Duration) -> io::Result<Option<String>> {
+    let mut child = Command::new("getent")
+        .arg("hosts")
+        .arg(addr.to_string())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => break,
+            Ok(None) if Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(5));
+            }
+            Ok(Some(_)) => return Ok(None), // not found
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Ok(None);
+            }
+        }
+    }
+
+    let mut output = String::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        stdout.read_to_string(&mut output)?;
+    }
+    Ok(parse_getent_hosts(&output))
+}
+
+/// Take the hostname from "getent hosts" output: "ADDRESS NAME ALIAS...".
+fn parse_getent_hosts(output: &str) -> Option<String> {
+    output.split_whitespace().nth(1).map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::Collect;
+
+    #[test]
+    fn test_parse_getent_hosts() {
+        assert_eq!(
+            parse_getent_hosts("127.0.0.1       localhost\n").as_deref(),
+            Some("localhost")
+        );
+        assert_eq!(
+            parse_getent_hosts("::1  ip6-localhost ip6-loopback\n").as_deref(),
+            Some("ip6-localhost")
+        );
+        assert_eq!(parse_getent_hosts(""), None);
+    }
+
+    /// Knows only 192.0.2.1, and fails for 198.51.100.0/24.
+    fn stub_lookup(addr: IpAddr) -> io::Result<Option<String>> {
+        match addr.to_string().as_str() {
+            "192.0.2.1" => Ok(Some("one.example".into())),
+            s if s.starts_with("198.51.100.") => {
+                Err(io::Error::new(io::ErrorKind::NotFound, "no getent"))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    #[test]
+    fn test_resolve_all() {
+        let known: IpAddr = "192.0.2.1".parse().unwrap();
+        let unknown: IpAddr = "192.0.2.2".parse().unwrap();
+        let diagnostics = Arc::new(Collect::default());
+        let mut resolver =
+            Resolver::new(diagnostics.clone()).with_lookup(stub_lookup);
+        resolver.resolve_all(&[known, unknown, unknown]);
+        assert_eq!(resolver.name(&known), Some("one.example"));
+        assert_eq!(resolver.name(&unknown), None);
+        assert_eq!(resolver.cache.len(), 2, "looked up once");
+
+        // Unresolved addresses are not in the cache.
+        let other: IpAddr = "192.0.2.3".parse().unwrap();
+        assert_eq!(resolver.name(&other), None);
+        assert!(!resolver.cache.contains_key(&other));
+
+        // Failed lookups are warned about once.
+        let failing: Vec<IpAddr> = (1..=20)
+            .map(|i| format!("198.51.100.{i}").parse().unwrap())
+            .collect();
+        resolver.resolve_all(&failing);
+        assert_eq!(resolver.name(&failing[0]), None);
+        assert_eq!(
+            diagnostics.take(),
+            ["getent: cannot look up hostnames: no getent"]
+        );
+    }
+}
@@ -1,5 +1,10 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
 use memchr::{memchr_iter, memchr2_iter};
 
+use crate::diagnostics::{Diagnostics, Stderr};
 use crate::matching::{AcceptSet, InterfaceMode};
 use crate::net::Net;
 use crate::netlike::NetLikeScanner;
@@ -8,6 +13,59 @@ use crate::netlike::NetLikeScanner;
 pub struct NetCandidate {
     pub range: (usize, usize),
     pub net: Net,
+    pub kind: MatchKind,
+    /// The port after the address (1.2.3.4:80), for --with-ports. The
+    /// range then includes it.
+    pub port: Option<u16>,
+}
+
+impl NetCandidate {
+    /// The match written the normalized way, for --canonical: 10.0.0.1
+    /// for a single address, 10.0.0.0/24 for a network, with the port (if
+    /// any) after it like 10.0.0.1:443 or [2001:db8::1]:443.
+    pub fn canonical(&self) -> String {
+        let addr = match self.net.is_single_ip() {
+            true => self.net.0.addr().to_string(),
+            false => self.net.to_string(),
+        };
+        match self.port {
+            Some(port) if self.net.is_ipv6() => format!("[{addr}]:{port}"),
+            Some(port) => format!("{addr}:{port}"),
+            None => addr,
+        }
+    }
+}
+
+/// The form a candidate was written in.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MatchKind {
+    /// A bare IP (10.0.0.1), or a single address network (10.0.0.1/32).
+    #[default]
+    Host,
+    /// A network without host bits (10.0.0.0/24, 10.0.0.0/255.255.255.0).
+    Network,
+    /// An interface: an IP with a prefix and host bits (10.0.0.1/24).
+    Interface,
+}
+
+/// An invalid NetCandidateScanner configuration, returned by build().
+#[derive(Debug, PartialEq)]
+pub enum ScannerError {
+    NoFamilies,
+    EmptyAccept,
+}
+
+impl fmt::Display for ScannerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScannerError::NoFamilies => {
+                write!(f, "no IPv4 or IPv6 needles supplied")
+            }
+            ScannerError::EmptyAccept => {
+                write!(f, "no input forms (ip/net/oldnet/iface) accepted")
+            }
+        }
+    }
 }
 
 pub struct NetCandidateScanner {
@@ -17,8 +75,23 @@ pub struct NetCandidateScanner {
     accept: AcceptSet,
     // These is first relevant here after we've found the matches:
     interface_mode: InterfaceMode,
+    ipv4_mapped: bool,
+    leading_zeros: bool,
+    // Only accept candidates bounded by whitespace or a few punctuation
+    // characters.
+    strict_boundaries: bool,
+    // Look for ports after addresses; only keep those in the ranges, if
+    // there are any.
+    ports: Option<Vec<RangeInclusive<u16>>>,
+    // The accepted prefix lengths, as found (before any interface
+    // translation).
+    v4_prefix_lens: RangeInclusive<u8>,
+    v6_prefix_lens: RangeInclusive<u8>,
+    // Where InterfaceMode::ComplainAndSkip complains.
+    diagnostics: Arc<dyn Diagnostics>,
 }
 
+#[allow(clippy::new_without_default)]
 impl NetCandidateScanner {
     pub fn new() -> Self {
         Self {
@@ -26,22 +99,23 @@ impl NetCandidateScanner {
             include_ipv6: true,
             accept: AcceptSet::default(),
             interface_mode: InterfaceMode::default(),
+            ipv4_mapped: false,
+            leading_zeros: false,
+            strict_boundaries: false,
+            ports: None,
+            v4_prefix_lens: 0..=32,
+            v6_prefix_lens: 0..=128,
+            diagnostics: Arc::new(Stderr),
         }
     }
 
     pub fn ignore_ipv4(self, value: bool) -> Self {
-        if value && !self.include_ipv6 {
-            panic!("no IPv4 or IPv6 needles supplied");
-        }
         Self {
             include_ipv4: !value,
             ..self
         }
     }
     pub fn ignore_ipv6(self, value: bool) -> Self {
-        if value && !self.include_ipv4 {
-            panic!("no IPv4 or IPv6 needles supplied");
-        }
         Self {
             include_ipv6: !value,
             ..self
@@ -49,7 +123,6 @@ impl NetCandidateScanner {
     }
 
     pub fn set_accept(self, accept: AcceptSet) -> Self {
-        assert!(accept.ip || accept.net || accept.oldnet || accept.iface);
         Self { accept, ..self }
     }
 
@@ -60,100 +133,346 @@ impl NetCandidateScanner {
         }
     }
 
+    /// Treat IPv4-mapped IPv6 addresses (::ffff:10.0.0.1) as the IPv4
+    /// address they map.
+    pub fn set_ipv4_mapped(self, value: bool) -> Self {
+        Self {
+            ipv4_mapped: value,
+            ..self
+        }
+    }
+
+    /// Accept zero-padded IPv4 octets (010.000.000.001), read as
+    /// decimal. By default these are not IPs.
+    pub fn set_leading_zeros(self, value: bool) -> Self {
+        Self {
+            leading_zeros: value,
+            ..self
+        }
+    }
+
+    /// Only accept candidates that are bounded by whitespace, the start
+    /// or end of the buffer, or one of ,()[]"' on both sides. This
+    /// rejects those glued to letters or colons, like in "id=x10.0.0.1:",
+    /// and also those followed by a port, unless the port is recorded.
+    pub fn set_strict_boundaries(self, value: bool) -> Self {
+        Self {
+            strict_boundaries: value,
+            ..self
+        }
+    }
+
+    /// Record the port after an address (1.2.3.4:80, [::1]:80). With
+    /// port ranges, only addresses with a port in them are candidates.
+    pub fn set_ports(self, ports: Option<Vec<RangeInclusive<u16>>>) -> Self {
+        Self { ports, ..self }
+    }
+
+    /// Only accept addresses and networks with a prefix length in these
+    /// ranges, per family. A bare address has the full length (32/128).
+    pub fn set_prefix_lens(
+        self,
+        v4: RangeInclusive<u8>,
+        v6: RangeInclusive<u8>,
+    ) -> Self {
+        Self {
+            v4_prefix_lens: v4,
+            v6_prefix_lens: v6,
+            ..self
+        }
+    }
+
+    /// Where the complaints about interfaces go.
+    pub fn set_diagnostics(self, diagnostics: Arc<dyn Diagnostics>) -> Self {
+        Self {
+            diagnostics,
+            ..self
+        }
+    }
+
+    /// Validate the configuration. An invalid one would find nothing, or
+    /// panic in find_all().
+    pub fn build(self) -> Result<Self, ScannerError> {
+        if !self.include_ipv4 && !self.include_ipv6 {
+            return Err(ScannerError::NoFamilies);
+        }
+        if self.accept.is_empty() {
+            return Err(ScannerError::EmptyAccept);
+        }
+        Ok(self)
+    }
+
     pub fn find_all(&self, buf: &[u8], filename: &str) -> Vec<NetCandidate> {
         let mut candidates = Vec::new();
+        self.find_all_into(buf, filename, &mut candidates);
+        candidates
+    }
 
+    /// Like find_all(), but into a cleared candidates buffer, so a caller
+    /// scanning many lines can reuse one allocation. Returns the number
+    /// of interfaces skipped by InterfaceMode::ComplainAndSkip or
+    /// SummarizeAndSkip.
+    pub fn find_all_into(
+        &self,
+        buf: &[u8],
+        filename: &str,
+        candidates: &mut Vec<NetCandidate>,
+    ) -> usize {
+        candidates.clear();
+        if !self.could_be_ip(buf) {
+            // The empty list.
+            return 0;
+        }
+        let mut skipped = 0;
+        candidates.extend(self.net_like_scanner(buf).filter_map(|range| {
+            self.candidate(buf, range, filename, &mut skipped)
+        }));
+        skipped
+    }
+
+    /// Quick check whether buf could hold an address of the families we
+    /// look for at all.
+    fn could_be_ip(&self, buf: &[u8]) -> bool {
         // This actually produces quite a speedup for the /etc/* dataset
         // of about 92ms to 40ms user time.
-        if !match (self.include_ipv4, self.include_ipv6) {
+        match (self.include_ipv4, self.include_ipv6) {
             (true, true) => prefilter_could_be_ip(buf),
             (true, false) => prefilter_could_be_ip4(buf),
             (false, true) => prefilter_could_be_ip6(buf),
             (false, false) => unreachable!(),
-        } {
-            // The empty list.
-            return candidates;
         }
+    }
 
-        let netlikescanner = if self.accept.oldnet {
-            NetLikeScanner::new(buf).with_oldnet()
+    /// The scanner for the address-like ranges in buf.
+    fn net_like_scanner<'a>(&self, buf: &'a [u8]) -> NetLikeScanner<'a> {
+        if self.accept.oldnet {
+            NetLikeScanner::new(buf).with_oldnet().with_zoneid()
         } else {
-            NetLikeScanner::new(buf)
-        };
+            NetLikeScanner::new(buf).with_zoneid()
+        }
+    }
+
+    /// Check and convert the address-like range of buf found by the
+    /// NetLikeScanner. Returns None if it is not a candidate. Skipped
+    /// interfaces are counted in skipped.
+    fn candidate(
+        &self,
+        buf: &[u8],
+        (start, end): (usize, usize),
+        filename: &str,
+        skipped: &mut usize,
+    ) -> Option<NetCandidate> {
         let nonet =
             !(self.accept.net || self.accept.oldnet || self.accept.iface);
 
-        for (start, end) in netlikescanner {
-            let mut slice = &buf[start..end];
-
-            // Restrict based on IP or not-IP.
-            match slice.iter().position(|&b| b == b'/') {
-                Some(slash_pos) => {
-                    // If there is a slash and we don't want networks.
-                    // Go to IP mode immediately.
-                    if nonet {
-                        slice = &slice[0..slash_pos];
-                    } else if self.accept.oldnet && !self.accept.net {
-                        // iface without net normally implies net. If there
-                        // is oldnet, we will only accept full old-style
-                        // masks.
-                        if !slice[slash_pos..].contains(&b'.') {
-                            continue;
-                        }
+        let mut slice = &buf[start..end];
+
+        // Restrict based on IP or not-IP.
+        match slice.iter().position(|&b| b == b'/') {
+            Some(slash_pos) => {
+                // If there is a slash and we don't want networks.
+                // Go to IP mode immediately.
+                if nonet {
+                    slice = &slice[0..slash_pos];
+                } else if self.accept.oldnet && !self.accept.net {
+                    // iface without net normally implies net. If there
+                    // is oldnet, we will only accept full old-style
+                    // masks.
+                    if !slice[slash_pos..].contains(&b'.') {
+                        return None;
                     }
                 }
-                None => {
-                    // There is no slash. Do we only want networks? Then skip.
-                    if !self.accept.ip {
-                        continue;
-                    }
+            }
+            None => {
+                // There is no slash. Do we only want networks? Then skip.
+                if !self.accept.ip {
+                    return None;
                 }
             }
+        }
 
-            let mut net = match Net::try_from(slice) {
-                Ok(s) => s,
-                Err(_) => continue,
-            };
+        let mut net = match Net::try_from(slice) {
+            Ok(s) => s,
+            Err(_) if self.leading_zeros => {
+                match strip_leading_zeros(slice).map(|s| Net::try_from(&s[..]))
+                {
+                    Some(Ok(s)) => s,
+                    _ => return None,
+                }
+            }
+            Err(_) => return None,
+        };
+
+        // Reject overly broad (or narrow) networks.
+        let prefix_lens = match net.is_ipv4() {
+            true => &self.v4_prefix_lens,
+            false => &self.v6_prefix_lens,
+        };
+        if !prefix_lens.contains(&net.0.prefix_len()) {
+            return None;
+        }
 
-            // Do we reject or translate interfaces (networks with host bits)?
-            if net.has_host_bits() {
-                if !self.accept.iface {
-                    continue;
+        let kind = if net.has_host_bits() {
+            MatchKind::Interface
+        } else if net.is_single_ip() {
+            MatchKind::Host
+        } else {
+            MatchKind::Network
+        };
+
+        // Do we reject or translate interfaces (networks with host bits)?
+        if net.has_host_bits() {
+            if !self.accept.iface {
+                return None;
+            }
+            // Translate/complain?
+            net = match self.interface_mode {
+                InterfaceMode::TreatAsIp => net.as_ip(),
+                InterfaceMode::TreatAsNetwork => net.as_network(),
+                InterfaceMode::ComplainAndSkip => {
+                    self.diagnostics.warn(
+                        filename,
+                        &format!("Ignoring network {net} with host bits set"),
+                    );
+                    *skipped += 1;
+                    return None;
                 }
-                // Translate/complain?
-                net = match self.interface_mode {
-                    InterfaceMode::TreatAsIp => net.as_ip(),
-                    InterfaceMode::TreatAsNetwork => net.as_network(),
-                    InterfaceMode::ComplainAndSkip => {
-                        eprintln!(
-                            "ipgrep: {filename}: warning: \
-                             Ignoring network {net} with host bits set"
-                        );
-                        continue;
-                    }
+                InterfaceMode::SummarizeAndSkip => {
+                    *skipped += 1;
+                    return None;
                 }
             }
+        }
+
+        // Treat "::ffff:1.2.3.4" as IPv4 space, if so requested.
+        if self.ipv4_mapped
+            && let Some(unmapped) = net.to_ipv4_unmapped()
+        {
+            net = unmapped;
+        }
+
+        // If we found an IP, check that we're doing Needle scans on those.
+        if !self.include_ipv6 && net.is_ipv6() {
+            return None;
+        }
+        if !self.include_ipv4 && net.is_ipv4() {
+            return None;
+        }
 
-            // If we found an IP, check that we're doing Needle scans on those.
-            if !self.include_ipv6 && net.is_ipv6() {
-                // TODO: At one point, (re)consider whether we want to
-                // treat "::ffff.1.2.3.4/96" as IPv4 space or not. For
-                // now, we don't.
-                continue;
+        // Take the port along, for --with-ports.
+        let mut range = (start, end);
+        let mut port = None;
+        if let Some(ports) = &self.ports {
+            if let Some((port_range, found)) = find_port(buf, start, end) {
+                range = port_range;
+                port = Some(found);
             }
-            if !self.include_ipv4 && net.is_ipv4() {
-                continue;
+            if !ports.is_empty()
+                && !port.is_some_and(|p| ports.iter().any(|r| r.contains(&p)))
+            {
+                return None;
             }
+        }
 
-            // Found one.
-            candidates.push(NetCandidate {
-                range: (start, end),
-                net,
-            });
+        if self.strict_boundaries && !is_bounded(buf, range) {
+            return None;
         }
 
-        candidates
+        // Found one.
+        Some(NetCandidate {
+            range,
+            net,
+            kind,
+            port,
+        })
+    }
+}
+
+/// Returns an iterator over the IPs and networks in buf, in the forms
+/// that accept allows. Nothing is found if accept is empty. For other
+/// options, configure a NetCandidateScanner instead.
+pub fn scan_bytes(
+    buf: &[u8],
+    accept: AcceptSet,
+) -> impl Iterator<Item = NetCandidate> + '_ {
+    let scanner = NetCandidateScanner::new().set_accept(accept);
+    let ranges = match !accept.is_empty() && scanner.could_be_ip(buf) {
+        true => Some(scanner.net_like_scanner(buf)),
+        false => None,
+    };
+    // Interfaces are taken as IPs, so nothing is skipped.
+    let mut skipped = 0;
+    ranges.into_iter().flatten().filter_map(move |range| {
+        scanner.candidate(buf, range, "(bytes)", &mut skipped)
+    })
+}
+
+/// Returns the IPv4 address/network without the leading zeros of its
+/// numbers: 010.000.000.001/08 becomes 10.0.0.1/8. The numbers stay
+/// decimal; 010 is 10, not octal 8. Returns None for IPv6 or if there
+/// was nothing to strip.
+fn strip_leading_zeros(slice: &[u8]) -> Option<Vec<u8>> {
+    if slice.contains(&b':') {
+        return None;
+    }
+    let mut stripped: Vec<u8> = Vec::with_capacity(slice.len());
+    for (idx, &ch) in slice.iter().enumerate() {
+        // A zero that starts a number and is not the last digit.
+        let in_number = stripped.last().is_some_and(u8::is_ascii_digit);
+        let next_is_digit = slice.get(idx + 1).is_some_and(u8::is_ascii_digit);
+        if ch == b'0' && !in_number && next_is_digit {
+            continue;
+        }
+        stripped.push(ch);
+    }
+    (stripped.len() != slice.len()).then_some(stripped)
+}
+
+/// Returns true if buf[start..end] is bounded by whitespace, the ends of
+/// buf or a few punctuation characters, for --strict-boundaries.
+fn is_bounded(buf: &[u8], (start, end): (usize, usize)) -> bool {
+    let is_boundary = |b: &u8| {
+        b.is_ascii_whitespace()
+            || matches!(b, b',' | b'(' | b')' | b'[' | b']' | b'"' | b'\'')
+    };
+    (start == 0 || is_boundary(&buf[start - 1]))
+        && buf.get(end).is_none_or(is_boundary)
+}
+
+/// Find a ":port" right after the address at buf[start..end], if it is
+/// an IPv4 address (1.2.3.4:80) or a bracketed IPv6 one ([::1]:80).
+/// Returns the range of the address with its port, and the port.
+fn find_port(
+    buf: &[u8],
+    start: usize,
+    end: usize,
+) -> Option<((usize, usize), u16)> {
+    let addr = &buf[start..end];
+    if addr.contains(&b'/') {
+        return None; // a network
+    }
+    let (start, colon) = if addr.contains(&b':') {
+        // Without brackets, an IPv6 port is indistinguishable from the
+        // last group.
+        let is_bracketed =
+            start > 0 && buf[start - 1] == b'[' && buf.get(end) == Some(&b']');
+        if !is_bracketed {
+            return None;
+        }
+        (start - 1, end + 1)
+    } else {
+        (start, end)
+    };
+    if buf.get(colon) != Some(&b':') {
+        return None;
     }
+    let digits = &buf[colon + 1..];
+    let len = digits.iter().take_while(|b| b.is_ascii_digit()).count();
+    if !(1..=5).contains(&len) {
+        return None;
+    }
+    let port = std::str::from_utf8(&digits[..len]).ok()?.parse().ok()?;
+    Some(((start, colon + 1 + len), port))
 }
 
 /// The old regex implementation was rather slow. A prefilter reduced the times
@@ -162,6 +481,33 @@ impl NetCandidateScanner {
 /// Right now, this quickly checks for "[0-9][.][0-9]" and ":[0-9a-fA-F:]".
 #[inline]
 fn prefilter_could_be_ip(line: &[u8]) -> bool {
+    #[cfg(target_arch = "x86_64")]
+    if line.len() >= sse2::MIN_LEN {
+        return sse2::could_be_ip(line, true, true);
+    }
+    prefilter_could_be_ip_scalar(line)
+}
+
+#[inline]
+fn prefilter_could_be_ip4(line: &[u8]) -> bool {
+    #[cfg(target_arch = "x86_64")]
+    if line.len() >= sse2::MIN_LEN {
+        return sse2::could_be_ip(line, true, false);
+    }
+    prefilter_could_be_ip4_scalar(line)
+}
+
+#[inline]
+fn prefilter_could_be_ip6(line: &[u8]) -> bool {
+    #[cfg(target_arch = "x86_64")]
+    if line.len() >= sse2::MIN_LEN {
+        return sse2::could_be_ip(line, false, true);
+    }
+    prefilter_could_be_ip6_scalar(line)
+}
+
+#[inline]
+fn prefilter_could_be_ip_scalar(line: &[u8]) -> bool {
     // Check this, or we might fail at (line.len() - 1).
     if line.is_empty() {
         return false;
@@ -197,7 +543,7 @@ fn prefilter_could_be_ip(line: &[u8]) -> bool {
 }
 
 #[inline]
-fn prefilter_could_be_ip4(line: &[u8]) -> bool {
+fn prefilter_could_be_ip4_scalar(line: &[u8]) -> bool {
     // Check this, or we might fail at (line.len() - 1).
     if line.is_empty() {
         return false;
@@ -219,7 +565,7 @@ fn prefilter_could_be_ip4(line: &[u8]) -> bool {
 }
 
 #[inline]
-fn prefilter_could_be_ip6(line: &[u8]) -> bool {
+fn prefilter_could_be_ip6_scalar(line: &[u8]) -> bool {
     // Check this, or we might fail at (line.len() - 1).
     if line.is_empty() {
         return false;
@@ -238,9 +584,134 @@ fn prefilter_could_be_ip6(line: &[u8]) -> bool {
     false
 }
 
+/// The prefilters, checking 16 positions at a time. SSE2 is part of the
+/// x86_64 baseline, so there is no need for runtime feature detection.
+#[cfg(target_arch = "x86_64")]
+mod sse2 {
+    use std::arch::x86_64::*;
+
+    const LANES: usize = 16;
+    // Every window needs a byte before and after it.
+    pub const MIN_LEN: usize = LANES + 2;
+
+    /// Look for "[0-9][.][0-9]" (ip4) and/or ":[0-9a-fA-F:]" (ip6). Same
+    /// results as the scalar prefilters, for lines of at least MIN_LEN.
+    pub fn could_be_ip(line: &[u8], ip4: bool, ip6: bool) -> bool {
+        // SAFETY: All x86_64 CPUs have SSE2.
+        unsafe { could_be_ip_sse2(line, ip4, ip6) }
+    }
+
+    #[target_feature(enable = "sse2")]
+    fn could_be_ip_sse2(line: &[u8], ip4: bool, ip6: bool) -> bool {
+        assert!(line.len() >= MIN_LEN);
+        // The last window overlaps the one before it, instead of leaving
+        // a tail to check byte by byte.
+        let last = line.len() - LANES - 1;
+        let mut pos = 1;
+        loop {
+            let start = pos.min(last);
+            if window_could_be_ip(line, start, ip4, ip6) {
+                return true;
+            }
+            if start == last {
+                break;
+            }
+            pos += LANES;
+        }
+        // Position 0 has no byte before it, so it can only start an IPv6.
+        ip6 && line[0] == b':'
+            && (line[1].is_ascii_hexdigit() || line[1] == b':')
+    }
+
+    /// Check the LANES positions starting at pos.
+    #[inline]
+    #[target_feature(enable = "sse2")]
+    fn window_could_be_ip(
+        line: &[u8],
+        pos: usize,
+        ip4: bool,
+        ip6: bool,
+    ) -> bool {
+        assert!(pos >= 1 && pos + LANES < line.len());
+        // SAFETY: The three loads read line[pos - 1..pos + LANES + 1],
+        // which is in bounds by the assert above.
+        let (prev, cur, next) = unsafe {
+            let ptr = line.as_ptr().add(pos);
+            (
+                _mm_loadu_si128(ptr.sub(1).cast()),
+                _mm_loadu_si128(ptr.cast()),
+                _mm_loadu_si128(ptr.add(1).cast()),
+            )
+        };
+        let mut hits = _mm_setzero_si128();
+        if ip4 {
+            let dots = _mm_cmpeq_epi8(cur, _mm_set1_epi8(b'.' as i8));
+            let digits = _mm_and_si128(is_digit(prev), is_digit(next));
+            hits = _mm_or_si128(hits, _mm_and_si128(dots, digits));
+        }
+        if ip6 {
+            let colon = _mm_set1_epi8(b':' as i8);
+            let colons = _mm_cmpeq_epi8(cur, colon);
+            let followers =
+                _mm_or_si128(is_hexdigit(next), _mm_cmpeq_epi8(next, colon));
+            hits = _mm_or_si128(hits, _mm_and_si128(colons, followers));
+        }
+        _mm_movemask_epi8(hits) != 0
+    }
+
+    /// All ones for the bytes in lo..=hi (unsigned).
+    #[inline]
+    #[target_feature(enable = "sse2")]
+    fn in_range(v: __m128i, lo: u8, hi: u8) -> __m128i {
+        let ge_lo = _mm_cmpeq_epi8(_mm_max_epu8(v, _mm_set1_epi8(lo as i8)), v);
+        let le_hi = _mm_cmpeq_epi8(_mm_min_epu8(v, _mm_set1_epi8(hi as i8)), v);
+        _mm_and_si128(ge_lo, le_hi)
+    }
+
+    #[inline]
+    #[target_feature(enable = "sse2")]
+    fn is_digit(v: __m128i) -> __m128i {
+        in_range(v, b'0', b'9')
+    }
+
+    #[inline]
+    #[target_feature(enable = "sse2")]
+    fn is_hexdigit(v: __m128i) -> __m128i {
+        // Setting 0x20 maps "A-F" onto "a-f", and no other bytes.
+        let lower = _mm_or_si128(v, _mm_set1_epi8(0x20));
+        _mm_or_si128(is_digit(v), in_range(lower, b'a', b'f'))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::diagnostics::Collect;
+
+    #[test]
+    fn test_scan_bytes() {
+        let buf = b"from 10.0.0.1 via 10.0.0.0/8 to [2001:db8::1]:443";
+        let ip = AcceptSet {
+            ip: true,
+            ..Default::default()
+        };
+        let nets: Vec<String> =
+            scan_bytes(buf, ip).map(|c| c.net.to_string()).collect();
+        assert_eq!(nets, ["10.0.0.1/32", "10.0.0.0/32", "2001:db8::1/128"]);
+
+        let net = AcceptSet {
+            net: true,
+            ..Default::default()
+        };
+        let mut found = scan_bytes(buf, net);
+        let first = found.next().unwrap();
+        assert_eq!(first.range, (18, 28));
+        assert_eq!(first.kind, MatchKind::Network);
+        assert!(found.next().is_none());
+
+        assert_eq!(scan_bytes(buf, AcceptSet::default()).count(), 0);
+        assert_eq!(scan_bytes(b"no addresses", ip).count(), 0);
+    }
 
     #[test]
     fn test_interface_mode_treat_as_ip() {
@@ -263,10 +734,52 @@ mod tests {
                 NetCandidate {
                     range: (18, 33),
                     net: Net::from_str_unchecked("10.20.30.123"),
+                    kind: MatchKind::Interface,
+                    port: None,
                 },
                 NetCandidate {
                     range: (34, 44),
                     net: Net::from_str_unchecked("10.20.30.1"),
+                    kind: MatchKind::Host,
+                    port: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nul_bytes() {
+        let acc = AcceptSet {
+            ip: true,
+            net: true,
+            oldnet: true,
+            iface: true,
+        };
+        let ncs = NetCandidateScanner::new().set_accept(acc);
+        let res = ncs.find_all(
+            b"\x00\x0010.0.0.1\x00 10.0.\x000.2 \x00fe80::1\x00/64\x00 ::1",
+            "(stdin)",
+        );
+        assert_eq!(
+            res,
+            vec![
+                NetCandidate {
+                    range: (2, 10),
+                    net: Net::from_str_unchecked("10.0.0.1"),
+                    kind: MatchKind::Host,
+                    port: None,
+                },
+                NetCandidate {
+                    range: (23, 30),
+                    net: Net::from_str_unchecked("fe80::1"),
+                    kind: MatchKind::Host,
+                    port: None,
+                },
+                NetCandidate {
+                    range: (36, 39),
+                    net: Net::from_str_unchecked("::1"),
+                    kind: MatchKind::Host,
+                    port: None,
                 },
             ]
         );
@@ -293,10 +806,14 @@ mod tests {
                 NetCandidate {
                     range: (18, 33),
                     net: Net::from_str_unchecked("10.20.30.0/24"),
+                    kind: MatchKind::Interface,
+                    port: None,
                 },
                 NetCandidate {
                     range: (34, 44),
                     net: Net::from_str_unchecked("10.20.30.1"),
+                    kind: MatchKind::Host,
+                    port: None,
                 },
             ]
         );
@@ -310,24 +827,53 @@ mod tests {
             oldnet: false,
             iface: true,
         };
+        let diagnostics = Arc::new(Collect::default());
         let ncs = NetCandidateScanner::new()
             .set_accept(acc)
-            .set_interface_mode(InterfaceMode::ComplainAndSkip);
+            .set_interface_mode(InterfaceMode::ComplainAndSkip)
+            .set_diagnostics(diagnostics.clone());
         let res = ncs.find_all(
             b"  ipv4.address1: \"10.20.30.123/24,10.20.30.1\"",
             "(stdin)",
         );
-        // NOTE: A complaint is logged to stderr (and eaten by the
-        // test framework).
+        assert_eq!(
+            diagnostics.take(),
+            ["(stdin): Ignoring network 10.20.30.123/24 with host bits set"]
+        );
         assert_eq!(
             res,
             vec![NetCandidate {
                 range: (34, 44),
                 net: Net::from_str_unchecked("10.20.30.1"),
+                kind: MatchKind::Host,
+                port: None,
             }]
         );
     }
 
+    #[test]
+    fn test_interface_mode_summarize_and_skip() {
+        let acc = AcceptSet {
+            ip: true,
+            net: true,
+            oldnet: false,
+            iface: true,
+        };
+        let line = b"10.20.30.123/24 10.20.30.1 10.0.0.0/8 10.1.1.1/16";
+        let mut res = Vec::new();
+        for (mode, skipped) in [
+            (InterfaceMode::TreatAsIp, 0),
+            (InterfaceMode::ComplainAndSkip, 2),
+            (InterfaceMode::SummarizeAndSkip, 2),
+        ] {
+            let ncs = NetCandidateScanner::new()
+                .set_accept(acc)
+                .set_interface_mode(mode);
+            assert_eq!(ncs.find_all_into(line, "(stdin)", &mut res), skipped);
+            assert_eq!(res.len(), 4 - skipped);
+        }
+    }
+
     #[test]
     fn test_accept_only_ip() {
         let acc = AcceptSet {
@@ -344,10 +890,14 @@ mod tests {
                 NetCandidate {
                     range: (2, 14),
                     net: Net::from_str_unchecked("11.22.0.0"),
+                    kind: MatchKind::Host,
+                    port: None,
                 },
                 NetCandidate {
                     range: (17, 31),
                     net: Net::from_str_unchecked("12.34.56.78"),
+                    kind: MatchKind::Host,
+                    port: None,
                 },
             ]
         );
@@ -375,10 +925,14 @@ mod tests {
                 NetCandidate {
                     range: (2, 11),
                     net: Net::from_str_unchecked("0.0.0.0/0"),
+                    kind: MatchKind::Network,
+                    port: None,
                 },
                 NetCandidate {
                     range: (14, 26),
                     net: Net::from_str_unchecked("12.34.0.0/24"),
+                    kind: MatchKind::Network,
+                    port: None,
                 },
             ]
         );
@@ -409,6 +963,8 @@ mod tests {
             vec![NetCandidate {
                 range: (109, 128),
                 net: Net::from_str_unchecked("4.4.0.0/16"),
+                kind: MatchKind::Network,
+                port: None,
             },]
         );
     }
@@ -441,12 +997,351 @@ mod tests {
                 NetCandidate {
                     range: (109, 128),
                     net: Net::from_str_unchecked("4.4.0.0/16"),
+                    kind: MatchKind::Network,
+                    port: None,
                 },
                 NetCandidate {
                     range: (148, 167),
                     net: Net::from_str_unchecked("3.3.0.0/16"),
+                    kind: MatchKind::Interface,
+                    port: None,
                 },
             ]
         );
     }
+
+    #[test]
+    fn test_build() {
+        let acc = AcceptSet {
+            ip: true,
+            ..Default::default()
+        };
+        assert!(NetCandidateScanner::new().set_accept(acc).build().is_ok());
+        assert!(
+            NetCandidateScanner::new()
+                .set_accept(acc)
+                .ignore_ipv4(true)
+                .build()
+                .is_ok()
+        );
+        let err = |ncs: NetCandidateScanner| ncs.build().err();
+        assert_eq!(
+            err(NetCandidateScanner::new()),
+            Some(ScannerError::EmptyAccept)
+        );
+        assert_eq!(
+            err(NetCandidateScanner::new()
+                .set_accept(acc)
+                .ignore_ipv4(true)
+                .ignore_ipv6(true)),
+            Some(ScannerError::NoFamilies)
+        );
+        assert_eq!(
+            err(NetCandidateScanner::new()
+                .set_accept(acc)
+                .ignore_ipv6(true)
+                .ignore_ipv4(true)),
+            Some(ScannerError::NoFamilies)
+        );
+    }
+
+    #[test]
+    fn test_ipv4_mapped_any_case() {
+        use crate::matching::MatchMode;
+
+        let acc = AcceptSet {
+            ip: true,
+            net: true,
+            ..Default::default()
+        };
+        let needle = Net::from_str_unchecked("10.0.0.0/8");
+        for ffff in ["ffff", "FFFF", "Ffff", "fFfF"] {
+            let line = format!("from ::{ffff}:10.0.0.1 and ::{ffff}:11.0.0.1");
+            for (ipv4_mapped, expected) in [(true, 1), (false, 0)] {
+                let ncs = NetCandidateScanner::new()
+                    .set_accept(acc)
+                    .ignore_ipv6(true)
+                    .set_ipv4_mapped(ipv4_mapped);
+                let found = ncs.find_all(line.as_bytes(), "(stdin)");
+                let matched: Vec<&NetCandidate> = found
+                    .iter()
+                    .filter(|c| MatchMode::Within.matches(&c.net, &needle))
+                    .collect();
+                assert_eq!(matched.len(), expected, "{line} {ipv4_mapped}");
+                if let Some(c) = matched.first() {
+                    assert_eq!(c.net, Net::from_str_unchecked("10.0.0.1"));
+                    assert_eq!(c.range, (5, 5 + 15), "the mapped text");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_all_into() {
+        let ncs = NetCandidateScanner::new().set_accept(AcceptSet {
+            ip: true,
+            ..Default::default()
+        });
+        let mut candidates = Vec::new();
+        ncs.find_all_into(b"a 10.0.0.1 b 10.0.0.2", "(stdin)", &mut candidates);
+        assert_eq!(candidates.len(), 2);
+        ncs.find_all_into(b"c 10.0.0.3", "(stdin)", &mut candidates);
+        assert_eq!(candidates, ncs.find_all(b"c 10.0.0.3", "(stdin)"));
+        // Also cleared when the prefilter skips the line.
+        ncs.find_all_into(b"no addresses", "(stdin)", &mut candidates);
+        assert!(candidates.is_empty());
+    }
+
+    #[test]
+    fn test_ports() {
+        let line =
+            b"a 10.0.0.1:22 [::1]:443 ::1:80 10.0.0.0/8:80 10.0.0.2:123456";
+        let ncs = NetCandidateScanner::new().set_accept(AcceptSet {
+            ip: true,
+            net: true,
+            ..Default::default()
+        });
+        let found = |ncs: &NetCandidateScanner| -> Vec<(String, Option<u16>)> {
+            ncs.find_all(line, "(stdin)")
+                .iter()
+                .map(|c| {
+                    let (start, end) = c.range;
+                    let text = String::from_utf8_lossy(&line[start..end]);
+                    (text.into_owned(), c.port)
+                })
+                .collect()
+        };
+        let ncs = ncs.set_ports(Some(Vec::new()));
+        assert_eq!(
+            found(&ncs),
+            [
+                ("10.0.0.1:22".into(), Some(22)),
+                ("[::1]:443".into(), Some(443)),
+                ("::1:80".into(), None),
+                ("10.0.0.0/8".into(), None),
+                ("10.0.0.2".into(), None),
+            ]
+        );
+        let ncs = ncs.set_ports(Some(vec![22..=22, 400..=500]));
+        assert_eq!(
+            found(&ncs),
+            [
+                ("10.0.0.1:22".into(), Some(22)),
+                ("[::1]:443".into(), Some(443)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strict_boundaries() {
+        let line = b"(10.0.0.1) 10.0.0.2:80 x10.0.0.3 \"::1\", \
+                     [2001:db8::1]:443 10.0.0.4.\t10.0.0.5";
+        let found = |strict, ports| -> Vec<String> {
+            let ncs = NetCandidateScanner::new()
+                .set_accept(AcceptSet {
+                    ip: true,
+                    ..Default::default()
+                })
+                .set_strict_boundaries(strict)
+                .set_ports(ports);
+            ncs.find_all(line, "(stdin)")
+                .iter()
+                .map(|c| String::from_utf8_lossy(&line[c.range.0..c.range.1]))
+                .map(String::from)
+                .collect()
+        };
+        assert_eq!(
+            found(false, None),
+            [
+                "10.0.0.1",
+                "10.0.0.2",
+                "10.0.0.3",
+                "::1",
+                "2001:db8::1",
+                "10.0.0.4",
+                "10.0.0.5"
+            ]
+        );
+        assert_eq!(
+            found(true, None),
+            ["10.0.0.1", "::1", "2001:db8::1", "10.0.0.5"]
+        );
+        // With the ports recorded, those no longer stick to the address.
+        assert_eq!(
+            found(true, Some(vec![])),
+            [
+                "10.0.0.1",
+                "10.0.0.2:80",
+                "::1",
+                "[2001:db8::1]:443",
+                "10.0.0.5"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_prefix_lens() {
+        let line = b"0.0.0.0/0 10.0.0.0/8 10.1.2.0/24 10.1.2.3/24 10.1.2.4 \
+                     ::/0 2001:db8::/32 2001:db8::1";
+        let found = |v4, v6| -> Vec<String> {
+            let ncs = NetCandidateScanner::new()
+                .set_accept(AcceptSet {
+                    ip: true,
+                    net: true,
+                    iface: true,
+                    ..Default::default()
+                })
+                .set_prefix_lens(v4, v6);
+            ncs.find_all(line, "(stdin)")
+                .iter()
+                .map(|c| c.net.to_string())
+                .collect()
+        };
+        assert_eq!(found(0..=32, 0..=128).len(), 8);
+        // The interface is taken by its prefix length, not as an IP.
+        assert_eq!(
+            found(8..=24, 0..=128),
+            [
+                "10.0.0.0/8",
+                "10.1.2.0/24",
+                "10.1.2.3/32",
+                "::/0",
+                "2001:db8::/32",
+                "2001:db8::1/128"
+            ]
+        );
+        assert_eq!(
+            found(0..=32, 1..=127),
+            [
+                "0.0.0.0/0",
+                "10.0.0.0/8",
+                "10.1.2.0/24",
+                "10.1.2.3/32",
+                "10.1.2.4/32",
+                "2001:db8::/32"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bracketed_ipv6() {
+        let with_ports = |ports| {
+            NetCandidateScanner::new()
+                .set_accept(AcceptSet {
+                    ip: true,
+                    ..Default::default()
+                })
+                .set_ports(ports)
+        };
+        let found = |ncs: &NetCandidateScanner, line: &str| {
+            let found = ncs.find_all(line.as_bytes(), "(stdin)");
+            let [candidate] = found.as_slice() else {
+                panic!("one candidate in {line}: {found:?}");
+            };
+            let (start, end) = candidate.range;
+            (line[start..end].to_string(), candidate.port)
+        };
+        let ncs = with_ports(Some(Vec::new()));
+        for (line, expected, port) in [
+            ("[2001:db8::1]:443", "[2001:db8::1]:443", Some(443)),
+            ("[::ffff:10.0.0.1]:80", "[::ffff:10.0.0.1]:80", Some(80)),
+            ("[::1]", "::1", None),
+            ("[::1]:", "::1", None),
+            ("[::1]:x", "::1", None),
+            ("[::1]:65536", "::1", None),
+            ("::1]:80", "::1", None),
+        ] {
+            assert_eq!(found(&ncs, line), (expected.into(), port), "{line}");
+        }
+
+        // Without --with-ports, it is just the address.
+        let ncs = with_ports(None);
+        let line = "[2001:db8::1]:443";
+        assert_eq!(found(&ncs, line), ("2001:db8::1".into(), None));
+    }
+
+    #[test]
+    fn test_zoneid() {
+        let ncs = NetCandidateScanner::new().set_accept(AcceptSet {
+            ip: true,
+            ..Default::default()
+        });
+        let line = b"neigh fe80::1%eth0 and [fe80::2%br-lan]:22";
+        let found = ncs.find_all(line, "(stdin)");
+        let got: Vec<_> = found
+            .iter()
+            .map(|c| (&line[c.range.0..c.range.1], c.net.to_string()))
+            .collect();
+        assert_eq!(
+            got,
+            [
+                (&b"fe80::1%eth0"[..], "fe80::1/128".to_string()),
+                (&b"fe80::2%br-lan"[..], "fe80::2/128".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strip_leading_zeros() {
+        for (s, expected) in [
+            ("010.000.000.001", Some("10.0.0.1")),
+            ("010.000.000.010", Some("10.0.0.10")), // not octal 8.0.0.8
+            ("100.0.0.1/024", Some("100.0.0.1/24")),
+            (
+                "192.168.001.000/255.255.255.000",
+                Some("192.168.1.0/255.255.255.0"),
+            ),
+            ("10.0.0.1", None),
+            ("::0001", None),
+        ] {
+            let got = strip_leading_zeros(s.as_bytes());
+            let got = got.map(|v| String::from_utf8(v).unwrap());
+            assert_eq!(got.as_deref(), expected, "for {s}");
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_prefilter_sse2() {
+        // Random lines with a varying share of bytes that matter to the
+        // prefilters, so both outcomes are common.
+        const BYTES: &[u8] = b"0123456789afAFgG..:: \n\xff";
+        let mut seed: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut random = || {
+            // xorshift64
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        let mut found = 0;
+        for _ in 0..20_000 {
+            let len = sse2::MIN_LEN + (random() % 48) as usize;
+            let density = random() % 100;
+            let line: Vec<u8> = (0..len)
+                .map(|_| match random() % 100 {
+                    n if n < density => BYTES[random() as usize % BYTES.len()],
+                    n if n < density + 2 => random() as u8,
+                    _ => b' ',
+                })
+                .collect();
+            found += usize::from(prefilter_could_be_ip_scalar(&line));
+            assert_eq!(
+                sse2::could_be_ip(&line, true, true),
+                prefilter_could_be_ip_scalar(&line),
+                "{line:?}"
+            );
+            assert_eq!(
+                sse2::could_be_ip(&line, true, false),
+                prefilter_could_be_ip4_scalar(&line),
+                "{line:?}"
+            );
+            assert_eq!(
+                sse2::could_be_ip(&line, false, true),
+                prefilter_could_be_ip6_scalar(&line),
+                "{line:?}"
+            );
+        }
+        assert!((5_000..15_000).contains(&found), "found {found}");
+    }
 }